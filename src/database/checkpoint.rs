@@ -0,0 +1,351 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::database::{
+    header::{DatabaseHeader, DatabaseHeaderError, PageSize},
+    sidecar_path,
+    wal::{Wal, WalError, WalSnapshot},
+};
+use thiserror::Error;
+
+/// What to do with the WAL once its committed frames are in the database file. These mirror
+/// SQLite's checkpoint modes, without the waiting on readers and writers that only matters while
+/// other connections have the database open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    /// Leave the WAL as it is. Reading it again gives the same pages the database file now has.
+    Passive,
+    /// The same as [`CheckpointMode::Passive`] offline. SQLite's FULL only differs in waiting
+    /// for writers to finish.
+    Full,
+    /// Reset the WAL by giving it a new header with new salts, so none of its frames are valid
+    /// and the next writer starts again from the first frame.
+    Restart,
+    /// Truncate the WAL to zero bytes.
+    Truncate,
+}
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("Encountered an error reading the WAL: {0}")]
+    Wal(WalError),
+    #[error("Encountered an IO error writing the checkpoint: {0}")]
+    Io(io::Error),
+    #[error("The WAL has {wal} byte pages, but the database has {database} byte pages")]
+    PageSize { database: PageSize, wal: PageSize },
+    #[error("The checkpointed database has a malformed header: {0:?}")]
+    Header(DatabaseHeaderError),
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// What a checkpoint did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// The commit frame the database file now matches, or `None` if nothing in the WAL had
+    /// committed.
+    pub commit_frame: Option<u32>,
+    /// Number of pages copied from the WAL into the database file.
+    pub pages_written: usize,
+    /// Size of the database in pages after the checkpoint.
+    pub database_size: u32,
+}
+
+/// Folds the WAL next to a database back into the database file, for databases that no process
+/// has open, such as those left behind after a crash.
+///
+/// The newest committed version of each page is copied into the database file, which is then
+/// resized to the database size the last commit recorded. The in-header database size and file
+/// change counter are updated to match. Frames after the last commit belong to a transaction
+/// that never committed, and are dropped by the modes that reset the WAL.
+pub fn checkpoint(
+    database_path: impl AsRef<Path>,
+    mode: CheckpointMode,
+) -> Result<Checkpoint, CheckpointError> {
+    let wal_path = sidecar_path(&database_path, "-wal");
+    // Like a reader, treat a WAL with a missing or damaged header as having no frames
+    let wal = match Wal::open(&wal_path) {
+        Ok(wal) => Some(wal),
+        Err(WalError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+            return empty_checkpoint(&database_path);
+        }
+        Err(WalError::Io(err)) => return Err(CheckpointError::Wal(WalError::Io(err))),
+        Err(_) => None,
+    };
+    let wal_header = wal.as_ref().map(|wal| wal.header.clone());
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&database_path)?;
+    let page_size = read_page_size(&mut file)?;
+    if let Some(wal_header) = &wal_header
+        && page_size != wal_header.page_size
+    {
+        return Err(CheckpointError::PageSize {
+            database: page_size,
+            wal: wal_header.page_size,
+        });
+    }
+
+    let result = match wal.and_then(WalSnapshot::latest) {
+        Some(snapshot) => copy_frames(&mut file, &snapshot)?,
+        None => Checkpoint {
+            commit_frame: None,
+            pages_written: 0,
+            database_size: (file.metadata()?.len() / u64::from(page_size.get())) as u32,
+        },
+    };
+    file.sync_all()?;
+
+    match (mode, wal_header) {
+        (CheckpointMode::Passive | CheckpointMode::Full, _) => {}
+        (CheckpointMode::Restart, Some(wal_header)) => {
+            // SQLite picks the new salt at random. It only has to differ from the old one.
+            let salt = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.subsec_nanos())
+                ^ wal_header.salts[1].rotate_left(1);
+            let mut wal_file = OpenOptions::new().write(true).open(&wal_path)?;
+            wal_file.write_all(&wal_header.restarted(salt).to_bytes())?;
+            wal_file.sync_all()?;
+        }
+        // Without a valid header there's nothing to restart from. An empty WAL is just as
+        // empty, and the next writer gives it a header.
+        (CheckpointMode::Restart, None) | (CheckpointMode::Truncate, _) => {
+            let wal_file = OpenOptions::new().write(true).open(&wal_path)?;
+            wal_file.set_len(0)?;
+            wal_file.sync_all()?;
+        }
+    }
+    Ok(result)
+}
+
+/// The result of checkpointing a database with no WAL, which leaves everything alone.
+fn empty_checkpoint(database_path: impl AsRef<Path>) -> Result<Checkpoint, CheckpointError> {
+    let mut file = File::open(database_path)?;
+    let page_size = read_page_size(&mut file)?;
+    Ok(Checkpoint {
+        commit_frame: None,
+        pages_written: 0,
+        database_size: (file.metadata()?.len() / u64::from(page_size.get())) as u32,
+    })
+}
+
+/// Reads just the page size from the database header. The rest of the header may not be valid
+/// until page 1 comes from the WAL.
+fn read_page_size(file: &mut File) -> Result<PageSize, CheckpointError> {
+    let mut raw_page_size = [0; 2];
+    file.seek(SeekFrom::Start(16))?;
+    file.read_exact(&mut raw_page_size)?;
+    let value = u16::from_be_bytes(raw_page_size);
+    PageSize::try_from(value).map_err(|error| {
+        CheckpointError::Header(DatabaseHeaderError::PageSize {
+            offset: 16,
+            value,
+            error,
+        })
+    })
+}
+
+/// Writes each page the snapshot reads from the WAL into the database file, then sizes the file
+/// and updates the header to match the commit.
+fn copy_frames(file: &mut File, snapshot: &WalSnapshot) -> Result<Checkpoint, CheckpointError> {
+    let page_size = u64::from(snapshot.wal().header.page_size.get());
+    let database_size = snapshot.database_size();
+    let mut pages_written = 0;
+    for (page_number, _) in snapshot.page_frames() {
+        // Pages past the end of the database were freed by a later commit
+        if page_number > database_size {
+            continue;
+        }
+        let page = snapshot
+            .page(page_number)
+            .expect("the snapshot reads this page from the WAL");
+        file.seek(SeekFrom::Start(u64::from(page_number - 1) * page_size))?;
+        file.write_all(page)?;
+        pages_written += 1;
+    }
+    file.set_len(u64::from(database_size) * page_size)?;
+
+    let mut header_bytes = vec![0; 100];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header_bytes)?;
+    let mut header = DatabaseHeader::try_from(header_bytes).map_err(CheckpointError::Header)?;
+    header.database_size_in_pages = database_size;
+    header.file_change_counter = header.file_change_counter.wrapping_add(1);
+    // The in-header database size is only trusted when this matches the change counter
+    header.version_valid_for = header.file_change_counter;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header.to_bytes())?;
+
+    Ok(Checkpoint {
+        commit_frame: Some(snapshot.commit_frame()),
+        pages_written,
+        database_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{Checkpoint, CheckpointMode, checkpoint};
+    use crate::database::{
+        Database,
+        cursor::table::TableCursor,
+        sidecar_path,
+        test_support::{database_bytes, rows_page, schema_page, wal_bytes},
+        wal::{FrameError, Wal},
+    };
+
+    /// Table `t` on page 2 has row 1 in the database file. The WAL commits row 2 and a new empty
+    /// page 3, then adds row 3 without committing.
+    fn write_files(name: &str) -> PathBuf {
+        let database = database_bytes(512, vec![schema_page(&[("t", 2)]), rows_page(&[1])]);
+        let wal = wal_bytes(
+            512,
+            [7, 8],
+            &[
+                (2, 0, rows_page(&[1, 2])),
+                (3, 3, vec![0; 512]),
+                (2, 0, rows_page(&[1, 2, 3])),
+            ],
+        );
+        let path =
+            std::env::temp_dir().join(format!("checkpoint-{name}-{}.db", std::process::id()));
+        fs::write(&path, database).unwrap();
+        fs::write(sidecar_path(&path, "-wal"), wal).unwrap();
+        path
+    }
+
+    fn rowids(path: &PathBuf) -> Vec<i64> {
+        let database = Database::open(path).unwrap();
+        TableCursor::new(&database, 2)
+            .map(|row| row.unwrap().0)
+            .collect()
+    }
+
+    const RESULT: Checkpoint = Checkpoint {
+        commit_frame: Some(2),
+        pages_written: 2,
+        database_size: 3,
+    };
+
+    #[test]
+    fn truncate_copies_committed_frames_and_empties_the_wal() {
+        let path = write_files("truncate");
+        let result = checkpoint(&path, CheckpointMode::Truncate);
+        let wal_len = fs::metadata(sidecar_path(&path, "-wal")).unwrap().len();
+        let database = Database::from_bytes(fs::read(&path).unwrap()).unwrap();
+        let rows = rowids(&path);
+        fs::remove_file(sidecar_path(&path, "-wal")).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap(), RESULT);
+        assert_eq!(wal_len, 0);
+        assert_eq!(database.pager().len(), 3);
+        assert_eq!(database.header.database_size_in_pages, 3);
+        assert_eq!(database.header.file_change_counter, 2);
+        assert!(database.validate_header().is_empty());
+        assert_eq!(rows, vec![1, 2]);
+    }
+
+    #[test]
+    fn restart_resets_the_wal_and_passive_leaves_it() {
+        let path = write_files("restart");
+        let result = checkpoint(&path, CheckpointMode::Restart);
+        let wal = Wal::open(sidecar_path(&path, "-wal")).unwrap();
+        let rows = rowids(&path);
+        // Checkpointing a reset WAL copies nothing
+        let again = checkpoint(&path, CheckpointMode::Restart);
+        fs::remove_file(sidecar_path(&path, "-wal")).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap(), RESULT);
+        assert_eq!(wal.header.checkpoint_sequence, 1);
+        assert_eq!(wal.header.salts[0], 8);
+        assert!(wal.frames.is_empty());
+        assert!(matches!(
+            wal.end,
+            Some(FrameError::SaltMismatch {
+                frame_number: 1,
+                ..
+            })
+        ));
+        assert_eq!(rows, vec![1, 2]);
+        assert_eq!(
+            again.unwrap(),
+            Checkpoint {
+                commit_frame: None,
+                pages_written: 0,
+                database_size: 3,
+            }
+        );
+
+        for (name, mode) in [
+            ("passive", CheckpointMode::Passive),
+            ("full", CheckpointMode::Full),
+        ] {
+            let path = write_files(name);
+            let wal_before = fs::read(sidecar_path(&path, "-wal")).unwrap();
+            let result = checkpoint(&path, mode);
+            let wal_after = fs::read(sidecar_path(&path, "-wal")).unwrap();
+            let rows = rowids(&path);
+            fs::remove_file(sidecar_path(&path, "-wal")).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(result.unwrap(), RESULT);
+            assert_eq!(wal_before, wal_after);
+            assert_eq!(rows, vec![1, 2]);
+        }
+    }
+
+    #[test]
+    fn empty_and_damaged_wals_have_nothing_to_copy() {
+        let nothing = Checkpoint {
+            commit_frame: None,
+            pages_written: 0,
+            database_size: 3,
+        };
+        let path = write_files("truncate-twice");
+        assert_eq!(checkpoint(&path, CheckpointMode::Truncate).unwrap(), RESULT);
+        let again = checkpoint(&path, CheckpointMode::Truncate);
+        let restarted = checkpoint(&path, CheckpointMode::Restart);
+        fs::remove_file(sidecar_path(&path, "-wal")).unwrap();
+        let missing = checkpoint(&path, CheckpointMode::Restart);
+        let wal_created = sidecar_path(&path, "-wal").exists();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(again.unwrap(), nothing);
+        assert_eq!(restarted.unwrap(), nothing);
+        assert_eq!(missing.unwrap(), nothing);
+        assert!(!wal_created);
+
+        let path = write_files("damaged");
+        let mut wal = fs::read(sidecar_path(&path, "-wal")).unwrap();
+        wal[12] ^= 1;
+        fs::write(sidecar_path(&path, "-wal"), wal).unwrap();
+        let database_before = fs::read(&path).unwrap();
+        let result = checkpoint(&path, CheckpointMode::Restart);
+        let database_after = fs::read(&path).unwrap();
+        let wal_len = fs::metadata(sidecar_path(&path, "-wal")).unwrap().len();
+        fs::remove_file(sidecar_path(&path, "-wal")).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result.unwrap(),
+            Checkpoint {
+                database_size: 2,
+                ..nothing
+            }
+        );
+        assert_eq!(database_before, database_after);
+        assert_eq!(wal_len, 0);
+    }
+}
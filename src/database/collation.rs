@@ -0,0 +1,228 @@
+use std::cmp::Ordering;
+
+use crate::database::{record::Value, schema::IndexColumn};
+use thiserror::Error;
+
+/// The built-in collating functions, used to compare text values.
+// https://www.sqlite.org/datatype3.html#collation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    /// Compares bytes with memcmp.
+    #[default]
+    Binary,
+    /// Like `Binary`, but folds the 26 upper case ASCII letters to lower case first.
+    NoCase,
+    /// Like `Binary`, but ignores trailing spaces.
+    RTrim,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CollationError {
+    #[error("Unknown collating function {0}. Valid options are BINARY, NOCASE, RTRIM")]
+    Unknown(String),
+}
+
+impl TryFrom<&str> for Collation {
+    type Error = CollationError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_ascii_uppercase().as_str() {
+            "BINARY" => Ok(Self::Binary),
+            "NOCASE" => Ok(Self::NoCase),
+            "RTRIM" => Ok(Self::RTrim),
+            _ => Err(CollationError::Unknown(value.to_owned())),
+        }
+    }
+}
+
+impl Collation {
+    pub fn compare_text(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Self::Binary => a.as_bytes().cmp(b.as_bytes()),
+            Self::NoCase => a
+                .bytes()
+                .map(|byte| byte.to_ascii_lowercase())
+                .cmp(b.bytes().map(|byte| byte.to_ascii_lowercase())),
+            Self::RTrim => a.trim_end_matches(' ').cmp(b.trim_end_matches(' ')),
+        }
+    }
+
+    /// Compares two values the way SQLite orders them in an index: NULL first, then integers and
+    /// floats by numeric value, then text using this collation, then blobs with memcmp.
+    pub fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        match (a, b) {
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::Integer(a), Value::Float(b)) => compare_integer_to_float(*a, *b),
+            (Value::Float(a), Value::Integer(b)) => compare_integer_to_float(*b, *a).reverse(),
+            (Value::Text(a), Value::Text(b)) => self.compare_text(a, b),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            (a, b) => type_rank(a).cmp(&type_rank(b)),
+        }
+    }
+}
+
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Integer(_) | Value::Float(_) => 1,
+        Value::Text(_) => 2,
+        Value::Blob(_) => 3,
+    }
+}
+
+// Converting a large i64 to f64 loses precision, so compare the integer parts as integers and
+// only fall back to the fraction when they are equal.
+fn compare_integer_to_float(integer: i64, float: f64) -> Ordering {
+    if float.is_nan() {
+        return Ordering::Greater;
+    }
+    if float < -9_223_372_036_854_775_808.0 {
+        return Ordering::Greater;
+    }
+    if float >= 9_223_372_036_854_775_808.0 {
+        return Ordering::Less;
+    }
+    let truncated = float.trunc();
+    match integer.cmp(&(truncated as i64)) {
+        Ordering::Equal => 0.0
+            .partial_cmp(&(float - truncated))
+            .unwrap_or(Ordering::Equal),
+        ordering => ordering,
+    }
+}
+
+/// Compares two index keys column by column, in the order the index stores them: with each
+/// column's collating function, reversed for `DESC` columns. Only the first
+/// `a.len().min(b.len())` columns are compared, so a shorter key matches every key it is a
+/// prefix of. Columns past the end of `columns`, like the trailing rowid, sort ascending with
+/// `Collation::Binary`.
+pub fn compare_keys(a: &[Value], b: &[Value], columns: &[IndexColumn]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .map(|(idx, (a, b))| match columns.get(idx) {
+            Some(column) if column.descending => column.collation.compare(a, b).reverse(),
+            Some(column) => column.collation.compare(a, b),
+            None => Collation::Binary.compare(a, b),
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{Collation, CollationError, compare_keys};
+    use crate::database::{record::Value, schema::IndexColumn};
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_owned())
+    }
+
+    #[test]
+    fn collation_names() {
+        assert_eq!(Collation::try_from("nocase"), Ok(Collation::NoCase));
+        assert_eq!(Collation::try_from("BINARY"), Ok(Collation::Binary));
+        assert_eq!(Collation::try_from("RTrim"), Ok(Collation::RTrim));
+        assert_eq!(
+            Collation::try_from("unicode"),
+            Err(CollationError::Unknown("unicode".to_owned()))
+        );
+    }
+
+    #[test]
+    fn type_ordering() {
+        let ordered = [
+            Value::Null,
+            Value::Integer(-5),
+            Value::Float(-4.5),
+            Value::Integer(0),
+            Value::Float(0.5),
+            Value::Integer(1),
+            text("A"),
+            text("a"),
+            Value::Blob(vec![0]),
+            Value::Blob(vec![0, 1]),
+        ];
+        for (idx, a) in ordered.iter().enumerate() {
+            for (other, b) in ordered.iter().enumerate() {
+                assert_eq!(
+                    Collation::Binary.compare(a, b),
+                    idx.cmp(&other),
+                    "{a:?} vs {b:?}"
+                );
+            }
+        }
+        assert_eq!(
+            Collation::Binary.compare(&Value::Integer(3), &Value::Float(3.0)),
+            Ordering::Equal
+        );
+        assert_eq!(
+            Collation::Binary.compare(&Value::Integer(i64::MAX), &Value::Float(9.3e18)),
+            Ordering::Less
+        );
+        assert_eq!(
+            Collation::Binary.compare(
+                &Value::Integer(i64::MAX - 1),
+                &Value::Float(i64::MAX as f64)
+            ),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn text_collations() {
+        assert_eq!(Collation::Binary.compare_text("ABC", "abc"), Ordering::Less);
+        assert_eq!(
+            Collation::NoCase.compare_text("ABC", "abc"),
+            Ordering::Equal
+        );
+        assert_eq!(
+            Collation::NoCase.compare_text("abd", "ABC"),
+            Ordering::Greater
+        );
+        // NOCASE only folds ASCII
+        assert_eq!(Collation::NoCase.compare_text("É", "é"), Ordering::Less);
+        assert_eq!(
+            Collation::RTrim.compare_text("abc  ", "abc"),
+            Ordering::Equal
+        );
+        assert_eq!(Collation::RTrim.compare_text(" abc", "abc"), Ordering::Less);
+        assert_eq!(
+            Collation::Binary.compare_text("abc ", "abc"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn key_prefixes() {
+        let key = [text("smith"), Value::Integer(42), Value::Integer(7)];
+        let column = |collation, descending| IndexColumn {
+            column: Some(0),
+            collation,
+            descending,
+        };
+        assert_eq!(
+            compare_keys(&[text("SMITH")], &key, &[column(Collation::NoCase, false)]),
+            Ordering::Equal
+        );
+        assert_eq!(
+            compare_keys(&[text("smith"), Value::Integer(43)], &key, &[]),
+            Ordering::Greater
+        );
+        assert_eq!(compare_keys(&[], &key, &[]), Ordering::Equal);
+
+        // DESC columns sort the other way, but the rowid after them is still ascending
+        let descending = [column(Collation::Binary, true)];
+        assert_eq!(
+            compare_keys(&[text("adams")], &key, &descending),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_keys(&[text("smith"), Value::Integer(43)], &key, &descending),
+            Ordering::Greater
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use std::cmp::Ordering;
+
+use crate::database::{
+    Database,
+    collation::compare_keys,
+    cursor::{CursorError, Frame, push_frame, read_record},
+    page::{
+        cell::{Cell, PayloadLimits},
+        header::PageType,
+    },
+    record::Value,
+    schema::IndexColumn,
+};
+
+/// Walks an index b-tree in key order, yielding each entry's values: the indexed columns
+/// followed by the rowid of the row they came from.
+///
+/// Unlike table b-trees, the cells on interior index pages are entries too. On an interior page
+/// the frame position counts two steps per cell: an even position `2 * i` descends into the
+/// left child of cell `i` (or the right-most pointer when `i` is the number of cells) and the
+/// odd position after it yields cell `i` itself.
+pub struct IndexCursor<'a> {
+    database: &'a Database,
+    root_page: u32,
+    /// The collating function and sort order of each indexed column. Missing columns sort
+    /// ascending with `Binary`.
+    columns: Vec<IndexColumn>,
+    stack: Vec<Frame>,
+    started: bool,
+    /// When set, the cursor only yields entries whose leading columns equal this key.
+    key: Option<Vec<Value>>,
+    pages_read: usize,
+}
+
+impl<'a> IndexCursor<'a> {
+    pub fn new(database: &'a Database, root_page: u32, columns: Vec<IndexColumn>) -> Self {
+        Self {
+            database,
+            root_page,
+            columns,
+            stack: Vec::new(),
+            started: false,
+            key: None,
+            pages_read: 0,
+        }
+    }
+
+    /// A cursor over the entries whose leading columns equal `key`. Passing every indexed column
+    /// looks up exact matches; passing fewer does a prefix seek over the leading columns.
+    pub fn equal(
+        database: &'a Database,
+        root_page: u32,
+        columns: Vec<IndexColumn>,
+        key: Vec<Value>,
+    ) -> Self {
+        Self {
+            key: Some(key),
+            ..Self::new(database, root_page, columns)
+        }
+    }
+
+    /// Number of pages loaded so far, counting a page again each time the cursor revisits it.
+    pub fn pages_read(&self) -> usize {
+        self.pages_read
+    }
+
+    /// Positions the cursor so the next entry it yields is the first whose leading columns are
+    /// greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[Value]) -> Result<(), CursorError> {
+        self.started = true;
+        self.stack.clear();
+        self.push(self.root_page)?;
+        let limits = self.database.payload_limits();
+        loop {
+            let frame = self
+                .stack
+                .last_mut()
+                .expect("the root page was just pushed");
+            let (index, child) = partition_point(self.database, frame, limits, key, &self.columns)?;
+            match child {
+                Some(child) => {
+                    // Yield cell `index` once its left subtree is done
+                    frame.position = 2 * index + 1;
+                    self.push(child)?;
+                }
+                None => {
+                    frame.position = index;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, page_number: u32) -> Result<(), CursorError> {
+        push_frame(
+            self.database,
+            &mut self.stack,
+            page_number,
+            [PageType::InteriorIndex, PageType::LeafIndex],
+        )?;
+        self.pages_read += 1;
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Option<Vec<Value>>, CursorError> {
+        if !self.started {
+            match self.key.clone() {
+                Some(key) => self.seek(&key)?,
+                None => {
+                    self.started = true;
+                    self.push(self.root_page)?;
+                }
+            }
+        }
+        let limits = self.database.payload_limits();
+        while let Some(frame) = self.stack.last_mut() {
+            let header = frame.page.get_page_header();
+            let cell_count = usize::from(header.get_number_of_cells());
+            let position = frame.position;
+            frame.position += 1;
+            let page_number = frame.page_number;
+            let (index, descend) = match header.get_page_type() {
+                PageType::InteriorIndex => (position / 2, position % 2 == 0),
+                _ => (position, false),
+            };
+            if descend {
+                match frame.page.cell(index, limits) {
+                    Some(cell) => {
+                        let child =
+                            left_child(cell.map_err(|err| CursorError::Cell(page_number, err))?);
+                        self.push(child)?;
+                    }
+                    None if index == cell_count => {
+                        let right_most_pointer = header.get_right_most_pointer();
+                        self.push(
+                            right_most_pointer.expect("interior pages have a right-most pointer"),
+                        )?;
+                    }
+                    None => {
+                        self.stack.pop();
+                    }
+                }
+                continue;
+            }
+            let Some(cell) = frame.page.cell(index, limits) else {
+                self.stack.pop();
+                continue;
+            };
+            let values = entry(
+                self.database,
+                cell.map_err(|err| CursorError::Cell(page_number, err))?,
+            )?;
+            if let Some(key) = &self.key
+                && compare_keys(key, &values, &self.columns) != Ordering::Equal
+            {
+                self.stack.clear();
+                return Ok(None);
+            }
+            return Ok(Some(values));
+        }
+        Ok(None)
+    }
+}
+
+fn left_child(cell: Cell) -> u32 {
+    match cell {
+        Cell::IndexInterior(cell) => cell.left_child_page,
+        // Only interior index pages descend, and the page type was checked when it was pushed
+        _ => unreachable!(),
+    }
+}
+
+/// Decodes the entry stored in an index cell.
+fn entry(database: &Database, cell: Cell) -> Result<Vec<Value>, CursorError> {
+    match cell {
+        Cell::IndexLeaf(cell) => read_record(
+            database,
+            &cell.payload,
+            cell.payload_size,
+            cell.first_overflow_page,
+        ),
+        Cell::IndexInterior(cell) => read_record(
+            database,
+            &cell.payload,
+            cell.payload_size,
+            cell.first_overflow_page,
+        ),
+        // The page type was checked when the page was pushed
+        Cell::TableLeaf(_) | Cell::TableInterior(_) => unreachable!(),
+    }
+}
+
+/// Binary searches an index page for the first cell whose entry is greater than or equal to
+/// `key`. On interior pages, also returns the child to descend into: that cell's left child,
+/// or the right-most pointer if every cell is smaller.
+fn partition_point(
+    database: &Database,
+    frame: &Frame,
+    limits: PayloadLimits,
+    key: &[Value],
+    columns: &[IndexColumn],
+) -> Result<(usize, Option<u32>), CursorError> {
+    let header = frame.page.get_page_header();
+    let (mut low, mut high) = (0, usize::from(header.get_number_of_cells()));
+    let mut child = header.get_right_most_pointer();
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let cell = frame
+            .page
+            .cell(middle, limits)
+            .expect("middle is less than the number of cells")
+            .map_err(|err| CursorError::Cell(frame.page_number, err))?;
+        let left_child_page = match &cell {
+            Cell::IndexInterior(cell) => Some(cell.left_child_page),
+            _ => None,
+        };
+        let values = entry(database, cell)?;
+        if compare_keys(key, &values, columns) == Ordering::Greater {
+            low = middle + 1;
+        } else {
+            high = middle;
+            child = left_child_page;
+        }
+    }
+    Ok((low, child))
+}
+
+/// The rowid an index entry points at, which SQLite stores as the entry's last column.
+pub fn entry_rowid(entry: &[Value]) -> Option<i64> {
+    match entry.last() {
+        Some(Value::Integer(rowid)) => Some(*rowid),
+        _ => None,
+    }
+}
+
+impl Iterator for IndexCursor<'_> {
+    type Item = Result<Vec<Value>, CursorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.step() {
+            Ok(entry) => entry.map(Ok),
+            Err(err) => {
+                // Stop after the first error rather than resuming from a broken position
+                self.stack.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IndexCursor, entry_rowid};
+    use crate::database::{
+        Database,
+        collation::Collation,
+        cursor::table::TableCursor,
+        record::Value,
+        schema::IndexColumn,
+        test_support::{
+            btree_page, database_bytes, index_interior_cell, index_leaf_cell, record_bytes,
+            table_leaf_cell,
+        },
+    };
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_owned())
+    }
+
+    fn index_entry(name: &str, rowid: i64) -> Vec<Value> {
+        vec![text(name), Value::Integer(rowid)]
+    }
+
+    /// The layout of a single column index on the first column of its table.
+    fn columns(collation: Collation, descending: bool) -> Vec<IndexColumn> {
+        vec![IndexColumn {
+            column: Some(0),
+            collation,
+            descending,
+        }]
+    }
+
+    fn index_leaf(entries: &[(&str, i64)]) -> Vec<u8> {
+        let cells: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|(name, rowid)| index_leaf_cell(&record_bytes(&index_entry(name, *rowid))))
+            .collect();
+        btree_page(512, 0, 10, None, &cells)
+    }
+
+    /// Page 2 is a table of five names. Page 3 is a NOCASE index on the name column, with an
+    /// interior root pointing at leaves on pages 4 and 5.
+    fn indexed_table() -> Database {
+        let names = ["bob", "Alice", "carol", "alice", "Dave"];
+        let rows: Vec<Vec<u8>> = names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| table_leaf_cell(idx as i64 + 1, &record_bytes(&[text(name)])))
+            .collect();
+        // Sorted with NOCASE and then by rowid: Alice(2) alice(4) bob(1) carol(3) Dave(5)
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                btree_page(512, 0, 13, None, &rows),
+                btree_page(
+                    512,
+                    0,
+                    2,
+                    Some(5),
+                    &[index_interior_cell(
+                        4,
+                        &record_bytes(&index_entry("bob", 1)),
+                    )],
+                ),
+                index_leaf(&[("Alice", 2), ("alice", 4)]),
+                index_leaf(&[("carol", 3), ("Dave", 5)]),
+            ],
+        );
+        Database::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn scans_entries_in_key_order() {
+        let database = indexed_table();
+        let rowids: Vec<i64> = IndexCursor::new(&database, 3, columns(Collation::NoCase, false))
+            .map(|entry| entry_rowid(&entry.unwrap()).unwrap())
+            .collect();
+        assert_eq!(rowids, vec![2, 4, 1, 3, 5]);
+    }
+
+    #[test]
+    fn equality_seek_uses_collation() {
+        let database = indexed_table();
+        let entries: Vec<Vec<Value>> = IndexCursor::equal(
+            &database,
+            3,
+            columns(Collation::NoCase, false),
+            vec![text("ALICE")],
+        )
+        .map(Result::unwrap)
+        .collect();
+        assert_eq!(
+            entries,
+            vec![index_entry("Alice", 2), index_entry("alice", 4)]
+        );
+
+        let binary: Vec<Vec<Value>> = IndexCursor::equal(
+            &database,
+            3,
+            columns(Collation::Binary, false),
+            vec![text("ALICE")],
+        )
+        .map(Result::unwrap)
+        .collect();
+        assert!(binary.is_empty());
+    }
+
+    #[test]
+    fn seek_finds_interior_entries() {
+        let database = indexed_table();
+        let mut cursor = IndexCursor::equal(
+            &database,
+            3,
+            columns(Collation::NoCase, false),
+            vec![text("Bob")],
+        );
+        assert_eq!(
+            cursor.by_ref().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![index_entry("bob", 1)]
+        );
+        // The root, the left leaf in case it holds more matches, and the right leaf, whose first
+        // entry ends the scan
+        assert_eq!(cursor.pages_read(), 3);
+
+        let mut cursor = IndexCursor::new(&database, 3, columns(Collation::NoCase, false));
+        cursor.seek(&[text("c")]).unwrap();
+        let rowids: Vec<i64> = cursor
+            .map(|entry| entry_rowid(&entry.unwrap()).unwrap())
+            .collect();
+        assert_eq!(rowids, vec![3, 5]);
+    }
+
+    #[test]
+    fn full_key_and_prefix_seeks() {
+        let database = indexed_table();
+        let exact: Vec<Vec<Value>> = IndexCursor::equal(
+            &database,
+            3,
+            columns(Collation::NoCase, false),
+            vec![text("alice"), Value::Integer(4)],
+        )
+        .map(Result::unwrap)
+        .collect();
+        assert_eq!(exact, vec![index_entry("alice", 4)]);
+        assert_eq!(
+            IndexCursor::equal(
+                &database,
+                3,
+                columns(Collation::NoCase, false),
+                vec![text("erin")]
+            )
+            .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn look_up_rows_through_index() {
+        let database = indexed_table();
+        let mut table = TableCursor::new(&database, 2);
+        let names: Vec<Vec<Value>> = IndexCursor::equal(
+            &database,
+            3,
+            columns(Collation::NoCase, false),
+            vec![text("alice")],
+        )
+        .map(|entry| {
+            let rowid = entry_rowid(&entry.unwrap()).unwrap();
+            table.get(rowid).unwrap().unwrap()
+        })
+        .collect();
+        assert_eq!(names, vec![vec![text("Alice")], vec![text("alice")]]);
+    }
+
+    #[test]
+    fn descending_indexes_are_searched_in_reverse() {
+        // A DESC index stores names from largest to smallest, with the rowids still ascending:
+        // dave(5) carol(3) bob(1) alice(2) alice(4)
+        let names = ["bob", "alice", "carol", "alice", "dave"];
+        let rows: Vec<Vec<u8>> = names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| table_leaf_cell(idx as i64 + 1, &record_bytes(&[text(name)])))
+            .collect();
+        let database = Database::from_bytes(database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                btree_page(512, 0, 13, None, &rows),
+                btree_page(
+                    512,
+                    0,
+                    2,
+                    Some(5),
+                    &[index_interior_cell(
+                        4,
+                        &record_bytes(&index_entry("carol", 3)),
+                    )],
+                ),
+                index_leaf(&[("dave", 5)]),
+                index_leaf(&[("bob", 1), ("alice", 2), ("alice", 4)]),
+            ],
+        ))
+        .unwrap();
+        let descending = columns(Collation::Binary, true);
+        let rowids = |cursor: IndexCursor| -> Vec<i64> {
+            cursor
+                .map(|entry| entry_rowid(&entry.unwrap()).unwrap())
+                .collect()
+        };
+        assert_eq!(
+            rowids(IndexCursor::new(&database, 3, descending.clone())),
+            vec![5, 3, 1, 2, 4]
+        );
+        assert_eq!(
+            rowids(IndexCursor::equal(
+                &database,
+                3,
+                descending.clone(),
+                vec![text("alice")]
+            )),
+            vec![2, 4]
+        );
+        assert_eq!(
+            rowids(IndexCursor::equal(
+                &database,
+                3,
+                descending.clone(),
+                vec![text("carol"), Value::Integer(3)]
+            )),
+            vec![3]
+        );
+
+        // Seeking to "c" skips the names after it, which come first in a DESC index
+        let mut cursor = IndexCursor::new(&database, 3, descending);
+        cursor.seek(&[text("c")]).unwrap();
+        assert_eq!(rowids(cursor), vec![1, 2, 4]);
+    }
+}
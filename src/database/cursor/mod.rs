@@ -0,0 +1,80 @@
+use crate::database::{
+    Database,
+    overflow::OverflowError,
+    page::{Page, cell::CellError, header::PageType},
+    pager::PagerError,
+    record::{Record, RecordError, Value},
+};
+use thiserror::Error;
+
+pub mod index;
+pub mod table;
+
+#[derive(Debug, Error)]
+pub enum CursorError {
+    #[error("Encountered an error reading a b-tree page:\n{0}")]
+    Page(PagerError),
+    #[error("Encountered an error decoding a cell on page {0}:\n{1}")]
+    Cell(u32, CellError),
+    #[error("Encountered an error reading a payload's overflow pages: {0}")]
+    Overflow(OverflowError),
+    #[error("Encountered an error decoding a record: {0}")]
+    Record(RecordError),
+    #[error("Page {0} is a {1:?} page, which doesn't belong in this b-tree")]
+    UnexpectedPageType(u32, PageType),
+    #[error("Page {0} is its own ancestor in the b-tree")]
+    Cycle(u32),
+}
+
+/// A page on the path from the root to a cursor's current position.
+struct Frame {
+    page_number: u32,
+    page: Page,
+    /// Where the cursor is on this page. What it counts depends on the kind of b-tree.
+    position: usize,
+}
+
+/// Loads a page onto the cursor's path, checking that it belongs in the b-tree being walked and
+/// isn't already one of its own ancestors.
+fn push_frame(
+    database: &Database,
+    stack: &mut Vec<Frame>,
+    page_number: u32,
+    page_types: [PageType; 2],
+) -> Result<(), CursorError> {
+    if stack.iter().any(|frame| frame.page_number == page_number) {
+        return Err(CursorError::Cycle(page_number));
+    }
+    let page = database
+        .pager()
+        .get(page_number)
+        .map_err(CursorError::Page)?;
+    let page_type = page.get_page_header().get_page_type();
+    if !page_types.contains(page_type) {
+        return Err(CursorError::UnexpectedPageType(
+            page_number,
+            page_type.clone(),
+        ));
+    }
+    stack.push(Frame {
+        page_number,
+        page,
+        position: 0,
+    });
+    Ok(())
+}
+
+/// Reads a cell's payload, including any overflow, and decodes it as a record.
+fn read_record(
+    database: &Database,
+    local_payload: &[u8],
+    payload_size: u64,
+    first_overflow_page: Option<u32>,
+) -> Result<Vec<Value>, CursorError> {
+    let payload = database
+        .read_payload(local_payload, payload_size, first_overflow_page)
+        .map_err(CursorError::Overflow)?;
+    let record =
+        Record::decode(&payload, &database.header.text_encoding).map_err(CursorError::Record)?;
+    Ok(record.values)
+}
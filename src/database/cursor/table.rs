@@ -0,0 +1,422 @@
+use std::ops::Range;
+
+use crate::database::{
+    Database,
+    cursor::{CursorError, Frame, push_frame, read_record},
+    page::{
+        cell::{Cell, PayloadLimits},
+        header::PageType,
+    },
+    record::Value,
+};
+
+/// Walks a table b-tree in rowid order, yielding each row's rowid and column values.
+///
+/// Columns declared `INTEGER PRIMARY KEY` are aliases for the rowid and are stored as NULL in
+/// the record, so they come back as `Value::Null`.
+pub struct TableCursor<'a> {
+    database: &'a Database,
+    root_page: u32,
+    stack: Vec<Frame>,
+    started: bool,
+    /// Rows start at the first rowid greater than or equal to this, if set.
+    start: Option<i64>,
+    /// Rows stop before the first rowid greater than or equal to this, if set.
+    end: Option<i64>,
+    pages_read: usize,
+}
+
+impl<'a> TableCursor<'a> {
+    pub fn new(database: &'a Database, root_page: u32) -> Self {
+        Self {
+            database,
+            root_page,
+            stack: Vec::new(),
+            started: false,
+            start: None,
+            end: None,
+            pages_read: 0,
+        }
+    }
+
+    /// A cursor over the rows with `range.start <= rowid < range.end`. It seeks straight to the
+    /// first row rather than scanning the rows before it.
+    pub fn range(database: &'a Database, root_page: u32, range: Range<i64>) -> Self {
+        Self {
+            start: Some(range.start),
+            end: Some(range.end),
+            ..Self::new(database, root_page)
+        }
+    }
+
+    /// Number of pages loaded so far, counting a page again each time the cursor revisits it.
+    pub fn pages_read(&self) -> usize {
+        self.pages_read
+    }
+
+    /// Positions the cursor so the next row it yields is the first with a rowid greater than or
+    /// equal to `rowid`. Descends from the root by binary searching each page's cells, so it
+    /// only loads one page per level of the b-tree.
+    pub fn seek(&mut self, rowid: i64) -> Result<(), CursorError> {
+        self.started = true;
+        self.stack.clear();
+        self.push(self.root_page)?;
+        let limits = self.database.payload_limits();
+        loop {
+            let frame = self
+                .stack
+                .last_mut()
+                .expect("the root page was just pushed");
+            let (index, child) = partition_point(frame, limits, rowid)?;
+            match child {
+                Some(child) => {
+                    frame.position = index + 1;
+                    self.push(child)?;
+                }
+                None => {
+                    frame.position = index;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Looks up the row with exactly this rowid.
+    pub fn get(&mut self, rowid: i64) -> Result<Option<Vec<Value>>, CursorError> {
+        self.seek(rowid)?;
+        Ok(self
+            .step()?
+            .filter(|(found, _)| *found == rowid)
+            .map(|(_, values)| values))
+    }
+
+    fn push(&mut self, page_number: u32) -> Result<(), CursorError> {
+        push_frame(
+            self.database,
+            &mut self.stack,
+            page_number,
+            [PageType::InteriorTable, PageType::LeafTable],
+        )?;
+        self.pages_read += 1;
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Option<(i64, Vec<Value>)>, CursorError> {
+        if !self.started {
+            match self.start {
+                Some(start) => self.seek(start)?,
+                None => {
+                    self.started = true;
+                    self.push(self.root_page)?;
+                }
+            }
+        }
+        let limits = self.database.payload_limits();
+        while let Some(frame) = self.stack.last_mut() {
+            // The position is the next cell to visit. On interior pages, a position equal to the
+            // number of cells stands for the right-most pointer.
+            let index = frame.position;
+            frame.position += 1;
+            let page_number = frame.page_number;
+            match frame.page.cell(index, limits) {
+                Some(cell) => match cell.map_err(|err| CursorError::Cell(page_number, err))? {
+                    Cell::TableInterior(cell) => self.push(cell.left_child_page)?,
+                    Cell::TableLeaf(cell) => {
+                        // Stop before reading the payload of a row the range doesn't include
+                        if self.end.is_some_and(|end| cell.rowid >= end) {
+                            self.stack.clear();
+                            return Ok(None);
+                        }
+                        let values = read_record(
+                            self.database,
+                            &cell.payload,
+                            cell.payload_size,
+                            cell.first_overflow_page,
+                        )?;
+                        return Ok(Some((cell.rowid, values)));
+                    }
+                    // The page type was checked when the page was pushed
+                    Cell::IndexInterior(_) | Cell::IndexLeaf(_) => unreachable!(),
+                },
+                None => {
+                    // Descend into the right-most child once, right after the last cell
+                    let header = frame.page.get_page_header();
+                    let right_most_pointer = header
+                        .get_right_most_pointer()
+                        .filter(|_| index == usize::from(header.get_number_of_cells()));
+                    match right_most_pointer {
+                        Some(page_number) => self.push(page_number)?,
+                        None => {
+                            self.stack.pop();
+                        }
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Binary searches a table page for the first cell whose rowid is greater than or equal to
+/// `rowid`. On interior pages, also returns the child that can hold `rowid`: that cell's left
+/// child, or the right-most pointer if every cell is smaller.
+fn partition_point(
+    frame: &Frame,
+    limits: PayloadLimits,
+    rowid: i64,
+) -> Result<(usize, Option<u32>), CursorError> {
+    let header = frame.page.get_page_header();
+    let (mut low, mut high) = (0, usize::from(header.get_number_of_cells()));
+    let mut child = header.get_right_most_pointer();
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let cell = frame
+            .page
+            .cell(middle, limits)
+            .expect("middle is less than the number of cells")
+            .map_err(|err| CursorError::Cell(frame.page_number, err))?;
+        let (key, left_child_page) = match cell {
+            Cell::TableInterior(cell) => (cell.rowid, Some(cell.left_child_page)),
+            Cell::TableLeaf(cell) => (cell.rowid, None),
+            // The page type was checked when the page was pushed
+            Cell::IndexInterior(_) | Cell::IndexLeaf(_) => unreachable!(),
+        };
+        if key < rowid {
+            low = middle + 1;
+        } else {
+            high = middle;
+            child = left_child_page;
+        }
+    }
+    Ok((low, child))
+}
+
+impl Iterator for TableCursor<'_> {
+    type Item = Result<(i64, Vec<Value>), CursorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.step() {
+            Ok(row) => row.map(Ok),
+            Err(err) => {
+                // Stop after the first error rather than resuming from a broken position
+                self.stack.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TableCursor;
+    use crate::database::{
+        Database,
+        cursor::CursorError,
+        record::Value,
+        test_support::{
+            btree_page, database_bytes, record_bytes, table_interior_cell, table_leaf_cell,
+        },
+    };
+    use crate::util::write_varint;
+
+    fn row(rowid: i64) -> Vec<u8> {
+        table_leaf_cell(
+            rowid,
+            &record_bytes(&[
+                Value::Integer(rowid * 10),
+                Value::Text(format!("row {rowid}")),
+            ]),
+        )
+    }
+
+    fn leaf(rowids: std::ops::Range<i64>) -> Vec<u8> {
+        let cells: Vec<Vec<u8>> = rowids.map(row).collect();
+        btree_page(512, 0, 13, None, &cells)
+    }
+
+    /// A two level table rooted at page 2: page 2 points at leaves 3, 4 and 5.
+    fn two_level_table() -> Database {
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                btree_page(
+                    512,
+                    0,
+                    5,
+                    Some(5),
+                    &[table_interior_cell(3, 3), table_interior_cell(4, 6)],
+                ),
+                leaf(1..4),
+                leaf(4..7),
+                leaf(7..10),
+            ],
+        );
+        Database::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn scans_rows_in_order() {
+        let database = two_level_table();
+        let rows: Vec<(i64, Vec<Value>)> =
+            TableCursor::new(&database, 2).map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 9);
+        assert_eq!(
+            rows.iter().map(|(rowid, _)| *rowid).collect::<Vec<i64>>(),
+            (1..10).collect::<Vec<i64>>()
+        );
+        assert_eq!(
+            rows[4].1,
+            vec![Value::Integer(50), Value::Text("row 5".to_owned())]
+        );
+    }
+
+    /// A three level table rooted at page 2 with rowids 1 through 18, three to a leaf. Page 2
+    /// points at interior pages 3 and 4, which point at leaves 5-7 and 8-10.
+    fn three_level_table() -> Database {
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                btree_page(512, 0, 5, Some(4), &[table_interior_cell(3, 9)]),
+                btree_page(
+                    512,
+                    0,
+                    5,
+                    Some(7),
+                    &[table_interior_cell(5, 3), table_interior_cell(6, 6)],
+                ),
+                btree_page(
+                    512,
+                    0,
+                    5,
+                    Some(10),
+                    &[table_interior_cell(8, 12), table_interior_cell(9, 15)],
+                ),
+                leaf(1..4),
+                leaf(4..7),
+                leaf(7..10),
+                leaf(10..13),
+                leaf(13..16),
+                leaf(16..19),
+            ],
+        );
+        Database::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn point_lookup_reads_one_page_per_level() {
+        let database = three_level_table();
+        let mut full_scan = TableCursor::new(&database, 2);
+        assert_eq!(full_scan.by_ref().count(), 18);
+        assert_eq!(full_scan.pages_read(), 9);
+
+        for rowid in 1..19 {
+            let mut cursor = TableCursor::new(&database, 2);
+            assert_eq!(
+                cursor.get(rowid).unwrap(),
+                Some(vec![
+                    Value::Integer(rowid * 10),
+                    Value::Text(format!("row {rowid}"))
+                ])
+            );
+            assert_eq!(cursor.pages_read(), 3);
+        }
+
+        let mut cursor = TableCursor::new(&database, 2);
+        assert_eq!(cursor.get(0).unwrap(), None);
+        assert_eq!(cursor.get(19).unwrap(), None);
+    }
+
+    #[test]
+    fn seek_continues_across_leaves() {
+        let database = three_level_table();
+        let mut cursor = TableCursor::new(&database, 2);
+        cursor.seek(9).unwrap();
+        let rowids: Vec<i64> = cursor.map(|row| row.unwrap().0).collect();
+        assert_eq!(rowids, (9..19).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn range_scan() {
+        let database = three_level_table();
+        let mut cursor = TableCursor::range(&database, 2, 5..11);
+        let rowids: Vec<i64> = cursor.by_ref().map(|row| row.unwrap().0).collect();
+        assert_eq!(rowids, (5..11).collect::<Vec<i64>>());
+        // Root, both interior pages, and leaves 6, 7 and 8
+        assert_eq!(cursor.pages_read(), 6);
+
+        let rowids: Vec<i64> = TableCursor::range(&database, 2, 17..100)
+            .map(|row| row.unwrap().0)
+            .collect();
+        assert_eq!(rowids, vec![17, 18]);
+        assert_eq!(TableCursor::range(&database, 2, 7..7).count(), 0);
+        assert_eq!(TableCursor::range(&database, 2, 50..60).count(), 0);
+    }
+
+    #[test]
+    fn range_scan_stops_before_reading_the_row_past_its_end() {
+        // Row 3 spills onto page 99, which doesn't exist
+        let mut damaged = write_varint(1000);
+        damaged.extend(write_varint(3));
+        damaged.extend([0x55; 39]);
+        damaged.extend(99u32.to_be_bytes());
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                btree_page(512, 0, 13, None, &[row(1), row(2), damaged]),
+            ],
+        );
+        let database = Database::from_bytes(bytes).unwrap();
+        let rowids: Vec<i64> = TableCursor::range(&database, 2, 1..3)
+            .map(|row| row.unwrap().0)
+            .collect();
+        assert_eq!(rowids, vec![1, 2]);
+        assert!(matches!(
+            TableCursor::new(&database, 2).nth(2),
+            Some(Err(CursorError::Overflow(_)))
+        ));
+    }
+
+    #[test]
+    fn scans_single_leaf_and_empty_tables() {
+        let bytes = database_bytes(512, vec![btree_page(512, 100, 13, None, &[]), leaf(1..3)]);
+        let database = Database::from_bytes(bytes).unwrap();
+        assert_eq!(TableCursor::new(&database, 2).count(), 2);
+        assert_eq!(TableCursor::new(&database, 1).count(), 0);
+    }
+
+    #[test]
+    fn stops_on_cycles() {
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                btree_page(512, 0, 5, Some(3), &[table_interior_cell(2, 3)]),
+                leaf(4..5),
+            ],
+        );
+        let database = Database::from_bytes(bytes).unwrap();
+        let results: Vec<_> = TableCursor::new(&database, 2).collect();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0], Err(CursorError::Cycle(2))));
+    }
+
+    #[test]
+    fn rejects_index_pages() {
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                btree_page(512, 0, 10, None, &[]),
+            ],
+        );
+        let database = Database::from_bytes(bytes).unwrap();
+        let results: Vec<_> = TableCursor::new(&database, 2).collect();
+        assert!(matches!(
+            results[..],
+            [Err(CursorError::UnexpectedPageType(2, _))]
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+
+use crate::{
+    database::pager::{Pager, PagerError},
+    util::{DecodeError, get_u32_from_bytes},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FreelistError {
+    #[error("Freelist page {0} is outside of the database")]
+    PageOutOfRange(u32),
+    #[error("Encountered an error reading a freelist trunk page: {0}")]
+    Pager(PagerError),
+    #[error("Freelist visits page {0} more than once")]
+    Cycle(u32),
+    #[error("Freelist trunk page {page} claims {count} leaves, but only has room for {max}")]
+    TooManyLeaves { page: u32, count: u32, max: u32 },
+    #[error("The header counts {expected} freelist pages, but the freelist has {found}")]
+    CountMismatch { expected: u32, found: u32 },
+    #[error("Encountered an error decoding a freelist trunk page: {0}")]
+    Decode(DecodeError),
+}
+
+impl From<DecodeError> for FreelistError {
+    fn from(value: DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+/// A freelist trunk page. Trunk pages form a linked list, and each lists some of the free leaf
+/// pages. The trunk pages themselves are free pages too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreelistTrunk {
+    pub page_number: u32,
+    /// The next trunk page, or `None` on the last one.
+    pub next_trunk: Option<u32>,
+    pub leaves: Vec<u32>,
+}
+
+/// The pages of the database that are not in use.
+// https://www.sqlite.org/fileformat.html#the_freelist
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Freelist {
+    pub trunks: Vec<FreelistTrunk>,
+}
+
+impl Freelist {
+    /// Walks the trunk pages starting at `first_trunk`, which is zero for an empty freelist.
+    ///
+    /// Each trunk page starts with the 4 byte page number of the next trunk page and the 4 byte
+    /// number of leaves on this page, followed by that many 4 byte leaf page numbers.
+    pub fn read(
+        pager: &Pager,
+        usable_size: usize,
+        first_trunk: u32,
+    ) -> Result<Self, FreelistError> {
+        let max_leaves = (usable_size / 4).saturating_sub(2) as u32;
+        let page_count = pager.len() as u32;
+        let mut seen = HashSet::new();
+        let mut trunks = Vec::new();
+        let mut next_trunk = first_trunk;
+        while next_trunk != 0 {
+            let page_number = next_trunk;
+            if !seen.insert(page_number) {
+                return Err(FreelistError::Cycle(page_number));
+            }
+            let page = pager.page_bytes(page_number).map_err(|err| match err {
+                PagerError::PageOutOfRange(page) => FreelistError::PageOutOfRange(page),
+                err => FreelistError::Pager(err),
+            })?;
+            if page.len() < usable_size {
+                return Err(FreelistError::PageOutOfRange(page_number));
+            }
+            next_trunk = get_u32_from_bytes(&page[0..4], "next_freelist_trunk")?;
+            let count = get_u32_from_bytes(&page[4..8], "freelist_leaf_count")?;
+            if count > max_leaves {
+                return Err(FreelistError::TooManyLeaves {
+                    page: page_number,
+                    count,
+                    max: max_leaves,
+                });
+            }
+            let leaves = page[8..8 + count as usize * 4]
+                .chunks(4)
+                .map(|bytes| {
+                    let leaf = get_u32_from_bytes(bytes, "freelist_leaf")?;
+                    if leaf == 0 || leaf > page_count {
+                        return Err(FreelistError::PageOutOfRange(leaf));
+                    }
+                    if !seen.insert(leaf) {
+                        return Err(FreelistError::Cycle(leaf));
+                    }
+                    Ok(leaf)
+                })
+                .collect::<Result<Vec<u32>, FreelistError>>()?;
+            trunks.push(FreelistTrunk {
+                page_number,
+                next_trunk: (next_trunk != 0).then_some(next_trunk),
+                leaves,
+            });
+        }
+        Ok(Self { trunks })
+    }
+
+    /// Checks the number of pages on the freelist against the count in the database header.
+    pub fn validate(&self, num_freelist: u32) -> Result<(), FreelistError> {
+        let found = self.len() as u32;
+        if found != num_freelist {
+            return Err(FreelistError::CountMismatch {
+                expected: num_freelist,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// Number of free pages, counting both trunk and leaf pages.
+    pub fn len(&self) -> usize {
+        self.trunks.iter().map(|trunk| 1 + trunk.leaves.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trunks.is_empty()
+    }
+
+    /// Every free page: each trunk page followed by its leaves.
+    pub fn pages(&self) -> impl Iterator<Item = u32> + '_ {
+        self.trunks.iter().flat_map(|trunk| {
+            std::iter::once(trunk.page_number).chain(trunk.leaves.iter().copied())
+        })
+    }
+
+    pub fn contains(&self, page_number: u32) -> bool {
+        self.pages().any(|page| page == page_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Freelist, FreelistError, FreelistTrunk};
+    use crate::database::{
+        Database,
+        test_support::{btree_page, database_bytes},
+    };
+
+    fn trunk_page(next_trunk: u32, leaves: &[u32]) -> Vec<u8> {
+        let mut page = vec![0; 512];
+        page[0..4].copy_from_slice(&next_trunk.to_be_bytes());
+        page[4..8].copy_from_slice(&(leaves.len() as u32).to_be_bytes());
+        for (idx, leaf) in leaves.iter().enumerate() {
+            page[8 + idx * 4..12 + idx * 4].copy_from_slice(&leaf.to_be_bytes());
+        }
+        page
+    }
+
+    /// Pages 2 and 5 are trunk pages, listing leaves 3, 4 and 6.
+    fn database(first_freelist: u32, num_freelist: u32, last_trunk: Vec<u8>) -> Database {
+        let mut bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                trunk_page(5, &[3, 4]),
+                vec![0; 512],
+                vec![0; 512],
+                last_trunk,
+                vec![0; 512],
+            ],
+        );
+        bytes[32..36].copy_from_slice(&first_freelist.to_be_bytes());
+        bytes[36..40].copy_from_slice(&num_freelist.to_be_bytes());
+        Database::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn walks_trunk_pages() {
+        let database = database(2, 5, trunk_page(0, &[6]));
+        let freelist = database.freelist().unwrap();
+        assert_eq!(
+            freelist.trunks,
+            vec![
+                FreelistTrunk {
+                    page_number: 2,
+                    next_trunk: Some(5),
+                    leaves: vec![3, 4],
+                },
+                FreelistTrunk {
+                    page_number: 5,
+                    next_trunk: None,
+                    leaves: vec![6],
+                },
+            ]
+        );
+        assert_eq!(freelist.pages().collect::<Vec<u32>>(), vec![2, 3, 4, 5, 6]);
+        assert!(freelist.contains(4));
+        assert!(!freelist.contains(1));
+
+        let empty = Freelist::read(database.pager(), 512, 0).unwrap();
+        assert!(empty.is_empty());
+        assert!(empty.validate(0).is_ok());
+    }
+
+    #[test]
+    fn damaged_freelists() {
+        assert!(matches!(
+            database(2, 4, trunk_page(0, &[6])).freelist(),
+            Err(FreelistError::CountMismatch {
+                expected: 4,
+                found: 5,
+            })
+        ));
+        assert!(matches!(
+            database(2, 5, trunk_page(2, &[6])).freelist(),
+            Err(FreelistError::Cycle(2))
+        ));
+        assert!(matches!(
+            database(2, 5, trunk_page(0, &[9])).freelist(),
+            Err(FreelistError::PageOutOfRange(9))
+        ));
+        assert!(matches!(
+            database(2, 5, trunk_page(0, &[3])).freelist(),
+            Err(FreelistError::Cycle(3))
+        ));
+        let mut too_many = trunk_page(0, &[]);
+        too_many[4..8].copy_from_slice(&200u32.to_be_bytes());
+        assert!(matches!(
+            database(2, 5, too_many).freelist(),
+            Err(FreelistError::TooManyLeaves {
+                page: 5,
+                count: 200,
+                max: 126,
+            })
+        ));
+    }
+}
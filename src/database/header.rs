@@ -1,8 +1,8 @@
 use crate::util::{DecodeError, get_u16_from_bytes, get_u32_from_bytes};
-use core::str;
+use core::fmt;
 use thiserror::Error;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FileFormatVersion {
     Legacy,
     Wal,
@@ -14,6 +14,15 @@ pub enum FileFormatVersionError {
     IncorrectVariant(u8),
 }
 
+impl From<&FileFormatVersion> for u8 {
+    fn from(value: &FileFormatVersion) -> Self {
+        match value {
+            FileFormatVersion::Legacy => 1,
+            FileFormatVersion::Wal => 2,
+        }
+    }
+}
+
 impl TryFrom<u8> for FileFormatVersion {
     type Error = FileFormatVersionError;
 
@@ -26,7 +35,7 @@ impl TryFrom<u8> for FileFormatVersion {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TextEncoding {
     Utf8,
     Utf16Le,
@@ -39,6 +48,16 @@ pub enum TextEncodingError {
     IncorrectVariant(u32),
 }
 
+impl From<&TextEncoding> for u32 {
+    fn from(value: &TextEncoding) -> Self {
+        match value {
+            TextEncoding::Utf8 => 1,
+            TextEncoding::Utf16Le => 2,
+            TextEncoding::Utf16Be => 3,
+        }
+    }
+}
+
 impl TryFrom<u32> for TextEncoding {
     type Error = TextEncodingError;
 
@@ -52,12 +71,66 @@ impl TryFrom<u32> for TextEncoding {
     }
 }
 
+/// The database page size in bytes. A power of two between 512 and 65536 inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageSize(u32);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PageSizeError {
+    #[error("Page size should be a power of two between 512 and 32768, or 1 for 65536, was {0}")]
+    Invalid(u16),
+    #[error("Page size should be a power of two between 512 and 65536, was {0}")]
+    InvalidBytes(u32),
+}
+
+impl TryFrom<u16> for PageSize {
+    type Error = PageSizeError;
+
+    /// Decodes the page size as stored in the header, where 65536 doesn't fit in two bytes and
+    /// is stored as 1 instead.
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self(65536)),
+            512.. if value.is_power_of_two() => Ok(Self(u32::from(value))),
+            _ => Err(PageSizeError::Invalid(value)),
+        }
+    }
+}
+
+impl PageSize {
+    /// A page size given as a plain number of bytes, the way the WAL and rollback journal
+    /// headers store it.
+    pub fn from_bytes(bytes: u32) -> Result<Self, PageSizeError> {
+        match bytes {
+            512..=65536 if bytes.is_power_of_two() => Ok(Self(bytes)),
+            _ => Err(PageSizeError::InvalidBytes(bytes)),
+        }
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+
+    /// The page size as stored in the header.
+    pub fn to_raw(&self) -> u16 {
+        match self.0 {
+            65536 => 1,
+            page_size => page_size as u16,
+        }
+    }
+}
+
+impl fmt::Display for PageSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 // https://www.sqlite.org/fileformat.html
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DatabaseHeader {
     /// The database page size in bytes.
-    /// Must be a power of two between 512 and 32768 inclusive, or the value 1 representing a page size of 65536.
-    pub page_size: u16,
+    pub page_size: PageSize,
     /// File format write version. 1 for legacy; 2 for WAL.
     pub file_format_write_version: FileFormatVersion,
     /// File format read version. 1 for legacy; 2 for WAL.
@@ -98,14 +171,147 @@ pub struct DatabaseHeader {
     pub sqlite_version_number: u32,
 }
 
+impl DatabaseHeader {
+    /// The page size minus the reserved space at the end of each page.
+    pub fn usable_size(&self) -> usize {
+        self.page_size.get() as usize - usize::from(self.reserved_space)
+    }
+
+    /// Writes the header in its 100 byte on-disk form. The inverse of `DatabaseHeader::try_from`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(100);
+        bytes.extend(b"SQLite format 3\0");
+        bytes.extend(self.page_size.to_raw().to_be_bytes());
+        bytes.push(u8::from(&self.file_format_write_version));
+        bytes.push(u8::from(&self.file_format_read_version));
+        bytes.push(self.reserved_space);
+        bytes.push(self.maximum_embedded_payload_fraction);
+        bytes.push(self.minimum_embedded_payload_fraction);
+        bytes.push(self.leaf_payload_fraction);
+        for field in [
+            self.file_change_counter,
+            self.database_size_in_pages,
+            self.first_freelist,
+            self.num_freelist,
+            self.schema_cookie,
+            self.schema_format_number,
+            self.default_page_cache_size,
+            self.largest_root_page,
+            u32::from(&self.text_encoding),
+            self.user_version,
+            u32::from(self.incremental_vaccuum_mode),
+            self.application_id,
+        ] {
+            bytes.extend(field.to_be_bytes());
+        }
+        // Bytes 72 to 92 are reserved for expansion and must be zero
+        bytes.extend([0; 20]);
+        bytes.extend(self.version_valid_for.to_be_bytes());
+        bytes.extend(self.sqlite_version_number.to_be_bytes());
+        bytes
+    }
+
+    /// Checks the rules the file format places on the header that parsing doesn't already
+    /// enforce. `file_len` is the length of the database file in bytes.
+    pub fn validate(&self, file_len: u64) -> Vec<HeaderValidationError> {
+        let mut problems = Vec::new();
+        if !(1..=4).contains(&self.schema_format_number) {
+            problems.push(HeaderValidationError::SchemaFormatNumber(
+                self.schema_format_number,
+            ));
+        }
+        // The in-header database size is only trusted when it was written by a version of SQLite
+        // that keeps it up to date, which it shows by matching version-valid-for to the change
+        // counter.
+        if self.version_valid_for != self.file_change_counter {
+            problems.push(HeaderValidationError::StaleDatabaseSize {
+                version_valid_for: self.version_valid_for,
+                file_change_counter: self.file_change_counter,
+            });
+        } else if self.database_size_in_pages == 0 {
+            // SQLite never writes a size of zero, and reads it as the size not being known
+            problems.push(HeaderValidationError::MissingDatabaseSize);
+        } else {
+            let file_pages = file_len.div_ceil(u64::from(self.page_size.get()));
+            if u64::from(self.database_size_in_pages) != file_pages {
+                problems.push(HeaderValidationError::DatabaseSizeMismatch {
+                    header_pages: self.database_size_in_pages,
+                    file_pages,
+                });
+            }
+        }
+        problems
+    }
+}
+
+/// SQLite refuses to open databases whose usable page size is smaller than this.
+const MIN_USABLE_SIZE: usize = 480;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum HeaderValidationError {
+    #[error("Schema format number must be between 1 and 4, was {0}")]
+    SchemaFormatNumber(u32),
+    #[error(
+        "Version-valid-for number {version_valid_for} doesn't match change counter {file_change_counter}, so the in-header database size is stale"
+    )]
+    StaleDatabaseSize {
+        version_valid_for: u32,
+        file_change_counter: u32,
+    },
+    #[error(
+        "The header says the database has {header_pages} pages, but the file holds {file_pages}"
+    )]
+    DatabaseSizeMismatch { header_pages: u32, file_pages: u64 },
+    #[error("The in-header database size is 0, so the size of the file is used instead")]
+    MissingDatabaseSize,
+}
+
+/// Errors parsing the database header. Errors in a single field carry the offset of the field
+/// within the header and the raw value found there.
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum DatabaseHeaderError {
     #[error("Length should be 100. Was {0}")]
     IncorrectLength(usize),
-    #[error("Header string should be SQLite format 3\\0, was {0}")]
-    IncorrectHeaderString(String),
-    #[error("DB Header problem encountered while computing {0}")]
-    MalformedDatabaseHeader(String),
+    #[error("Header string at offset {offset} should be SQLite format 3\\0, was {value:?}")]
+    IncorrectHeaderString { offset: usize, value: Vec<u8> },
+    #[error("Page size at offset {offset} is invalid: {error}")]
+    PageSize {
+        offset: usize,
+        value: u16,
+        error: PageSizeError,
+    },
+    #[error("File format write version at offset {offset} is invalid: {error}")]
+    FileFormatWriteVersion {
+        offset: usize,
+        value: u8,
+        error: FileFormatVersionError,
+    },
+    #[error("File format read version at offset {offset} is invalid: {error}")]
+    FileFormatReadVersion {
+        offset: usize,
+        value: u8,
+        error: FileFormatVersionError,
+    },
+    #[error("Text encoding at offset {offset} is invalid: {error}")]
+    TextEncoding {
+        offset: usize,
+        value: u32,
+        error: TextEncodingError,
+    },
+    #[error("Payload fraction at offset {offset} must be {expected}, was {value}")]
+    PayloadFraction {
+        offset: usize,
+        value: u8,
+        expected: u8,
+    },
+    #[error(
+        "Reserved space at offset {offset} of {value} bytes leaves {usable_size} usable bytes per page, fewer than {MIN_USABLE_SIZE}"
+    )]
+    ReservedSpace {
+        offset: usize,
+        value: u8,
+        usable_size: usize,
+    },
     #[error("Encountered error decoding: {0}")]
     DecodeError(DecodeError),
 }
@@ -125,18 +331,54 @@ impl TryFrom<Vec<u8>> for DatabaseHeader {
         }
 
         let header_string_bytes = &value[0..16];
-
-        let header_string = str::from_utf8(header_string_bytes).unwrap();
-        if header_string != "SQLite format 3\0" {
-            return Err(DatabaseHeaderError::IncorrectHeaderString(
-                header_string.to_owned(),
-            ));
+        if header_string_bytes != b"SQLite format 3\0" {
+            return Err(DatabaseHeaderError::IncorrectHeaderString {
+                offset: 0,
+                value: header_string_bytes.to_vec(),
+            });
         }
 
-        let page_size = get_u16_from_bytes(&value[16..18], "page_size")?;
-        let file_format_write_version = FileFormatVersion::try_from(value[18]).unwrap();
-        let file_format_read_version = FileFormatVersion::try_from(value[19]).unwrap();
+        let raw_page_size = get_u16_from_bytes(&value[16..18], "page_size")?;
+        let page_size =
+            PageSize::try_from(raw_page_size).map_err(|error| DatabaseHeaderError::PageSize {
+                offset: 16,
+                value: raw_page_size,
+                error,
+            })?;
+        let file_format_write_version =
+            FileFormatVersion::try_from(value[18]).map_err(|error| {
+                DatabaseHeaderError::FileFormatWriteVersion {
+                    offset: 18,
+                    value: value[18],
+                    error,
+                }
+            })?;
+        let file_format_read_version = FileFormatVersion::try_from(value[19]).map_err(|error| {
+            DatabaseHeaderError::FileFormatReadVersion {
+                offset: 19,
+                value: value[19],
+                error,
+            }
+        })?;
         let reserved_space = value[20];
+        let usable_size = page_size.get() as usize - usize::from(reserved_space);
+        if usable_size < MIN_USABLE_SIZE {
+            return Err(DatabaseHeaderError::ReservedSpace {
+                offset: 20,
+                value: reserved_space,
+                usable_size,
+            });
+        }
+        // Cell sizes are computed from these, and SQLite refuses to open files where they differ
+        for (offset, expected) in [(21, 64), (22, 32), (23, 32)] {
+            if value[offset] != expected {
+                return Err(DatabaseHeaderError::PayloadFraction {
+                    offset,
+                    value: value[offset],
+                    expected,
+                });
+            }
+        }
         let maximum_embedded_payload_fraction = value[21];
         let minimum_embedded_payload_fraction = value[22];
         let leaf_payload_fraction = value[23];
@@ -149,10 +391,14 @@ impl TryFrom<Vec<u8>> for DatabaseHeader {
         let default_page_cache_size =
             get_u32_from_bytes(&value[48..52], "default_page_cache_size")?;
         let largest_root_page = get_u32_from_bytes(&value[52..56], "largest_root_page")?;
-        let text_encoding =
-            TextEncoding::try_from(get_u32_from_bytes(&value[56..60], "text_encoding")?).map_err(
-                |_| DatabaseHeaderError::MalformedDatabaseHeader("text_encoding".to_owned()),
-            )?;
+        let raw_text_encoding = get_u32_from_bytes(&value[56..60], "text_encoding")?;
+        let text_encoding = TextEncoding::try_from(raw_text_encoding).map_err(|error| {
+            DatabaseHeaderError::TextEncoding {
+                offset: 56,
+                value: raw_text_encoding,
+                error,
+            }
+        })?;
         let user_version = get_u32_from_bytes(&value[60..64], "user_version")?;
         let incremental_vaccuum_mode =
             get_u32_from_bytes(&value[64..68], "incremental_vaccuum_mode")? > 0;
@@ -188,9 +434,80 @@ impl TryFrom<Vec<u8>> for DatabaseHeader {
 
 #[cfg(test)]
 mod tests {
-    use crate::database::header::{
-        FileFormatVersion, FileFormatVersionError, TextEncoding, TextEncodingError,
+    use crate::database::{
+        header::{
+            DatabaseHeader, DatabaseHeaderError, FileFormatVersion, FileFormatVersionError,
+            HeaderValidationError, PageSize, PageSizeError, TextEncoding, TextEncodingError,
+        },
+        test_support::header_bytes,
     };
+    use proptest::prelude::*;
+
+    fn file_format_version() -> impl Strategy<Value = FileFormatVersion> {
+        prop_oneof![
+            Just(FileFormatVersion::Legacy),
+            Just(FileFormatVersion::Wal)
+        ]
+    }
+
+    prop_compose! {
+        fn database_header()(
+            page_size in (9u32..=16).prop_map(|power| PageSize(1 << power)),
+            file_format_write_version in file_format_version(),
+            file_format_read_version in file_format_version(),
+            reserved_space in 0u8..=32,
+            counters in any::<[u32; 12]>(),
+            text_encoding in prop_oneof![
+                Just(TextEncoding::Utf8),
+                Just(TextEncoding::Utf16Le),
+                Just(TextEncoding::Utf16Be),
+            ],
+            incremental_vaccuum_mode in any::<bool>(),
+        ) -> DatabaseHeader {
+            DatabaseHeader {
+                page_size,
+                file_format_write_version,
+                file_format_read_version,
+                reserved_space,
+                maximum_embedded_payload_fraction: 64,
+                minimum_embedded_payload_fraction: 32,
+                leaf_payload_fraction: 32,
+                file_change_counter: counters[0],
+                database_size_in_pages: counters[1],
+                first_freelist: counters[2],
+                num_freelist: counters[3],
+                schema_cookie: counters[4],
+                schema_format_number: counters[5],
+                default_page_cache_size: counters[6],
+                largest_root_page: counters[7],
+                text_encoding,
+                user_version: counters[8],
+                incremental_vaccuum_mode,
+                application_id: counters[9],
+                version_valid_for: counters[10],
+                sqlite_version_number: counters[11],
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn header_round_trips(header in database_header()) {
+            let bytes = header.to_bytes();
+            prop_assert_eq!(bytes.len(), 100);
+            prop_assert!(bytes[72..92].iter().all(|byte| *byte == 0));
+            prop_assert_eq!(DatabaseHeader::try_from(bytes).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn serializes_parsed_header() {
+        let bytes = header_bytes(1, 7);
+        assert_eq!(
+            DatabaseHeader::try_from(bytes.clone()).unwrap().to_bytes(),
+            bytes
+        );
+    }
 
     #[test]
     fn file_format_version_conversion() {
@@ -215,4 +532,142 @@ mod tests {
             Err(TextEncodingError::IncorrectVariant(4))
         );
     }
+
+    #[test]
+    fn page_size_conversion() {
+        assert_eq!(PageSize::try_from(512).map(|size| size.get()), Ok(512));
+        assert_eq!(PageSize::try_from(32768).map(|size| size.get()), Ok(32768));
+        assert_eq!(PageSize::try_from(1).map(|size| size.get()), Ok(65536));
+        assert_eq!(PageSize::try_from(1).map(|size| size.to_raw()), Ok(1));
+        assert_eq!(PageSize::from_bytes(65536).map(|size| size.to_raw()), Ok(1));
+        assert_eq!(
+            PageSize::from_bytes(131072),
+            Err(PageSizeError::InvalidBytes(131072))
+        );
+        for invalid in [0, 2, 256, 1000, 4095] {
+            assert_eq!(
+                PageSize::try_from(invalid),
+                Err(PageSizeError::Invalid(invalid))
+            );
+        }
+
+        let header = DatabaseHeader::try_from(header_bytes(1, 1)).unwrap();
+        assert_eq!(header.page_size.get(), 65536);
+        assert_eq!(header.usable_size(), 65536);
+        assert_eq!(
+            DatabaseHeader::try_from(header_bytes(1000, 1)).unwrap_err(),
+            DatabaseHeaderError::PageSize {
+                offset: 16,
+                value: 1000,
+                error: PageSizeError::Invalid(1000),
+            }
+        );
+    }
+
+    #[test]
+    fn header_validation() {
+        let header = DatabaseHeader::try_from(header_bytes(512, 2)).unwrap();
+        assert_eq!(header.validate(1024), vec![]);
+        assert_eq!(
+            header.validate(1536),
+            vec![HeaderValidationError::DatabaseSizeMismatch {
+                header_pages: 2,
+                file_pages: 3,
+            }]
+        );
+
+        let header = DatabaseHeader::try_from(header_bytes(512, 0)).unwrap();
+        assert_eq!(
+            header.validate(1024),
+            vec![HeaderValidationError::MissingDatabaseSize]
+        );
+
+        let mut bytes = header_bytes(512, 2);
+        bytes[44..48].copy_from_slice(&5u32.to_be_bytes());
+        bytes[92..96].copy_from_slice(&7u32.to_be_bytes());
+        let header = DatabaseHeader::try_from(bytes).unwrap();
+        assert_eq!(
+            header.validate(1536),
+            vec![
+                HeaderValidationError::SchemaFormatNumber(5),
+                HeaderValidationError::StaleDatabaseSize {
+                    version_valid_for: 7,
+                    file_change_counter: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_fields_report_offset_and_value() {
+        let mut bytes = header_bytes(512, 1);
+        bytes[0..16].copy_from_slice(&[0xff; 16]);
+        assert_eq!(
+            DatabaseHeader::try_from(bytes).unwrap_err(),
+            DatabaseHeaderError::IncorrectHeaderString {
+                offset: 0,
+                value: vec![0xff; 16],
+            }
+        );
+
+        let mut bytes = header_bytes(512, 1);
+        bytes[18] = 0;
+        assert_eq!(
+            DatabaseHeader::try_from(bytes).unwrap_err(),
+            DatabaseHeaderError::FileFormatWriteVersion {
+                offset: 18,
+                value: 0,
+                error: FileFormatVersionError::IncorrectVariant(0),
+            }
+        );
+
+        let mut bytes = header_bytes(512, 1);
+        bytes[19] = 3;
+        assert_eq!(
+            DatabaseHeader::try_from(bytes).unwrap_err(),
+            DatabaseHeaderError::FileFormatReadVersion {
+                offset: 19,
+                value: 3,
+                error: FileFormatVersionError::IncorrectVariant(3),
+            }
+        );
+
+        let mut bytes = header_bytes(512, 1);
+        bytes[20] = 40;
+        assert_eq!(
+            DatabaseHeader::try_from(bytes).unwrap_err(),
+            DatabaseHeaderError::ReservedSpace {
+                offset: 20,
+                value: 40,
+                usable_size: 472,
+            }
+        );
+
+        let mut bytes = header_bytes(512, 1);
+        bytes[23] = 0;
+        assert_eq!(
+            DatabaseHeader::try_from(bytes).unwrap_err(),
+            DatabaseHeaderError::PayloadFraction {
+                offset: 23,
+                value: 0,
+                expected: 32,
+            }
+        );
+
+        let mut bytes = header_bytes(512, 1);
+        bytes[56..60].copy_from_slice(&9u32.to_be_bytes());
+        assert_eq!(
+            DatabaseHeader::try_from(bytes).unwrap_err(),
+            DatabaseHeaderError::TextEncoding {
+                offset: 56,
+                value: 9,
+                error: TextEncodingError::IncorrectVariant(9),
+            }
+        );
+
+        assert_eq!(
+            DatabaseHeader::try_from(vec![0; 40]).unwrap_err(),
+            DatabaseHeaderError::IncorrectLength(40)
+        );
+    }
 }
@@ -0,0 +1,866 @@
+use std::{cmp::Ordering, collections::HashSet};
+
+use crate::database::{
+    Database,
+    collation::compare_keys,
+    cursor::{CursorError, index::IndexCursor, table::TableCursor},
+    freelist::{Freelist, FreelistError},
+    header::HeaderValidationError,
+    page::{
+        MAX_BTREE_DEPTH,
+        cell::{Cell, CellError, PayloadLimits},
+        header::PageType,
+        space::SpaceError,
+    },
+    pager::PagerError,
+    ptrmap::{PointerMap, PointerMapError, PointerMapMismatch, pointer_map_page},
+    record::{Record, RecordError, Value},
+    schema::{IndexColumn, SCHEMA_ROOT_PAGE, Schema, SchemaError, SchemaObject, SchemaObjectType},
+};
+use thiserror::Error;
+
+/// One problem found by [`check`]. Together they play the part of the rows `PRAGMA
+/// integrity_check` returns.
+#[derive(Debug, Error)]
+pub enum IntegrityProblem {
+    #[error("The database header is invalid: {0}")]
+    Header(HeaderValidationError),
+    #[error("Couldn't read the schema: {0}")]
+    Schema(SchemaError),
+    #[error("{0}")]
+    Pager(PagerError),
+    #[error("Page {referenced_from} refers to page {page_number}, which is outside the database")]
+    PageOutOfRange {
+        page_number: u32,
+        referenced_from: u32,
+    },
+    #[error("Page {referenced_from} refers to page {page_number}, which is already in use")]
+    ReferencedTwice {
+        page_number: u32,
+        referenced_from: u32,
+    },
+    #[error("Page {0} is never used")]
+    NeverUsed(u32),
+    #[error(
+        "Page {page_number} is a {page_type:?} page, which doesn't belong in the b-tree rooted at page {root_page}"
+    )]
+    UnexpectedPageType {
+        page_number: u32,
+        page_type: PageType,
+        root_page: u32,
+    },
+    #[error("Page {page_number}: {error}")]
+    Space { page_number: u32, error: SpaceError },
+    #[error("Cell {index} on page {page_number} couldn't be decoded: {error}")]
+    Cell {
+        page_number: u32,
+        index: usize,
+        error: CellError,
+    },
+    #[error("Cell {index} on page {page_number} has a payload that isn't a valid record: {error}")]
+    Record {
+        page_number: u32,
+        index: usize,
+        error: RecordError,
+    },
+    #[error("Cell {index} on page {page_number} has a key out of order with the cell before it")]
+    KeyOutOfOrder { page_number: u32, index: usize },
+    #[error("Cell {index} on page {page_number} has a key outside the range its parent allows")]
+    KeyOutOfBounds { page_number: u32, index: usize },
+    #[error(
+        "Leaf page {page_number} is at depth {depth}, but other leaves of the b-tree rooted at page {root_page} are at depth {expected}"
+    )]
+    UnevenDepth {
+        page_number: u32,
+        root_page: u32,
+        depth: usize,
+        expected: usize,
+    },
+    #[error(
+        "Page {page_number} is more than {} levels down the b-tree rooted at page {root_page}",
+        MAX_BTREE_DEPTH
+    )]
+    TooDeep { page_number: u32, root_page: u32 },
+    #[error(
+        "The overflow chain of cell {index} on page {page_number} has {found} pages, but should have {expected}"
+    )]
+    OverflowChainTooShort {
+        page_number: u32,
+        index: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[error(
+        "The overflow chain of cell {index} on page {page_number} continues past the {expected} pages it should have"
+    )]
+    OverflowChainTooLong {
+        page_number: u32,
+        index: usize,
+        expected: usize,
+    },
+    #[error("{0}")]
+    Freelist(FreelistError),
+    #[error("{0}")]
+    PointerMap(PointerMapError),
+    #[error(
+        "The pointer map entry for page {} is {:?}, but should be {:?}",
+        .0.page_number,
+        .0.found,
+        .0.expected
+    )]
+    PointerMapMismatch(PointerMapMismatch),
+    #[error("Row {rowid} of table {table} is missing from index {index}")]
+    MissingIndexEntry {
+        table: String,
+        index: String,
+        rowid: i64,
+    },
+    #[error("Index {index} has an entry {entry:?} that doesn't match any row of table {table}")]
+    ExtraIndexEntry {
+        table: String,
+        index: String,
+        entry: Vec<Value>,
+    },
+    #[error("Couldn't compare index {index} against its table: {error}")]
+    Cursor { index: String, error: CursorError },
+}
+
+/// Checks the whole database the way `PRAGMA integrity_check` does, collecting every problem
+/// instead of stopping at the first:
+///
+/// - every page is used exactly once, by a b-tree, an overflow chain, the freelist or the
+///   pointer map,
+/// - b-tree keys are in order and within the range their parent cell allows,
+/// - every leaf of a b-tree is at the same depth,
+/// - cells and freeblocks don't overlap,
+/// - every index has exactly one entry per row of its table.
+///
+/// Key order is only checked on indexes whose `CREATE INDEX` statement can be read, since that
+/// is where their collating functions and sort orders come from. For the same reason, indexes
+/// SQLite created automatically, partial indexes and indexes on expressions aren't compared
+/// against their tables.
+pub fn check(database: &Database) -> Vec<IntegrityProblem> {
+    let mut checker = Checker {
+        database,
+        limits: database.payload_limits(),
+        used: vec![false; database.pager().len()],
+        problems: database
+            .validate_header()
+            .into_iter()
+            .map(IntegrityProblem::Header)
+            .collect(),
+        damaged_roots: HashSet::new(),
+    };
+    checker.check();
+    checker.problems
+}
+
+/// The key of a b-tree cell: a rowid in table b-trees and the whole entry in index b-trees.
+#[derive(Debug, Clone)]
+enum Key {
+    Rowid(i64),
+    Entry(Vec<Value>),
+}
+
+/// The b-tree being checked.
+struct Tree {
+    root_page: u32,
+    index: bool,
+    /// The collating function and sort order of each column of an index's keys, or `None` if
+    /// they aren't known, which skips key order checks on index b-trees.
+    columns: Option<Vec<IndexColumn>>,
+    /// The depth of the first leaf found, which every other leaf should match.
+    leaf_depth: Option<usize>,
+}
+
+impl Tree {
+    fn compare(&self, a: &Key, b: &Key) -> Option<Ordering> {
+        match (a, b) {
+            (Key::Rowid(a), Key::Rowid(b)) => Some(a.cmp(b)),
+            (Key::Entry(a), Key::Entry(b)) => Some(compare_keys(a, b, self.columns.as_ref()?)),
+            _ => None,
+        }
+    }
+
+    /// Whether `key` falls after `lower` and before `upper`. Keys in a table b-tree can equal
+    /// the rowid of the parent cell above them, but index entries are unique.
+    fn in_bounds(&self, key: &Key, lower: Option<&Key>, upper: Option<&Key>) -> bool {
+        let above_lower =
+            lower.is_none_or(|lower| !self.compare(lower, key).is_some_and(Ordering::is_ge));
+        let below_upper = upper.is_none_or(|upper| match self.compare(key, upper) {
+            Some(Ordering::Greater) => false,
+            Some(Ordering::Equal) => !self.index,
+            _ => true,
+        });
+        above_lower && below_upper
+    }
+}
+
+struct Checker<'a> {
+    database: &'a Database,
+    limits: PayloadLimits,
+    /// Whether page `n` is in use, at index `n - 1`.
+    used: Vec<bool>,
+    problems: Vec<IntegrityProblem>,
+    /// Roots of b-trees with problems, which aren't worth comparing against other b-trees.
+    damaged_roots: HashSet<u32>,
+}
+
+impl Checker<'_> {
+    fn check(&mut self) {
+        let database = self.database;
+        let problems_before = self.problems.len();
+        let auto_vacuum = database.header.largest_root_page != 0;
+        let lock_byte_page = database.pager().lock_byte_page();
+        self.mark(lock_byte_page);
+        if auto_vacuum {
+            let usable_size = database.header.usable_size();
+            for page_number in 2..=self.used.len() as u32 {
+                if pointer_map_page(usable_size, lock_byte_page, page_number) == page_number {
+                    self.mark(page_number);
+                }
+            }
+        }
+
+        self.check_tree(SCHEMA_ROOT_PAGE, SCHEMA_ROOT_PAGE, false, None);
+        let schema = match database.schema() {
+            Ok(schema) => Some(schema),
+            Err(error) => {
+                self.problems.push(IntegrityProblem::Schema(error));
+                None
+            }
+        };
+        for object in schema.iter().flat_map(|schema| &schema.objects) {
+            let Some(root_page) = object.root_page else {
+                continue;
+            };
+            let (index, columns) = match object.object_type {
+                SchemaObjectType::Index => (
+                    true,
+                    schema
+                        .as_ref()
+                        .and_then(|schema| schema.index_definition(&object.name))
+                        .map(|definition| definition.columns),
+                ),
+                _ => (object.without_rowid(), None),
+            };
+            self.check_tree(root_page, SCHEMA_ROOT_PAGE, index, columns);
+        }
+        self.check_freelist();
+        for page_number in 1..=self.used.len() as u32 {
+            if !self.used[page_number as usize - 1] {
+                self.problems.push(IntegrityProblem::NeverUsed(page_number));
+            }
+        }
+
+        // The pointer map check walks the b-trees again, so only run it on a sound structure
+        let sound = self.problems.len() == problems_before;
+        if auto_vacuum {
+            match PointerMap::read(database) {
+                Ok(map) if sound => match map.check(database) {
+                    Ok(mismatches) => self.problems.extend(
+                        mismatches
+                            .into_iter()
+                            .map(IntegrityProblem::PointerMapMismatch),
+                    ),
+                    Err(error) => self.problems.push(IntegrityProblem::PointerMap(error)),
+                },
+                Ok(_) => {}
+                Err(error) => self.problems.push(IntegrityProblem::PointerMap(error)),
+            }
+        }
+
+        if let Some(schema) = &schema {
+            for index in schema.indexes() {
+                self.check_index_rows(schema, index);
+            }
+        }
+    }
+
+    /// Marks a page as used by something other than a b-tree or the freelist.
+    fn mark(&mut self, page_number: u32) {
+        if let Some(used) = self.used.get_mut((page_number as usize).wrapping_sub(1)) {
+            *used = true;
+        }
+    }
+
+    /// Marks a page as used, reporting it if it is outside the database or already in use.
+    fn claim(&mut self, page_number: u32, referenced_from: u32) -> bool {
+        match self.used.get_mut((page_number as usize).wrapping_sub(1)) {
+            None => {
+                self.problems.push(IntegrityProblem::PageOutOfRange {
+                    page_number,
+                    referenced_from,
+                });
+                false
+            }
+            Some(true) => {
+                self.problems.push(IntegrityProblem::ReferencedTwice {
+                    page_number,
+                    referenced_from,
+                });
+                false
+            }
+            Some(used) => {
+                *used = true;
+                true
+            }
+        }
+    }
+
+    fn check_tree(
+        &mut self,
+        root_page: u32,
+        referenced_from: u32,
+        index: bool,
+        columns: Option<Vec<IndexColumn>>,
+    ) {
+        let problems_before = self.problems.len();
+        let mut tree = Tree {
+            root_page,
+            index,
+            columns,
+            leaf_depth: None,
+        };
+        self.check_page(&mut tree, root_page, referenced_from, 0, None, None);
+        if self.problems.len() != problems_before {
+            self.damaged_roots.insert(root_page);
+        }
+    }
+
+    /// Checks a b-tree page and everything below it. Every key on the page must fall between
+    /// `lower` and `upper`, the keys of the parent cells on either side of it. Pages below
+    /// `MAX_BTREE_DEPTH` are left unchecked, which also bounds the recursion.
+    fn check_page(
+        &mut self,
+        tree: &mut Tree,
+        page_number: u32,
+        referenced_from: u32,
+        depth: usize,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+    ) {
+        if !self.claim(page_number, referenced_from) {
+            return;
+        }
+        if depth >= MAX_BTREE_DEPTH {
+            self.problems.push(IntegrityProblem::TooDeep {
+                page_number,
+                root_page: tree.root_page,
+            });
+            return;
+        }
+        let page = match self.database.pager().get(page_number) {
+            Ok(page) => page,
+            Err(error) => {
+                self.problems.push(IntegrityProblem::Pager(error));
+                return;
+            }
+        };
+        let page_type = page.get_page_header().get_page_type();
+        let index_page = matches!(page_type, PageType::InteriorIndex | PageType::LeafIndex);
+        if index_page != tree.index {
+            self.problems.push(IntegrityProblem::UnexpectedPageType {
+                page_number,
+                page_type: page_type.clone(),
+                root_page: tree.root_page,
+            });
+            return;
+        }
+        if let Err(error) = page.space_usage(self.limits) {
+            self.problems
+                .push(IntegrityProblem::Space { page_number, error });
+        }
+
+        // Each child with the keys on either side of it
+        let mut children: Vec<(u32, Option<Key>, Option<Key>)> = Vec::new();
+        let mut previous: Option<Key> = None;
+        for (index, cell) in page.cells(self.limits).enumerate() {
+            let cell = match cell {
+                Ok(cell) => cell,
+                Err(error) => {
+                    self.problems.push(IntegrityProblem::Cell {
+                        page_number,
+                        index,
+                        error,
+                    });
+                    continue;
+                }
+            };
+            self.check_overflow(page_number, index, &cell);
+            let key = self.key(page_number, index, &cell);
+            if let Some(key) = &key {
+                if previous.as_ref().is_some_and(|previous| {
+                    tree.compare(previous, key).is_some_and(Ordering::is_ge)
+                }) {
+                    self.problems
+                        .push(IntegrityProblem::KeyOutOfOrder { page_number, index });
+                } else if !tree.in_bounds(key, lower, upper) {
+                    self.problems
+                        .push(IntegrityProblem::KeyOutOfBounds { page_number, index });
+                }
+            }
+            if let Some(child) = cell.left_child_page() {
+                children.push((child, previous.clone().or(lower.cloned()), key.clone()));
+            }
+            if key.is_some() {
+                previous = key;
+            }
+        }
+
+        match page.get_page_header().get_right_most_pointer() {
+            Some(child) => children.push((child, previous.or(lower.cloned()), upper.cloned())),
+            None => match tree.leaf_depth {
+                Some(expected) if expected != depth => {
+                    self.problems.push(IntegrityProblem::UnevenDepth {
+                        page_number,
+                        root_page: tree.root_page,
+                        depth,
+                        expected,
+                    })
+                }
+                Some(_) => {}
+                None => tree.leaf_depth = Some(depth),
+            },
+        }
+        for (child, lower, upper) in children {
+            self.check_page(
+                tree,
+                child,
+                page_number,
+                depth + 1,
+                lower.as_ref(),
+                upper.as_ref(),
+            );
+        }
+    }
+
+    /// The key of a cell, or `None` if an index entry can't be read. Problems with the overflow
+    /// chain are reported by `check_overflow`, so only bad records are reported here.
+    fn key(&mut self, page_number: u32, index: usize, cell: &Cell) -> Option<Key> {
+        let (payload, payload_size, first_overflow_page) = match cell {
+            Cell::TableLeaf(cell) => return Some(Key::Rowid(cell.rowid)),
+            Cell::TableInterior(cell) => return Some(Key::Rowid(cell.rowid)),
+            Cell::IndexLeaf(cell) => (&cell.payload, cell.payload_size, cell.first_overflow_page),
+            Cell::IndexInterior(cell) => {
+                (&cell.payload, cell.payload_size, cell.first_overflow_page)
+            }
+        };
+        let payload = self
+            .database
+            .read_payload(payload, payload_size, first_overflow_page)
+            .ok()?;
+        match Record::decode(&payload, &self.database.header.text_encoding) {
+            Ok(record) => Some(Key::Entry(record.values)),
+            Err(error) => {
+                self.problems.push(IntegrityProblem::Record {
+                    page_number,
+                    index,
+                    error,
+                });
+                None
+            }
+        }
+    }
+
+    /// Claims the pages of a cell's overflow chain, checking it is as long as the payload needs.
+    fn check_overflow(&mut self, page_number: u32, index: usize, cell: &Cell) {
+        let (local, payload_size) = match cell {
+            Cell::TableLeaf(cell) => (cell.payload.len(), cell.payload_size),
+            Cell::IndexLeaf(cell) => (cell.payload.len(), cell.payload_size),
+            Cell::IndexInterior(cell) => (cell.payload.len(), cell.payload_size),
+            Cell::TableInterior(_) => return,
+        };
+        let Some(first_overflow_page) = cell.first_overflow_page() else {
+            return;
+        };
+        let expected = (payload_size as usize)
+            .saturating_sub(local)
+            .div_ceil(self.limits.usable_size - 4);
+        let mut found = 0;
+        let mut parent = page_number;
+        let mut next = first_overflow_page;
+        while next != 0 && found < expected {
+            if !self.claim(next, parent) {
+                return;
+            }
+            found += 1;
+            let bytes = match self.database.pager().page_bytes(next) {
+                Ok(bytes) => bytes,
+                Err(error) => {
+                    self.problems.push(IntegrityProblem::Pager(error));
+                    return;
+                }
+            };
+            parent = next;
+            next = bytes.get(0..4).map_or(0, |bytes| {
+                u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            });
+        }
+        if found < expected {
+            self.problems.push(IntegrityProblem::OverflowChainTooShort {
+                page_number,
+                index,
+                expected,
+                found,
+            });
+        } else if next != 0 {
+            self.problems.push(IntegrityProblem::OverflowChainTooLong {
+                page_number,
+                index,
+                expected,
+            });
+        }
+    }
+
+    /// Claims every freelist page. A freelist that can't be read leaves its pages unclaimed, so
+    /// they are also reported as never used.
+    fn check_freelist(&mut self) {
+        let database = self.database;
+        let freelist = match Freelist::read(
+            database.pager(),
+            database.header.usable_size(),
+            database.header.first_freelist,
+        ) {
+            Ok(freelist) => freelist,
+            Err(error) => {
+                self.problems.push(IntegrityProblem::Freelist(error));
+                return;
+            }
+        };
+        if let Err(error) = freelist.validate(database.header.num_freelist) {
+            self.problems.push(IntegrityProblem::Freelist(error));
+        }
+        // The first trunk page is referenced from the database header on page 1
+        let mut previous_trunk = 1;
+        for trunk in &freelist.trunks {
+            self.claim(trunk.page_number, previous_trunk);
+            for leaf in &trunk.leaves {
+                self.claim(*leaf, trunk.page_number);
+            }
+            previous_trunk = trunk.page_number;
+        }
+    }
+
+    /// Compares an index against its table: every row should have exactly one entry holding the
+    /// indexed columns followed by the rowid, and there should be no other entries.
+    fn check_index_rows(&mut self, schema: &Schema, index: &SchemaObject) {
+        let Some(index_root) = index.root_page else {
+            return;
+        };
+        let Some(table) = schema.get(&index.tbl_name) else {
+            return;
+        };
+        let Some(table_root) = table.root_page.filter(|_| !table.without_rowid()) else {
+            return;
+        };
+        if self.damaged_roots.contains(&index_root) || self.damaged_roots.contains(&table_root) {
+            return;
+        }
+        let Some(definition) = schema
+            .index_definition(&index.name)
+            .filter(|definition| !definition.partial)
+        else {
+            return;
+        };
+        let Some((table_columns, positions)) = table.columns().zip(
+            definition
+                .columns
+                .iter()
+                .map(|column| column.column)
+                .collect::<Option<Vec<usize>>>(),
+        ) else {
+            return;
+        };
+
+        let cursor_problem = |error| IntegrityProblem::Cursor {
+            index: index.name.clone(),
+            error,
+        };
+        let mut expected = Vec::new();
+        for row in TableCursor::new(self.database, table_root) {
+            let (rowid, values) = match row {
+                Ok(row) => row,
+                Err(error) => return self.problems.push(cursor_problem(error)),
+            };
+            let mut entry: Vec<Value> = positions
+                .iter()
+                .map(|position| match table_columns[*position].rowid_alias {
+                    true => Value::Integer(rowid),
+                    false => values.get(*position).cloned().unwrap_or(Value::Null),
+                })
+                .collect();
+            entry.push(Value::Integer(rowid));
+            expected.push(entry);
+        }
+        let mut found = Vec::new();
+        for entry in IndexCursor::new(self.database, index_root, definition.columns.clone()) {
+            match entry {
+                Ok(entry) => found.push(entry),
+                Err(error) => return self.problems.push(cursor_problem(error)),
+            }
+        }
+
+        // An entry that is a prefix of another, like one missing its rowid, isn't a match
+        let compare = |a: &Vec<Value>, b: &Vec<Value>| {
+            compare_keys(a, b, &definition.columns).then(a.len().cmp(&b.len()))
+        };
+        expected.sort_by(compare);
+        found.sort_by(compare);
+        let (mut expected, mut found) = (
+            expected.into_iter().peekable(),
+            found.into_iter().peekable(),
+        );
+        loop {
+            let ordering = match (expected.peek(), found.peek()) {
+                (Some(a), Some(b)) => compare(a, b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match ordering {
+                Ordering::Less => {
+                    let entry = expected.next().expect("peeked an entry");
+                    let Some(Value::Integer(rowid)) = entry.last() else {
+                        unreachable!("the rowid was pushed onto every expected entry");
+                    };
+                    self.problems.push(IntegrityProblem::MissingIndexEntry {
+                        table: table.name.clone(),
+                        index: index.name.clone(),
+                        rowid: *rowid,
+                    });
+                }
+                Ordering::Greater => {
+                    self.problems.push(IntegrityProblem::ExtraIndexEntry {
+                        table: table.name.clone(),
+                        index: index.name.clone(),
+                        entry: found.next().expect("peeked an entry"),
+                    });
+                }
+                Ordering::Equal => {
+                    expected.next();
+                    found.next();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IntegrityProblem;
+    use crate::database::{
+        Database,
+        page::header::PageType,
+        record::Value,
+        test_support::{
+            btree_page, database_bytes, index_leaf_cell, record_bytes, schema_row,
+            table_interior_cell, table_leaf_cell,
+        },
+    };
+    use crate::util::write_varint;
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_owned())
+    }
+
+    fn row(rowid: i64, value: &str) -> Vec<u8> {
+        table_leaf_cell(rowid, &record_bytes(&[text(value)]))
+    }
+
+    fn entry(value: &str, rowid: i64) -> Vec<u8> {
+        index_leaf_cell(&record_bytes(&[text(value), Value::Integer(rowid)]))
+    }
+
+    /// Table `t` is rooted on page 2 and, if `index_root` is set, index `i` on `t(a)` is rooted
+    /// there. `pages` are pages 2 onwards.
+    fn database(index_root: Option<i64>, pages: Vec<Vec<u8>>) -> Database {
+        let mut schema = vec![schema_row(1, "table", "t", "t", 2, "CREATE TABLE t(a)")];
+        if let Some(root) = index_root {
+            schema.push(schema_row(
+                2,
+                "index",
+                "i",
+                "t",
+                root,
+                "CREATE INDEX i ON t(a)",
+            ));
+        }
+        let mut all_pages = vec![btree_page(512, 100, 13, None, &schema)];
+        all_pages.extend(pages);
+        Database::from_bytes(database_bytes(512, all_pages)).unwrap()
+    }
+
+    fn table_leaf() -> Vec<u8> {
+        btree_page(512, 0, 13, None, &[row(1, "b"), row(2, "a")])
+    }
+
+    #[test]
+    fn sound_database_has_no_problems() {
+        let database = database(
+            Some(3),
+            vec![
+                table_leaf(),
+                btree_page(512, 0, 10, None, &[entry("a", 2), entry("b", 1)]),
+            ],
+        );
+        assert!(database.integrity_check().is_empty());
+    }
+
+    #[test]
+    fn reports_unused_and_shared_pages() {
+        let problems = database(
+            Some(3),
+            vec![
+                btree_page(512, 0, 5, Some(3), &[table_interior_cell(4, 1)]),
+                btree_page(512, 0, 10, None, &[entry("a", 2)]),
+                btree_page(512, 0, 13, None, &[row(1, "a")]),
+                vec![0; 512],
+            ],
+        )
+        .integrity_check();
+        assert!(matches!(
+            problems.as_slice(),
+            [
+                IntegrityProblem::UnexpectedPageType {
+                    page_number: 3,
+                    page_type: PageType::LeafIndex,
+                    root_page: 2,
+                },
+                IntegrityProblem::ReferencedTwice {
+                    page_number: 3,
+                    referenced_from: 1,
+                },
+                IntegrityProblem::NeverUsed(5),
+            ]
+        ));
+    }
+
+    #[test]
+    fn reports_keys_out_of_order_and_uneven_depth() {
+        let problems = database(
+            None,
+            vec![
+                btree_page(512, 0, 5, Some(4), &[table_interior_cell(3, 2)]),
+                btree_page(512, 0, 13, None, &[row(1, "a"), row(3, "b")]),
+                btree_page(512, 0, 5, Some(5), &[]),
+                btree_page(512, 0, 13, None, &[row(5, "c"), row(4, "d")]),
+            ],
+        )
+        .integrity_check();
+        assert!(matches!(
+            problems.as_slice(),
+            [
+                IntegrityProblem::KeyOutOfBounds {
+                    page_number: 3,
+                    index: 1,
+                },
+                IntegrityProblem::KeyOutOfOrder {
+                    page_number: 5,
+                    index: 1,
+                },
+                IntegrityProblem::UnevenDepth {
+                    page_number: 5,
+                    root_page: 2,
+                    depth: 2,
+                    expected: 1,
+                },
+            ]
+        ));
+
+        let problems = database(
+            Some(3),
+            vec![
+                table_leaf(),
+                btree_page(512, 0, 10, None, &[entry("b", 1), entry("a", 2)]),
+            ],
+        )
+        .integrity_check();
+        assert!(matches!(
+            problems.as_slice(),
+            [IntegrityProblem::KeyOutOfOrder {
+                page_number: 3,
+                index: 1,
+            }]
+        ));
+    }
+
+    #[test]
+    fn stops_at_the_btree_depth_limit() {
+        // Interior pages 2 to 24 each lead to the next through the right-most pointer
+        let mut pages: Vec<Vec<u8>> = (3..=25)
+            .map(|child| btree_page(512, 0, 5, Some(child), &[]))
+            .collect();
+        pages.push(table_leaf());
+        let problems = database(None, pages).integrity_check();
+        assert!(matches!(
+            problems.as_slice(),
+            [
+                IntegrityProblem::TooDeep {
+                    page_number: 22,
+                    root_page: 2,
+                },
+                IntegrityProblem::NeverUsed(23),
+                IntegrityProblem::NeverUsed(24),
+                IntegrityProblem::NeverUsed(25),
+            ]
+        ));
+    }
+
+    #[test]
+    fn reports_index_entries_not_matching_rows() {
+        let problems = database(
+            Some(3),
+            vec![
+                table_leaf(),
+                btree_page(512, 0, 10, None, &[entry("a", 2), entry("c", 1)]),
+            ],
+        )
+        .integrity_check();
+        match problems.as_slice() {
+            [
+                IntegrityProblem::MissingIndexEntry {
+                    table,
+                    index,
+                    rowid: 1,
+                },
+                IntegrityProblem::ExtraIndexEntry { entry, .. },
+            ] => {
+                assert_eq!((table.as_str(), index.as_str()), ("t", "i"));
+                assert_eq!(entry, &vec![text("c"), Value::Integer(1)]);
+            }
+            problems => panic!("unexpected problems {problems:?}"),
+        }
+    }
+
+    #[test]
+    fn reports_overflow_chains_of_the_wrong_length() {
+        // A 600 byte payload keeps 92 bytes on the page and needs one overflow page
+        let mut overflowing = write_varint(600);
+        overflowing.extend(write_varint(1));
+        overflowing.extend([0x55; 92]);
+        overflowing.extend(3u32.to_be_bytes());
+        let mut overflow_page = vec![0x55; 512];
+        overflow_page[0..4].copy_from_slice(&4u32.to_be_bytes());
+        let problems = database(
+            None,
+            vec![
+                btree_page(512, 0, 13, None, &[overflowing]),
+                overflow_page,
+                vec![0; 512],
+            ],
+        )
+        .integrity_check();
+        assert!(matches!(
+            problems.as_slice(),
+            [
+                IntegrityProblem::OverflowChainTooLong {
+                    page_number: 2,
+                    index: 0,
+                    expected: 1,
+                },
+                IntegrityProblem::NeverUsed(4),
+            ]
+        ));
+    }
+}
@@ -0,0 +1,500 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    database::{
+        header::{PageSize, PageSizeError},
+        sidecar_path,
+    },
+    util::read_u32_at,
+};
+use thiserror::Error;
+
+/// The first eight bytes of every journal header.
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+/// Length of the fields of a journal header. The header is padded out to a whole sector.
+const JOURNAL_HEADER_SIZE: usize = 28;
+/// A record count meaning every record up to the end of the file, written when the journal
+/// isn't synced and so never gets its real count filled in.
+const RECORDS_TO_END: u32 = 0xffff_ffff;
+/// The byte offset of the lock-byte page, which is never journaled.
+const PENDING_BYTE: u32 = 0x4000_0000;
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("Encountered an IO error reading the journal: {0}")]
+    Io(io::Error),
+    #[error("Journal headers are {JOURNAL_HEADER_SIZE} bytes, this journal is {0} bytes")]
+    TooShort(usize),
+    #[error("Journal magic number should be {JOURNAL_MAGIC:02x?}, was {0:02x?}")]
+    Magic([u8; 8]),
+    #[error("Journal header has an invalid page size: {0}")]
+    PageSize(PageSizeError),
+    #[error("Journal sector size should be a power of two from 32 to 65536, was {0}")]
+    SectorSize(u32),
+}
+
+/// Why rolling back stops before the end of the journal. Every record from there on is ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RecordError {
+    #[error("The record at offset {offset} is cut off after {len} of its {record_size} bytes")]
+    Truncated {
+        offset: usize,
+        len: usize,
+        record_size: usize,
+    },
+    #[error("The record at offset {offset} is for page {page_number}, which is never journaled")]
+    PageNumber { offset: usize, page_number: u32 },
+    #[error(
+        "The record at offset {offset} for page {page_number} has checksum {found:08x}, but its contents give {expected:08x}"
+    )]
+    Checksum {
+        offset: usize,
+        page_number: u32,
+        expected: u32,
+        found: u32,
+    },
+}
+
+/// A rollback journal header. A transaction writes one at the start of the journal, and another
+/// each time it syncs the journal before writing more of it.
+// https://www.sqlite.org/fileformat.html#the_rollback_journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalHeader {
+    /// Where the header starts in the journal.
+    pub offset: usize,
+    /// The number of records after this header. Zero until the journal is synced, which happens
+    /// before the database file is written, so a hot journal with no records has nothing to
+    /// restore.
+    pub record_count: u32,
+    /// Random value added to every record's checksum, so records left over from an earlier
+    /// transaction don't pass as this one's.
+    pub nonce: u32,
+    /// Size of the database in pages before the transaction started. Rolling back truncates the
+    /// database file to this size.
+    pub initial_size: u32,
+    /// Headers are padded to this many bytes, and each starts on a multiple of it.
+    pub sector_size: u32,
+    pub page_size: PageSize,
+}
+
+impl TryFrom<&[u8]> for JournalHeader {
+    type Error = JournalError;
+
+    /// Parses a header from the start of `value`. Its offset is left as zero.
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let bytes = value
+            .get(..JOURNAL_HEADER_SIZE)
+            .ok_or(JournalError::TooShort(value.len()))?;
+        let magic: [u8; 8] = bytes[..8].try_into().expect("the header is long enough");
+        if magic != JOURNAL_MAGIC {
+            return Err(JournalError::Magic(magic));
+        }
+        let word = |offset: usize| read_u32_at(bytes, offset, true);
+        let sector_size = word(20);
+        if !(32..=65536).contains(&sector_size) || !sector_size.is_power_of_two() {
+            return Err(JournalError::SectorSize(sector_size));
+        }
+        Ok(Self {
+            offset: 0,
+            record_count: word(8),
+            nonce: word(12),
+            initial_size: word(16),
+            sector_size,
+            page_size: PageSize::from_bytes(word(24)).map_err(JournalError::PageSize)?,
+        })
+    }
+}
+
+/// A page as it was before the transaction started, followed by a checksum of some of its bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalRecord {
+    /// Where the record starts in the journal.
+    pub offset: usize,
+    pub page_number: u32,
+    pub checksum: u32,
+}
+
+/// The records after one journal header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalSegment {
+    pub header: JournalHeader,
+    pub records: Vec<JournalRecord>,
+}
+
+/// A rollback journal. Before a transaction in a legacy (non-WAL) database changes a page, it
+/// copies the original page into the journal. Rolling back copies them back.
+#[derive(Debug)]
+pub struct Journal {
+    /// Each header and its valid records, up to the first invalid record or header.
+    pub segments: Vec<JournalSegment>,
+    /// Why the records end before the header said they would, or `None` if every record is
+    /// valid.
+    pub end: Option<RecordError>,
+    /// The super-journal of a transaction that spanned several attached databases, whose
+    /// journals all name it.
+    pub super_journal: Option<String>,
+    bytes: Vec<u8>,
+}
+
+impl Journal {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        Self::from_bytes(fs::read(path).map_err(JournalError::Io)?)
+    }
+
+    /// Parses every header and record the way rollback reads them: records are read until the
+    /// header's count runs out or one fails its checksum, and the next header starts at the
+    /// next sector boundary. The first header must be valid, later ones just end the journal.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, JournalError> {
+        let first = JournalHeader::try_from(bytes.as_slice())?;
+        let sector_size = first.sector_size as usize;
+        let page_size = first.page_size.get() as usize;
+        let record_size = page_size + 8;
+        let lock_page = PENDING_BYTE / first.page_size.get() + 1;
+        let mut segments = Vec::new();
+        let mut end = None;
+        let mut header = Some(first);
+        let mut offset = 0;
+        while let Some(mut segment_header) = header.take() {
+            segment_header.offset = offset;
+            offset += sector_size;
+            let record_count = match segment_header.record_count {
+                RECORDS_TO_END => bytes.len().saturating_sub(offset) / record_size,
+                count => count as usize,
+            };
+            let mut records = Vec::new();
+            for _ in 0..record_count {
+                let Some(record) = bytes.get(offset..offset + record_size) else {
+                    end = Some(RecordError::Truncated {
+                        offset,
+                        len: bytes.len().saturating_sub(offset),
+                        record_size,
+                    });
+                    break;
+                };
+                let page_number = read_u32_at(record, 0, true);
+                if page_number == 0 || page_number == lock_page {
+                    end = Some(RecordError::PageNumber {
+                        offset,
+                        page_number,
+                    });
+                    break;
+                }
+                let checksum = read_u32_at(record, 4 + page_size, true);
+                let expected = record_checksum(&record[4..4 + page_size], segment_header.nonce);
+                if checksum != expected {
+                    end = Some(RecordError::Checksum {
+                        offset,
+                        page_number,
+                        expected,
+                        found: checksum,
+                    });
+                    break;
+                }
+                records.push(JournalRecord {
+                    offset,
+                    page_number,
+                    checksum,
+                });
+                offset += record_size;
+            }
+            segments.push(JournalSegment {
+                header: segment_header,
+                records,
+            });
+            if end.is_some() {
+                break;
+            }
+            offset = offset.next_multiple_of(sector_size);
+            header = bytes
+                .get(offset..)
+                .and_then(|rest| JournalHeader::try_from(rest).ok())
+                .filter(|header| offset + header.sector_size as usize <= bytes.len());
+        }
+        let super_journal = super_journal(&bytes, lock_page);
+        Ok(Self {
+            segments,
+            end,
+            super_journal,
+            bytes,
+        })
+    }
+
+    pub fn header(&self) -> &JournalHeader {
+        &self.segments[0].header
+    }
+
+    /// Every valid record, in the order rollback writes them.
+    pub fn records(&self) -> impl Iterator<Item = &JournalRecord> {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.records.iter())
+    }
+
+    /// The original contents of the page a record holds.
+    pub fn record_page(&self, record: &JournalRecord) -> Option<&[u8]> {
+        let start = record.offset + 4;
+        self.bytes
+            .get(start..start + self.header().page_size.get() as usize)
+    }
+
+    /// The pages rolling back would write, in journal order. Pages past the initial size aren't
+    /// written, since the database file is truncated to that size anyway.
+    pub fn restored_pages(&self) -> Vec<u32> {
+        let initial_size = self.header().initial_size;
+        let mut pages = Vec::new();
+        for record in self.records() {
+            if record.page_number <= initial_size && !pages.contains(&record.page_number) {
+                pages.push(record.page_number);
+            }
+        }
+        pages
+    }
+}
+
+/// What the next connection to open a database will do with the journal next to it.
+#[derive(Debug)]
+pub enum JournalState {
+    /// There's no journal, or it's empty or has a zeroed header, so the last transaction
+    /// finished.
+    Finished,
+    /// The database file is empty, so there's nothing to roll back and the journal is deleted.
+    EmptyDatabase,
+    /// The journal is part of a multi-database transaction whose super-journal is gone, which
+    /// means the transaction committed. The journal is deleted without rolling back.
+    Committed(Journal),
+    /// The journal is hot: the transaction that wrote it never finished, and the next
+    /// connection will roll it back.
+    Hot(Journal),
+    /// The journal is hot, but its header was torn or never fully written. Rolling back stops
+    /// at the header, so nothing is restored and the journal is deleted.
+    HotWithInvalidHeader(JournalError),
+}
+
+/// Decides whether the journal next to a database is hot, the way SQLite does when it opens the
+/// database. SQLite also checks that no other connection holds a lock on the database, which
+/// can't be seen here, so this assumes the process that wrote the journal is gone.
+pub fn journal_state(database_path: impl AsRef<Path>) -> Result<JournalState, JournalError> {
+    let bytes = match fs::read(sidecar_path(&database_path, "-journal")) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(JournalState::Finished),
+        Err(err) => return Err(JournalError::Io(err)),
+    };
+    if fs::metadata(&database_path)
+        .map_err(JournalError::Io)?
+        .len()
+        == 0
+    {
+        return Ok(JournalState::EmptyDatabase);
+    }
+    if bytes.first().is_none_or(|byte| *byte == 0) {
+        return Ok(JournalState::Finished);
+    }
+    let journal = match Journal::from_bytes(bytes) {
+        Ok(journal) => journal,
+        Err(err) => return Ok(JournalState::HotWithInvalidHeader(err)),
+    };
+    // A relative super-journal path is relative to the working directory, as it is for SQLite
+    if let Some(super_journal) = &journal.super_journal
+        && !Path::new(super_journal).exists()
+    {
+        return Ok(JournalState::Committed(journal));
+    }
+    Ok(JournalState::Hot(journal))
+}
+
+/// A journal record's checksum: the nonce plus every 200th byte of the page, counting back from
+/// 200 bytes before its end.
+fn record_checksum(page: &[u8], nonce: u32) -> u32 {
+    (1..page.len().div_ceil(200))
+        .map(|step| page.len() - 200 * step)
+        .fold(nonce, |checksum, offset| {
+            checksum.wrapping_add(u32::from(page[offset]))
+        })
+}
+
+/// Reads the super-journal name from the end of the journal. It is stored as a record for the
+/// lock-byte page holding the name, then the name's length, a checksum of its bytes and the
+/// journal magic number.
+fn super_journal(bytes: &[u8], lock_page: u32) -> Option<String> {
+    let trailer = bytes.len().checked_sub(16)?;
+    if bytes[trailer + 8..] != JOURNAL_MAGIC {
+        return None;
+    }
+    let len = read_u32_at(bytes, trailer, true) as usize;
+    let start = trailer.checked_sub(len)?;
+    if len == 0 || read_u32_at(bytes, start.checked_sub(4)?, true) != lock_page {
+        return None;
+    }
+    let name = &bytes[start..trailer];
+    // SQLite sums the name as `char`s, which are signed on x86 and unsigned on ARM, so names
+    // with bytes past 0x7f have a different checksum depending on where they were written
+    let signed = name.iter().fold(0u32, |checksum, byte| {
+        checksum.wrapping_add(*byte as i8 as u32)
+    });
+    let unsigned = name.iter().fold(0u32, |checksum, byte| {
+        checksum.wrapping_add(u32::from(*byte))
+    });
+    let checksum = read_u32_at(bytes, trailer + 4, true);
+    if checksum != signed && checksum != unsigned {
+        return None;
+    }
+    let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+    Some(String::from_utf8_lossy(name).into_owned()).filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Journal, JournalError, JournalState, RecordError, journal_state};
+    use crate::database::sidecar_path;
+    use crate::database::test_support::journal_bytes;
+
+    const NONCE: u32 = 0xdead_beef;
+
+    fn page(fill: u8) -> Vec<u8> {
+        (0..512)
+            .map(|offset| fill.wrapping_add(offset as u8))
+            .collect()
+    }
+
+    /// Pages 1 and 3 of a 3 page database, page 5 which the transaction added, then page 1 again.
+    fn records() -> Vec<(u32, Vec<u8>)> {
+        vec![(1, page(1)), (3, page(3)), (5, page(5)), (1, page(9))]
+    }
+
+    #[test]
+    fn parses_the_header_and_records() {
+        let journal = Journal::from_bytes(journal_bytes(512, 512, NONCE, 3, &records())).unwrap();
+        let header = journal.header();
+        assert_eq!(header.record_count, 4);
+        assert_eq!(header.nonce, NONCE);
+        assert_eq!(header.initial_size, 3);
+        assert_eq!(header.sector_size, 512);
+        assert_eq!(header.page_size.get(), 512);
+        assert_eq!(journal.end, None);
+        assert_eq!(journal.super_journal, None);
+
+        let records: Vec<_> = journal.records().collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].offset, 512 + 520);
+        assert_eq!(records[1].page_number, 3);
+        assert_eq!(journal.record_page(records[1]), Some(page(3).as_slice()));
+        assert_eq!(journal.restored_pages(), vec![1, 3]);
+    }
+
+    #[test]
+    fn stops_at_the_first_invalid_record() {
+        let mut bytes = journal_bytes(512, 512, NONCE, 3, &records());
+        bytes[512 + 520 + 4 + 312] ^= 1;
+        let journal = Journal::from_bytes(bytes).unwrap();
+        assert_eq!(journal.records().count(), 1);
+        assert!(matches!(
+            journal.end,
+            Some(RecordError::Checksum {
+                offset: 1032,
+                page_number: 3,
+                ..
+            })
+        ));
+
+        // An unsynced journal counts records up to the end of the file
+        let mut bytes = journal_bytes(512, 512, NONCE, 3, &records());
+        bytes[8..12].fill(0xff);
+        bytes.truncate(bytes.len() - 10);
+        let journal = Journal::from_bytes(bytes).unwrap();
+        assert_eq!(journal.records().count(), 3);
+        assert_eq!(journal.end, None);
+
+        // A second header after the records of the first
+        let mut bytes = journal_bytes(512, 512, NONCE, 3, &records()[..1]);
+        bytes.resize(1536, 0);
+        bytes.extend(journal_bytes(512, 512, NONCE, 3, &records()[1..]));
+        let journal = Journal::from_bytes(bytes).unwrap();
+        assert_eq!(journal.segments.len(), 2);
+        assert_eq!(journal.segments[1].header.offset, 1536);
+        assert_eq!(journal.records().count(), 4);
+
+        let mut bytes = journal_bytes(512, 512, NONCE, 3, &records());
+        bytes[0] = 0;
+        assert!(matches!(
+            Journal::from_bytes(bytes),
+            Err(JournalError::Magic(_))
+        ));
+        let mut bytes = journal_bytes(512, 512, NONCE, 3, &records());
+        bytes[20..24].copy_from_slice(&100u32.to_be_bytes());
+        assert!(matches!(
+            Journal::from_bytes(bytes),
+            Err(JournalError::SectorSize(100))
+        ));
+    }
+
+    #[test]
+    fn decides_whether_the_journal_is_hot() {
+        let path = std::env::temp_dir().join(format!("journal-{}.db", std::process::id()));
+        let journal = journal_bytes(512, 512, NONCE, 3, &records());
+        let state = |database: &[u8], journal: Option<&[u8]>| {
+            fs::write(&path, database).unwrap();
+            match journal {
+                Some(journal) => fs::write(sidecar_path(&path, "-journal"), journal).unwrap(),
+                None => {
+                    let _ = fs::remove_file(sidecar_path(&path, "-journal"));
+                }
+            }
+            journal_state(&path).unwrap()
+        };
+
+        assert!(matches!(state(&[1; 1536], None), JournalState::Finished));
+        // Persistent journals are finished by zeroing the header
+        let mut zeroed = journal.clone();
+        zeroed[..28].fill(0);
+        assert!(matches!(
+            state(&[1; 1536], Some(&zeroed)),
+            JournalState::Finished
+        ));
+        assert!(matches!(
+            state(&[], Some(&journal)),
+            JournalState::EmptyDatabase
+        ));
+        assert!(matches!(
+            state(&[], Some(&zeroed)),
+            JournalState::EmptyDatabase
+        ));
+        // A header cut off while it was being written
+        assert!(matches!(
+            state(&[1; 1536], Some(&journal[..20])),
+            JournalState::HotWithInvalidHeader(JournalError::TooShort(20))
+        ));
+        let JournalState::Hot(hot) = state(&[1; 1536], Some(&journal)) else {
+            panic!("the journal should be hot");
+        };
+        assert_eq!(hot.restored_pages(), vec![1, 3]);
+
+        // A super-journal record: the lock-byte page number, the name, its length, checksum
+        // and the magic number. The checksum adds the name's bytes as signed chars, like SQLite
+        // on x86
+        let name = "/nonexistent/süper-journal".as_bytes();
+        let mut with_super = journal.clone();
+        with_super.resize(with_super.len().next_multiple_of(512), 0);
+        with_super.extend((0x4000_0000u32 / 512 + 1).to_be_bytes());
+        with_super.extend(name);
+        with_super.extend((name.len() as u32).to_be_bytes());
+        with_super.extend(
+            name.iter()
+                .fold(0u32, |sum, byte| sum.wrapping_add(*byte as i8 as u32))
+                .to_be_bytes(),
+        );
+        with_super.extend(&journal[..8]);
+        let committed = state(&[1; 1536], Some(&with_super));
+        fs::remove_file(sidecar_path(&path, "-journal")).unwrap();
+        fs::remove_file(&path).unwrap();
+        let JournalState::Committed(committed) = committed else {
+            panic!("the journal should belong to a committed transaction");
+        };
+        assert_eq!(
+            committed.super_journal.as_deref(),
+            Some("/nonexistent/süper-journal")
+        );
+        assert_eq!(committed.records().count(), 4);
+    }
+}
@@ -1,32 +1,398 @@
+use std::{
+    fs::File,
+    io,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
 use thiserror::Error;
 
 use crate::{
-    database::header::{DatabaseHeader, DatabaseHeaderError},
-    database::page_collection::PageCollection,
+    database::freelist::{Freelist, FreelistError},
+    database::header::{DatabaseHeader, DatabaseHeaderError, HeaderValidationError, PageSize},
+    database::integrity::IntegrityProblem,
+    database::overflow::OverflowError,
+    database::page::cell::{PayloadLimits, PayloadLimitsError},
+    database::pager::{PageProblem, Pager},
+    database::ptrmap::{PointerMap, PointerMapError},
+    database::schema::{Schema, SchemaError},
+    database::structure::PageKinds,
+    database::wal::{Wal, WalError, WalSnapshot},
 };
 
+pub mod checkpoint;
+pub mod collation;
+pub mod cursor;
+pub mod freelist;
 pub mod header;
+pub mod integrity;
+pub mod journal;
+pub mod overflow;
 pub mod page;
-pub mod page_collection;
+pub mod pager;
+pub mod ptrmap;
+pub mod record;
+pub mod schema;
+pub mod structure;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod wal;
+pub mod wal_index;
 
 #[derive(Debug)]
 pub struct Database {
     pub header: DatabaseHeader,
-    pages: PageCollection,
+    pager: Pager,
+    limits: PayloadLimits,
 }
 
-#[derive(Error, Debug, PartialEq, Eq)]
+#[derive(Error, Debug)]
 pub enum DatabaseReadError {
     #[error("Database file has a malformed header: {0:?}")]
     InvalidHeader(DatabaseHeaderError),
+    #[error("Encountered an IO error opening the database: {0}")]
+    Io(io::Error),
+    #[error("Database files are at least 100 bytes long, this one is {0}")]
+    TooShort(usize),
+    #[error("Encountered an error reading the WAL: {0}")]
+    Wal(WalError),
+    #[error("The WAL has {wal} byte pages, but the database has {database} byte pages")]
+    WalPageSize { database: PageSize, wal: PageSize },
+    #[error("The database header gives unusable payload limits: {0}")]
+    PayloadLimits(PayloadLimitsError),
 }
 
 impl Database {
     pub fn from_bytes(db_file: Vec<u8>) -> Result<Self, DatabaseReadError> {
-        let header_bytes = db_file[..100].to_vec();
-        let header =
-            DatabaseHeader::try_from(header_bytes).map_err(DatabaseReadError::InvalidHeader)?;
-        let pages = PageCollection::from_bytes(db_file, &header);
-        Ok(Database { header, pages })
+        Self::from_bytes_with_wal(db_file, None)
+    }
+
+    /// A database file that has already been read into memory, read through a WAL snapshot if
+    /// one is given.
+    pub fn from_bytes_with_wal(
+        db_file: Vec<u8>,
+        wal: Option<WalSnapshot>,
+    ) -> Result<Self, DatabaseReadError> {
+        let header = read_header(&db_file, wal.as_ref())?;
+        let pager = Pager::from_bytes(db_file, &header);
+        Self::new(header, pager, wal)
+    }
+
+    /// Opens a database file, reading only its header up front. Pages are read as they are
+    /// needed.
+    ///
+    /// Like a SQLite reader, if there is a `-wal` file next to the database it is read as of the
+    /// last commit in the WAL. A WAL without a valid header is ignored, since SQLite treats it
+    /// as empty.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DatabaseReadError> {
+        let wal = match Wal::open(sidecar_path(&path, "-wal")) {
+            Ok(wal) => WalSnapshot::latest(wal),
+            Err(WalError::Io(err)) if err.kind() != io::ErrorKind::NotFound => {
+                return Err(DatabaseReadError::Wal(WalError::Io(err)));
+            }
+            Err(_) => None,
+        };
+        Self::open_with_wal(path, wal)
+    }
+
+    /// Opens a database file as it was when the WAL frame `commit_frame` committed.
+    pub fn open_as_of(
+        path: impl AsRef<Path>,
+        commit_frame: u32,
+    ) -> Result<Self, DatabaseReadError> {
+        let wal = Wal::open(sidecar_path(&path, "-wal")).map_err(DatabaseReadError::Wal)?;
+        let snapshot = WalSnapshot::new(wal, commit_frame).map_err(DatabaseReadError::Wal)?;
+        Self::open_with_wal(path, Some(snapshot))
+    }
+
+    fn open_with_wal(
+        path: impl AsRef<Path>,
+        wal: Option<WalSnapshot>,
+    ) -> Result<Self, DatabaseReadError> {
+        let mut file = File::open(path).map_err(DatabaseReadError::Io)?;
+        let mut header_bytes = Vec::with_capacity(100);
+        (&mut file)
+            .take(100)
+            .read_to_end(&mut header_bytes)
+            .map_err(DatabaseReadError::Io)?;
+        let header = read_header(&header_bytes, wal.as_ref())?;
+        let pager = Pager::open(file, &header).map_err(DatabaseReadError::Io)?;
+        Self::new(header, pager, wal)
+    }
+
+    fn new(
+        header: DatabaseHeader,
+        pager: Pager,
+        wal: Option<WalSnapshot>,
+    ) -> Result<Self, DatabaseReadError> {
+        let limits = PayloadLimits::try_from(&header).map_err(DatabaseReadError::PayloadLimits)?;
+        let Some(wal) = wal else {
+            return Ok(Database {
+                header,
+                pager,
+                limits,
+            });
+        };
+        let wal_page_size = wal.wal().header.page_size;
+        if wal_page_size != header.page_size {
+            return Err(DatabaseReadError::WalPageSize {
+                database: header.page_size,
+                wal: wal_page_size,
+            });
+        }
+        let pager = pager.with_wal(wal);
+        Ok(Database {
+            header,
+            pager,
+            limits,
+        })
+    }
+
+    /// The WAL snapshot the database is read through, if it is read through one.
+    pub fn wal(&self) -> Option<&WalSnapshot> {
+        self.pager.wal()
+    }
+
+    pub fn pager(&self) -> &Pager {
+        &self.pager
+    }
+
+    /// Reads the `sqlite_schema` table rooted on page 1.
+    pub fn schema(&self) -> Result<Schema, SchemaError> {
+        Schema::read(self)
+    }
+
+    pub fn payload_limits(&self) -> PayloadLimits {
+        self.limits
+    }
+
+    /// Walks the freelist and checks its size against the database header.
+    pub fn freelist(&self) -> Result<Freelist, FreelistError> {
+        let freelist = Freelist::read(
+            &self.pager,
+            self.header.usable_size(),
+            self.header.first_freelist,
+        )?;
+        freelist.validate(self.header.num_freelist)?;
+        Ok(freelist)
+    }
+
+    /// Reads the pointer map, which only auto-vacuum and incremental-vacuum databases have.
+    pub fn pointer_map(&self) -> Result<Option<PointerMap>, PointerMapError> {
+        if self.header.largest_root_page == 0 {
+            return Ok(None);
+        }
+        PointerMap::read(self).map(Some)
+    }
+
+    /// Checks the header against the file format's rules and the length of the file.
+    pub fn validate_header(&self) -> Vec<HeaderValidationError> {
+        self.header.validate(self.pager.file_len())
+    }
+
+    /// What every page in the file is used for, found by walking the b-trees, freelist and
+    /// pointer map.
+    pub fn page_kinds(&self) -> PageKinds {
+        PageKinds::read(self)
+    }
+
+    /// Every problem found loading the pages of the database and decoding their cells. Pointer
+    /// map pages are skipped, since their first entry can look like a b-tree page type.
+    pub fn page_problems(&self) -> Vec<PageProblem> {
+        let usable_size = self.header.usable_size();
+        let lock_byte_page = self.pager.lock_byte_page();
+        let auto_vacuum = self.header.largest_root_page != 0;
+        let page_numbers = (1..=self.pager.len() as u32).filter(|page_number| {
+            !auto_vacuum
+                || *page_number < 2
+                || ptrmap::pointer_map_page(usable_size, lock_byte_page, *page_number)
+                    != *page_number
+        });
+        self.pager.problems(self.payload_limits(), page_numbers)
+    }
+
+    /// Checks the structure of the whole database, like `PRAGMA integrity_check`. An empty list
+    /// means no problems were found.
+    pub fn integrity_check(&self) -> Vec<IntegrityProblem> {
+        integrity::check(self)
+    }
+
+    /// Reassembles a cell's full payload, following its overflow chain if it has one.
+    pub fn read_payload(
+        &self,
+        local_payload: &[u8],
+        payload_size: u64,
+        first_overflow_page: Option<u32>,
+    ) -> Result<Vec<u8>, OverflowError> {
+        overflow::read_payload(
+            &self.pager,
+            self.header.usable_size(),
+            local_payload,
+            payload_size,
+            first_overflow_page,
+        )
+    }
+}
+
+/// Parses the database header from page 1 in the WAL if it has a newer version of it, and from
+/// the start of the database file otherwise.
+fn read_header(
+    file_header: &[u8],
+    wal: Option<&WalSnapshot>,
+) -> Result<DatabaseHeader, DatabaseReadError> {
+    let bytes = wal.and_then(|wal| wal.page(1)).unwrap_or(file_header);
+    let header_bytes = bytes
+        .get(..100)
+        .ok_or(DatabaseReadError::TooShort(bytes.len()))?;
+    DatabaseHeader::try_from(header_bytes.to_vec()).map_err(DatabaseReadError::InvalidHeader)
+}
+
+/// A file SQLite keeps next to the database, named by appending `suffix` to its path: `-wal`
+/// for the WAL, `-shm` for the wal-index and `-journal` for the rollback journal.
+pub fn sidecar_path(database_path: impl AsRef<Path>, suffix: &str) -> PathBuf {
+    let mut path = database_path.as_ref().as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Database, DatabaseReadError, sidecar_path};
+    use crate::database::{
+        cursor::table::TableCursor,
+        header::DatabaseHeaderError,
+        test_support::{
+            btree_page, database_bytes, header_bytes, rows_page, schema_page, wal_bytes,
+        },
+        wal::{Wal, WalError, WalSnapshot},
+    };
+
+    #[test]
+    fn short_files_are_rejected() {
+        assert!(matches!(
+            Database::from_bytes(b"SQLite format 3\0".to_vec()),
+            Err(DatabaseReadError::TooShort(16))
+        ));
+        let path = std::env::temp_dir().join(format!("short-test-{}.db", std::process::id()));
+        std::fs::write(&path, [0; 99]).unwrap();
+        let result = Database::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(DatabaseReadError::TooShort(99))));
+    }
+
+    #[test]
+    fn sidecar_files_sit_next_to_the_database() {
+        assert_eq!(
+            sidecar_path("/data/app.db", "-wal"),
+            std::path::PathBuf::from("/data/app.db-wal")
+        );
+        assert_eq!(
+            sidecar_path("/data/app.db", "-journal"),
+            std::path::PathBuf::from("/data/app.db-journal")
+        );
+    }
+
+    #[test]
+    fn unusable_payload_fractions_are_rejected() {
+        let mut bytes = database_bytes(512, vec![btree_page(512, 100, 13, None, &[])]);
+        bytes[21] = 0;
+        assert!(matches!(
+            Database::from_bytes(bytes),
+            Err(DatabaseReadError::InvalidHeader(
+                DatabaseHeaderError::PayloadFraction { offset: 21, .. }
+            ))
+        ));
+    }
+
+    /// Page 1 of a WAL mode database of `page_count` pages.
+    fn wal_schema_page(tables: &[(&str, i64)], page_count: u32) -> Vec<u8> {
+        let mut page = schema_page(tables);
+        page[..100].copy_from_slice(&header_bytes(512, page_count));
+        page[18] = 2;
+        page[19] = 2;
+        page
+    }
+
+    /// Table `t` on page 2 has row 1 in the database file. The WAL commits row 2 in frame 1,
+    /// then table `u` on a new page 3 in frame 3, then adds row 3 without committing.
+    fn database_and_wal() -> (Vec<u8>, Vec<u8>) {
+        let mut database = wal_schema_page(&[("t", 2)], 2);
+        database.extend(rows_page(&[1]));
+        let wal = wal_bytes(
+            512,
+            [7, 8],
+            &[
+                (2, 2, rows_page(&[1, 2])),
+                (1, 0, wal_schema_page(&[("t", 2), ("u", 3)], 3)),
+                (3, 3, rows_page(&[])),
+                (2, 0, rows_page(&[1, 2, 3])),
+            ],
+        );
+        (database, wal)
+    }
+
+    fn rowids(database: &Database) -> Vec<i64> {
+        TableCursor::new(database, 2)
+            .map(|row| row.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn reads_committed_pages_from_the_wal() {
+        let (database, wal) = database_and_wal();
+        let without_wal = Database::from_bytes(database.clone()).unwrap();
+        assert_eq!(rowids(&without_wal), vec![1]);
+        assert!(without_wal.wal().is_none());
+
+        let snapshot =
+            |commit_frame| WalSnapshot::new(Wal::from_bytes(wal.clone()).unwrap(), commit_frame);
+        let latest = Database::from_bytes_with_wal(
+            database.clone(),
+            WalSnapshot::latest(Wal::from_bytes(wal.clone()).unwrap()),
+        )
+        .unwrap();
+        assert_eq!(latest.wal().unwrap().commit_frame(), 3);
+        assert_eq!(latest.header.database_size_in_pages, 3);
+        assert_eq!(latest.pager().len(), 3);
+        assert_eq!(rowids(&latest), vec![1, 2]);
+        assert_eq!(latest.schema().unwrap().root_page("u"), Some(3));
+        assert!(latest.validate_header().is_empty());
+        assert!(latest.integrity_check().is_empty());
+
+        // A new database in WAL mode may only have a complete header in the WAL
+        let mut unset_encoding = database.clone();
+        unset_encoding[56..60].fill(0);
+        assert!(Database::from_bytes(unset_encoding.clone()).is_err());
+        assert!(Database::from_bytes_with_wal(unset_encoding, snapshot(3).ok()).is_ok());
+
+        let first_commit =
+            Database::from_bytes_with_wal(database, Some(snapshot(1).unwrap())).unwrap();
+        assert_eq!(first_commit.pager().len(), 2);
+        assert_eq!(rowids(&first_commit), vec![1, 2]);
+        assert_eq!(first_commit.schema().unwrap().root_page("u"), None);
+
+        assert!(matches!(snapshot(2), Err(WalError::NotACommit(2))));
+        assert!(matches!(snapshot(4), Err(WalError::NotACommit(4))));
+    }
+
+    #[test]
+    fn opens_the_wal_next_to_the_database() {
+        let (database, wal) = database_and_wal();
+        let path = std::env::temp_dir().join(format!("wal-test-{}.db", std::process::id()));
+        std::fs::write(&path, &database).unwrap();
+        std::fs::write(sidecar_path(&path, "-wal"), &wal).unwrap();
+        let latest = Database::open(&path).map(|database| rowids(&database));
+        let as_of = Database::open_as_of(&path, 1).map(|database| database.pager().len());
+        // SQLite treats a WAL with a damaged header as empty
+        std::fs::write(sidecar_path(&path, "-wal"), &wal[1..]).unwrap();
+        let damaged = Database::open(&path).map(|database| rowids(&database));
+        std::fs::remove_file(sidecar_path(&path, "-wal")).unwrap();
+        let missing = Database::open(&path).map(|database| rowids(&database));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(latest.unwrap(), vec![1, 2]);
+        assert_eq!(as_of.unwrap(), 2);
+        assert_eq!(damaged.unwrap(), vec![1]);
+        assert_eq!(missing.unwrap(), vec![1]);
     }
 }
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+
+use crate::{
+    database::pager::{Pager, PagerError},
+    util::{DecodeError, get_u32_from_bytes},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum OverflowError {
+    #[error("Overflow page {0} is outside of the database")]
+    PageOutOfRange(u32),
+    #[error("Encountered an error reading an overflow page: {0}")]
+    Pager(PagerError),
+    #[error("Overflow chain visits page {0} more than once")]
+    Cycle(u32),
+    #[error("Overflow chain ended after {read} of {expected} payload bytes")]
+    ChainTooShort { read: usize, expected: usize },
+    #[error("Encountered an error decoding an overflow page: {0}")]
+    Decode(DecodeError),
+}
+
+impl From<DecodeError> for OverflowError {
+    fn from(value: DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+/// Reassembles a full cell payload from the part stored on the b-tree page (`local_payload`) and
+/// the chain of overflow pages starting at `first_overflow_page`.
+///
+/// Each overflow page starts with the 4 byte page number of the next page in the chain (zero on
+/// the last page), followed by up to `usable_size - 4` bytes of payload.
+pub fn read_payload(
+    pager: &Pager,
+    usable_size: usize,
+    local_payload: &[u8],
+    payload_size: u64,
+    first_overflow_page: Option<u32>,
+) -> Result<Vec<u8>, OverflowError> {
+    let expected = payload_size as usize;
+    let mut payload = local_payload.to_vec();
+    let mut visited = HashSet::new();
+    let mut next_page = first_overflow_page.unwrap_or(0);
+    while payload.len() < expected {
+        if next_page == 0 {
+            return Err(OverflowError::ChainTooShort {
+                read: payload.len(),
+                expected,
+            });
+        }
+        if !visited.insert(next_page) {
+            return Err(OverflowError::Cycle(next_page));
+        }
+        let page = pager.page_bytes(next_page).map_err(|err| match err {
+            PagerError::PageOutOfRange(page) => OverflowError::PageOutOfRange(page),
+            err => OverflowError::Pager(err),
+        })?;
+        if page.len() < usable_size {
+            return Err(OverflowError::PageOutOfRange(next_page));
+        }
+        next_page = get_u32_from_bytes(&page[0..4], "next_overflow_page")?;
+        let remaining = expected - payload.len();
+        let content = &page[4..usable_size];
+        payload.extend_from_slice(&content[..remaining.min(content.len())]);
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OverflowError, read_payload};
+    use crate::database::{
+        Database,
+        test_support::{btree_page, database_bytes},
+    };
+
+    fn overflow_page(next_page: u32, fill: u8) -> Vec<u8> {
+        let mut page = vec![fill; 512];
+        page[0..4].copy_from_slice(&next_page.to_be_bytes());
+        page
+    }
+
+    #[test]
+    fn follows_overflow_chain() {
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                overflow_page(3, 0x22),
+                overflow_page(0, 0x33),
+            ],
+        );
+        let database = Database::from_bytes(bytes).unwrap();
+        let payload = read_payload(database.pager(), 512, &[0x11; 39], 1000, Some(2)).unwrap();
+        let mut expected = vec![0x11; 39];
+        expected.extend(vec![0x22; 508]);
+        expected.extend(vec![0x33; 1000 - 39 - 508]);
+        assert_eq!(payload, expected);
+    }
+
+    #[test]
+    fn broken_overflow_chains() {
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                overflow_page(3, 0x22),
+                overflow_page(2, 0x33),
+                overflow_page(0, 0x44),
+            ],
+        );
+        let database = Database::from_bytes(bytes).unwrap();
+        assert!(matches!(
+            read_payload(database.pager(), 512, &[], 2000, Some(2)),
+            Err(OverflowError::Cycle(2))
+        ));
+        assert!(matches!(
+            read_payload(database.pager(), 512, &[], 600, Some(9)),
+            Err(OverflowError::PageOutOfRange(9))
+        ));
+        assert!(matches!(
+            read_payload(database.pager(), 512, &[], 600, Some(4)),
+            Err(OverflowError::ChainTooShort {
+                read: 508,
+                expected: 600,
+            })
+        ));
+    }
+}
@@ -0,0 +1,600 @@
+use thiserror::Error;
+
+use super::{
+    cell::{Cell, MIN_CELL_SIZE, PayloadLimits},
+    header::PageType,
+};
+
+/// SQLite defragments a page rather than let its fragmented bytes pass this.
+const MAX_FRAGMENTED_BYTES: usize = 60;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PageBuilderError {
+    #[error("The cell needs {needed} bytes, but the page only has {available} free")]
+    PageFull { needed: usize, available: usize },
+    #[error("Cell index {index} is past the end of the {len} cells on the page")]
+    IndexOutOfBounds { index: usize, len: usize },
+    #[error("A {cell:?} cell can't go on a {page:?} page")]
+    WrongCellType { page: PageType, cell: PageType },
+    #[error("The page already has a cell with rowid {0}")]
+    DuplicateRowid(i64),
+    #[error("The page type can only be changed while the page is empty")]
+    NotEmpty,
+    #[error(
+        "A cell with {payload_size} bytes of payload keeps {expected} of them on the page, not {local}"
+    )]
+    LocalPayload {
+        payload_size: u64,
+        local: usize,
+        expected: usize,
+    },
+    #[error(
+        "A cell keeping {local} of its {payload_size} payload bytes on the page needs an overflow page"
+    )]
+    MissingOverflowPage { payload_size: u64, local: usize },
+    #[error(
+        "A cell whose {payload_size} payload bytes all fit on the page can't have an overflow page"
+    )]
+    UnexpectedOverflowPage { payload_size: u64 },
+}
+
+/// A cell's place in the cell content area.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: usize,
+    size: usize,
+    /// Table cells are kept in rowid order, so remember the rowid to search by.
+    rowid: Option<i64>,
+}
+
+/// Builds a b-tree page in memory, laying out cells the way SQLite does: cell content grows
+/// down from the end of the usable space, freed cells become freeblocks, and leftover scraps too
+/// small for a freeblock are counted as fragmented bytes.
+#[derive(Debug, Clone)]
+pub struct PageBuilder {
+    page_type: PageType,
+    /// Decides how much of each cell's payload belongs on the page.
+    limits: PayloadLimits,
+    /// Where the b-tree page header starts: 100 on page 1 and 0 everywhere else.
+    header_offset: usize,
+    right_most_pointer: Option<u32>,
+    /// The whole page. Only the cell content area is kept up to date; the header, cell pointer
+    /// array and freeblock headers are written by `build`.
+    bytes: Vec<u8>,
+    /// The cells in cell pointer array order.
+    cells: Vec<Slot>,
+    /// Offset and size of each freeblock, in offset order.
+    freeblocks: Vec<(usize, usize)>,
+    content_start: usize,
+    fragmented_bytes: usize,
+}
+
+impl PageBuilder {
+    /// An empty page. On page 1, pass a `header_offset` of 100 and write the database header
+    /// over the first 100 bytes of the built page.
+    pub fn new(
+        page_type: PageType,
+        page_size: usize,
+        limits: PayloadLimits,
+        header_offset: usize,
+    ) -> Self {
+        let right_most_pointer = match page_type {
+            PageType::InteriorIndex | PageType::InteriorTable => Some(0),
+            PageType::LeafIndex | PageType::LeafTable => None,
+        };
+        Self {
+            page_type,
+            limits,
+            header_offset,
+            right_most_pointer,
+            bytes: vec![0; page_size],
+            cells: Vec::new(),
+            freeblocks: Vec::new(),
+            content_start: limits.usable_size,
+            fragmented_bytes: 0,
+        }
+    }
+
+    pub fn page_type(&self) -> &PageType {
+        &self.page_type
+    }
+
+    /// Changes the page type. The header is a different length on leaf and interior pages, so
+    /// this is only allowed before any cells are added.
+    pub fn set_page_type(&mut self, page_type: PageType) -> Result<(), PageBuilderError> {
+        if !self.cells.is_empty() {
+            return Err(PageBuilderError::NotEmpty);
+        }
+        *self = Self::new(page_type, self.bytes.len(), self.limits, self.header_offset);
+        Ok(())
+    }
+
+    /// Sets the right-most pointer of an interior page. Ignored on leaf pages.
+    pub fn set_right_most_pointer(&mut self, page_number: u32) {
+        if let Some(pointer) = &mut self.right_most_pointer {
+            *pointer = page_number;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn content_start(&self) -> usize {
+        self.content_start
+    }
+
+    pub fn fragmented_bytes(&self) -> usize {
+        self.fragmented_bytes
+    }
+
+    /// Offset and size of each freeblock, in offset order.
+    pub fn freeblocks(&self) -> &[(usize, usize)] {
+        &self.freeblocks
+    }
+
+    /// The offset of each cell, in cell pointer array order.
+    pub fn cell_offsets(&self) -> Vec<usize> {
+        self.cells.iter().map(|slot| slot.offset).collect()
+    }
+
+    /// Inserts a table cell in rowid order.
+    pub fn insert_by_rowid(&mut self, cell: &Cell) -> Result<usize, PageBuilderError> {
+        let rowid = match cell {
+            Cell::TableLeaf(cell) => cell.rowid,
+            Cell::TableInterior(cell) => cell.rowid,
+            Cell::IndexLeaf(_) | Cell::IndexInterior(_) => {
+                return Err(PageBuilderError::WrongCellType {
+                    page: self.page_type.clone(),
+                    cell: cell.page_type(),
+                });
+            }
+        };
+        let index = self
+            .cells
+            .partition_point(|slot| slot.rowid.is_some_and(|other| other < rowid));
+        if self.cells.get(index).and_then(|slot| slot.rowid) == Some(rowid) {
+            return Err(PageBuilderError::DuplicateRowid(rowid));
+        }
+        self.insert(index, cell)?;
+        Ok(index)
+    }
+
+    /// Inserts a cell at `index` in the cell pointer array, shifting later cells along. Cells
+    /// must be inserted in key order; `insert_by_rowid` does that for table pages.
+    pub fn insert(&mut self, index: usize, cell: &Cell) -> Result<(), PageBuilderError> {
+        if cell.page_type() != self.page_type {
+            return Err(PageBuilderError::WrongCellType {
+                page: self.page_type.clone(),
+                cell: cell.page_type(),
+            });
+        }
+        if index > self.cells.len() {
+            return Err(PageBuilderError::IndexOutOfBounds {
+                index,
+                len: self.cells.len(),
+            });
+        }
+        self.check_payload(cell)?;
+        let bytes = cell.to_bytes();
+        let size = bytes.len().max(MIN_CELL_SIZE);
+        let offset = self.allocate(size)?;
+        self.bytes[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        let rowid = match cell {
+            Cell::TableLeaf(cell) => Some(cell.rowid),
+            Cell::TableInterior(cell) => Some(cell.rowid),
+            Cell::IndexLeaf(_) | Cell::IndexInterior(_) => None,
+        };
+        self.cells.insert(
+            index,
+            Slot {
+                offset,
+                size,
+                rowid,
+            },
+        );
+        Ok(())
+    }
+
+    /// Checks that the cell splits its payload between the page and overflow pages the way
+    /// `Page::cells` will read it back.
+    fn check_payload(&self, cell: &Cell) -> Result<(), PageBuilderError> {
+        let (payload_size, payload, first_overflow_page) = match cell {
+            Cell::TableLeaf(cell) => (cell.payload_size, &cell.payload, cell.first_overflow_page),
+            Cell::IndexLeaf(cell) => (cell.payload_size, &cell.payload, cell.first_overflow_page),
+            Cell::IndexInterior(cell) => {
+                (cell.payload_size, &cell.payload, cell.first_overflow_page)
+            }
+            Cell::TableInterior(_) => return Ok(()),
+        };
+        let local = payload.len();
+        let expected = self
+            .limits
+            .local_payload_size(&self.page_type, payload_size);
+        if local != expected {
+            return Err(PageBuilderError::LocalPayload {
+                payload_size,
+                local,
+                expected,
+            });
+        }
+        match (first_overflow_page, (local as u64) < payload_size) {
+            (None, true) => Err(PageBuilderError::MissingOverflowPage {
+                payload_size,
+                local,
+            }),
+            (Some(_), false) => Err(PageBuilderError::UnexpectedOverflowPage { payload_size }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the cell at `index`, turning its space into a freeblock.
+    pub fn remove(&mut self, index: usize) -> Result<(), PageBuilderError> {
+        if index >= self.cells.len() {
+            return Err(PageBuilderError::IndexOutOfBounds {
+                index,
+                len: self.cells.len(),
+            });
+        }
+        let slot = self.cells.remove(index);
+        self.free(slot.offset, slot.size);
+        Ok(())
+    }
+
+    /// Moves every cell to the end of the page, merging the freeblocks and fragmented bytes into
+    /// the unallocated space between the cell pointer array and the cell content area.
+    pub fn defragment(&mut self) {
+        let mut content = vec![0; self.limits.usable_size];
+        let mut content_start = self.limits.usable_size;
+        for slot in &mut self.cells {
+            content_start -= slot.size;
+            content[content_start..content_start + slot.size]
+                .copy_from_slice(&self.bytes[slot.offset..slot.offset + slot.size]);
+            slot.offset = content_start;
+        }
+        let cell_pointers_end = self.cell_pointers_end();
+        self.bytes[cell_pointers_end..self.limits.usable_size]
+            .copy_from_slice(&content[cell_pointers_end..]);
+        self.content_start = content_start;
+        self.freeblocks.clear();
+        self.fragmented_bytes = 0;
+    }
+
+    /// Emits the page's exact byte image.
+    pub fn build(&self) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+        let header = &mut bytes[self.header_offset..];
+        header[0] = u8::from(&self.page_type);
+        let first_freeblock = self.freeblocks.first().map_or(0, |(offset, _)| *offset);
+        header[1..3].copy_from_slice(&(first_freeblock as u16).to_be_bytes());
+        header[3..5].copy_from_slice(&(self.cells.len() as u16).to_be_bytes());
+        // A content area starting at 65536 doesn't fit in two bytes, so it is stored as 0
+        header[5..7].copy_from_slice(&(self.content_start as u16).to_be_bytes());
+        header[7] = self.fragmented_bytes as u8;
+        if let Some(pointer) = self.right_most_pointer {
+            header[8..12].copy_from_slice(&pointer.to_be_bytes());
+        }
+        let cell_pointers_start = self.header_offset + self.header_len();
+        for (idx, slot) in self.cells.iter().enumerate() {
+            let at = cell_pointers_start + idx * 2;
+            bytes[at..at + 2].copy_from_slice(&(slot.offset as u16).to_be_bytes());
+        }
+        for (idx, (offset, size)) in self.freeblocks.iter().enumerate() {
+            let next = self.freeblocks.get(idx + 1).map_or(0, |(next, _)| *next);
+            bytes[*offset..offset + 2].copy_from_slice(&(next as u16).to_be_bytes());
+            bytes[offset + 2..offset + 4].copy_from_slice(&(*size as u16).to_be_bytes());
+        }
+        bytes
+    }
+
+    fn header_len(&self) -> usize {
+        if self.right_most_pointer.is_some() {
+            12
+        } else {
+            8
+        }
+    }
+
+    fn cell_pointers_end(&self) -> usize {
+        self.header_offset + self.header_len() + 2 * self.cells.len()
+    }
+
+    /// Finds `size` bytes for a new cell and room for its cell pointer, defragmenting if the
+    /// free space is there but too scattered.
+    fn allocate(&mut self, size: usize) -> Result<usize, PageBuilderError> {
+        let free_bytes = self.content_start - self.cell_pointers_end()
+            + self.freeblocks.iter().map(|(_, size)| size).sum::<usize>()
+            + self.fragmented_bytes;
+        if free_bytes < size + 2 {
+            return Err(PageBuilderError::PageFull {
+                needed: size + 2,
+                available: free_bytes,
+            });
+        }
+        if self.content_start - self.cell_pointers_end() >= 2
+            && let Some(offset) = self.allocate_from_freeblock(size)
+        {
+            return Ok(offset);
+        }
+        if self.content_start - self.cell_pointers_end() < size + 2 {
+            self.defragment();
+        }
+        self.content_start -= size;
+        Ok(self.content_start)
+    }
+
+    /// Takes `size` bytes from the first freeblock big enough, like SQLite: from the end of the
+    /// freeblock, or the whole freeblock if what would be left is too small to be one.
+    fn allocate_from_freeblock(&mut self, size: usize) -> Option<usize> {
+        let idx = self.freeblocks.iter().position(|(_, free)| *free >= size)?;
+        let (offset, free) = self.freeblocks[idx];
+        let left_over = free - size;
+        if left_over < 4 {
+            if self.fragmented_bytes + left_over > MAX_FRAGMENTED_BYTES {
+                return None;
+            }
+            self.freeblocks.remove(idx);
+            self.fragmented_bytes += left_over;
+            return Some(offset);
+        }
+        self.freeblocks[idx].1 = left_over;
+        Some(offset + left_over)
+    }
+
+    fn free(&mut self, offset: usize, size: usize) {
+        self.bytes[offset..offset + size].fill(0);
+        let idx = self
+            .freeblocks
+            .partition_point(|(other, _)| *other < offset);
+        self.freeblocks.insert(idx, (offset, size));
+        // Merge with the next freeblock, then the previous one, if they touch
+        if let Some(&(next, next_size)) = self.freeblocks.get(idx + 1)
+            && offset + size == next
+        {
+            self.freeblocks[idx].1 += next_size;
+            self.freeblocks.remove(idx + 1);
+        }
+        let mut idx = idx;
+        if idx > 0 {
+            let (previous, previous_size) = self.freeblocks[idx - 1];
+            if previous + previous_size == offset {
+                self.freeblocks[idx - 1].1 += self.freeblocks[idx].1;
+                self.freeblocks.remove(idx);
+                idx -= 1;
+            }
+        }
+        // A freeblock at the start of the content area just becomes unallocated space
+        if idx == 0 && self.freeblocks[0].0 == self.content_start {
+            self.content_start += self.freeblocks[0].1;
+            self.freeblocks.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageBuilder, PageBuilderError};
+    use crate::database::{
+        page::{
+            Page,
+            cell::{Cell, IndexLeafCell, PayloadLimits, TableInteriorCell, TableLeafCell},
+            header::PageType,
+        },
+        test_support::{btree_page, table_leaf_cell},
+    };
+
+    const LIMITS: PayloadLimits = PayloadLimits {
+        usable_size: 512,
+        table_leaf_max_local: 477,
+        table_leaf_min_local: 39,
+        index_max_local: 102,
+        index_min_local: 39,
+    };
+
+    fn row(rowid: i64, payload: &[u8]) -> Cell {
+        Cell::TableLeaf(TableLeafCell {
+            payload_size: payload.len() as u64,
+            rowid,
+            payload: payload.to_vec(),
+            first_overflow_page: None,
+        })
+    }
+
+    fn rowids(bytes: &[u8], header_offset: usize) -> Vec<i64> {
+        Page::from_bytes(bytes, header_offset)
+            .unwrap()
+            .cells(LIMITS)
+            .map(|cell| match cell.unwrap() {
+                Cell::TableLeaf(cell) => cell.rowid,
+                cell => panic!("unexpected cell {cell:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn builds_exact_page_image() {
+        let mut builder = PageBuilder::new(PageType::LeafTable, 512, LIMITS, 0);
+        builder
+            .insert_by_rowid(&row(1, &[0x02, 0x01, 0x07]))
+            .unwrap();
+        builder
+            .insert_by_rowid(&row(2, &[0x02, 0x01, 0x08]))
+            .unwrap();
+        assert_eq!(
+            builder.build(),
+            btree_page(
+                512,
+                0,
+                13,
+                None,
+                &[
+                    table_leaf_cell(1, &[0x02, 0x01, 0x07]),
+                    table_leaf_cell(2, &[0x02, 0x01, 0x08]),
+                ],
+            )
+        );
+
+        let mut builder = PageBuilder::new(PageType::InteriorTable, 512, LIMITS, 100);
+        builder.set_right_most_pointer(9);
+        builder
+            .insert_by_rowid(&Cell::TableInterior(TableInteriorCell {
+                left_child_page: 3,
+                rowid: 10,
+            }))
+            .unwrap();
+        let bytes = builder.build();
+        let page = Page::from_bytes(&bytes, 100).unwrap();
+        assert_eq!(page.get_page_header().get_right_most_pointer(), Some(9));
+        assert_eq!(page.cell_offset(0), Some(512 - 5));
+    }
+
+    #[test]
+    fn keeps_cells_in_rowid_order() {
+        let mut builder = PageBuilder::new(PageType::LeafTable, 512, LIMITS, 0);
+        for rowid in [5, 1, 9, 3] {
+            builder.insert_by_rowid(&row(rowid, &[0x01, 0x00])).unwrap();
+        }
+        assert_eq!(rowids(&builder.build(), 0), vec![1, 3, 5, 9]);
+        assert_eq!(
+            builder.insert_by_rowid(&row(3, &[0x01, 0x00])),
+            Err(PageBuilderError::DuplicateRowid(3))
+        );
+        assert_eq!(
+            builder.insert(
+                0,
+                &Cell::IndexLeaf(IndexLeafCell {
+                    payload_size: 0,
+                    payload: vec![],
+                    first_overflow_page: None,
+                })
+            ),
+            Err(PageBuilderError::WrongCellType {
+                page: PageType::LeafTable,
+                cell: PageType::LeafIndex,
+            })
+        );
+    }
+
+    #[test]
+    fn reuses_freed_space() {
+        let mut builder = PageBuilder::new(PageType::LeafTable, 512, LIMITS, 0);
+        for rowid in 1..=3 {
+            builder.insert_by_rowid(&row(rowid, &[0x55; 18])).unwrap();
+        }
+        // Each cell is 20 bytes: cells 1, 2 and 3 sit at 492, 472 and 452
+        assert_eq!(builder.content_start(), 452);
+        builder.remove(1).unwrap();
+        assert_eq!(builder.freeblocks(), &[(472, 20)]);
+        let bytes = builder.build();
+        assert_eq!(&bytes[1..3], &472u16.to_be_bytes());
+        assert_eq!(&bytes[472..476], &[0, 0, 0, 20]);
+
+        // A 18 byte cell leaves 2 bytes over, too few for a freeblock
+        builder.insert_by_rowid(&row(2, &[0x66; 16])).unwrap();
+        assert_eq!(builder.freeblocks(), &[]);
+        assert_eq!(builder.fragmented_bytes(), 2);
+        assert_eq!(builder.cell_offsets(), vec![492, 472, 452]);
+        assert_eq!(rowids(&builder.build(), 0), vec![1, 2, 3]);
+
+        // Freeing the cell at the start of the content area gives the space straight back
+        builder.remove(2).unwrap();
+        assert_eq!(builder.content_start(), 472);
+        assert_eq!(builder.freeblocks(), &[]);
+    }
+
+    #[test]
+    fn defragments_when_space_is_scattered() {
+        let mut builder = PageBuilder::new(PageType::LeafTable, 512, LIMITS, 0);
+        let mut rowid = 0;
+        while builder.insert_by_rowid(&row(rowid, &[0x55; 48])).is_ok() {
+            rowid += 1;
+        }
+        // 50 byte cells and 2 byte pointers: 9 fit after the 8 byte header
+        assert_eq!(builder.len(), 9);
+        builder.remove(6).unwrap();
+        builder.remove(2).unwrap();
+        assert_eq!(builder.freeblocks().len(), 2);
+        // Neither freeblock fits a 60 byte cell, but together they do
+        builder.insert_by_rowid(&row(100, &[0x77; 58])).unwrap();
+        assert_eq!(builder.freeblocks(), &[]);
+        assert_eq!(builder.fragmented_bytes(), 0);
+        builder.insert_by_rowid(&row(101, &[0x77; 58])).unwrap();
+        assert_eq!(
+            rowids(&builder.build(), 0),
+            vec![0, 1, 3, 4, 5, 7, 8, 100, 101]
+        );
+        assert_eq!(
+            builder.insert_by_rowid(&row(102, &[0x77; 58])),
+            Err(PageBuilderError::PageFull {
+                needed: 62,
+                available: 16,
+            })
+        );
+    }
+
+    #[test]
+    fn full_size_pages_store_content_start_as_zero() {
+        let limits = PayloadLimits::new(65536, 64, 32, 32).unwrap();
+        let builder = PageBuilder::new(PageType::LeafIndex, 65536, limits, 0);
+        let bytes = builder.build();
+        assert_eq!(&bytes[5..7], &[0, 0]);
+        assert_eq!(bytes.len(), 65536);
+    }
+
+    #[test]
+    fn page_type_changes_only_when_empty() {
+        let mut builder = PageBuilder::new(PageType::LeafTable, 512, LIMITS, 0);
+        builder.set_page_type(PageType::InteriorIndex).unwrap();
+        assert_eq!(builder.build()[0], 2);
+        builder.set_page_type(PageType::LeafTable).unwrap();
+        builder.insert_by_rowid(&row(1, &[0x01, 0x00])).unwrap();
+        assert_eq!(
+            builder.set_page_type(PageType::LeafIndex),
+            Err(PageBuilderError::NotEmpty)
+        );
+    }
+
+    #[test]
+    fn payload_must_be_split_the_way_pages_are_read() {
+        let mut builder = PageBuilder::new(PageType::LeafTable, 512, LIMITS, 0);
+        // 500 bytes don't fit locally, so they have to spill
+        assert_eq!(
+            builder.insert_by_rowid(&row(1, &[0x55; 500])),
+            Err(PageBuilderError::LocalPayload {
+                payload_size: 500,
+                local: 500,
+                expected: 39,
+            })
+        );
+        let spilled = |first_overflow_page| {
+            Cell::TableLeaf(TableLeafCell {
+                payload_size: 500,
+                rowid: 1,
+                payload: vec![0x55; 39],
+                first_overflow_page,
+            })
+        };
+        assert_eq!(
+            builder.insert_by_rowid(&spilled(None)),
+            Err(PageBuilderError::MissingOverflowPage {
+                payload_size: 500,
+                local: 39,
+            })
+        );
+        builder.insert_by_rowid(&spilled(Some(2))).unwrap();
+        assert_eq!(
+            builder.insert_by_rowid(&Cell::TableLeaf(TableLeafCell {
+                payload_size: 2,
+                rowid: 2,
+                payload: vec![0x01, 0x00],
+                first_overflow_page: Some(3),
+            })),
+            Err(PageBuilderError::UnexpectedOverflowPage { payload_size: 2 })
+        );
+        assert_eq!(builder.len(), 1);
+    }
+}
@@ -0,0 +1,508 @@
+use crate::util::{DecodeError, get_u32_from_bytes, read_varint, write_varint};
+use thiserror::Error;
+
+use super::{Page, header::PageType};
+use crate::database::header::DatabaseHeader;
+
+/// SQLite counts every cell as at least this many bytes, so freeing one leaves room for a
+/// freeblock.
+pub(crate) const MIN_CELL_SIZE: usize = 4;
+
+/// A cell on a table b-tree leaf page. Holds the row itself.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TableLeafCell {
+    /// Total number of bytes of payload, including any overflow.
+    pub payload_size: u64,
+    pub rowid: i64,
+    /// The part of the payload stored on this page.
+    pub payload: Vec<u8>,
+    /// Page number of the first overflow page, if the payload spills.
+    pub first_overflow_page: Option<u32>,
+}
+
+/// A cell on a table b-tree interior page.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TableInteriorCell {
+    /// Page number of the child holding rowids less than or equal to `rowid`.
+    pub left_child_page: u32,
+    pub rowid: i64,
+}
+
+/// A cell on an index b-tree leaf page.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IndexLeafCell {
+    /// Total number of bytes of payload, including any overflow.
+    pub payload_size: u64,
+    /// The part of the payload stored on this page.
+    pub payload: Vec<u8>,
+    /// Page number of the first overflow page, if the payload spills.
+    pub first_overflow_page: Option<u32>,
+}
+
+/// A cell on an index b-tree interior page.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IndexInteriorCell {
+    /// Page number of the child holding keys less than this cell's key.
+    pub left_child_page: u32,
+    /// Total number of bytes of payload, including any overflow.
+    pub payload_size: u64,
+    /// The part of the payload stored on this page.
+    pub payload: Vec<u8>,
+    /// Page number of the first overflow page, if the payload spills.
+    pub first_overflow_page: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Cell {
+    TableLeaf(TableLeafCell),
+    TableInterior(TableInteriorCell),
+    IndexLeaf(IndexLeafCell),
+    IndexInterior(IndexInteriorCell),
+}
+
+impl Cell {
+    /// The child page an interior cell points at.
+    pub fn left_child_page(&self) -> Option<u32> {
+        match self {
+            Self::TableInterior(cell) => Some(cell.left_child_page),
+            Self::IndexInterior(cell) => Some(cell.left_child_page),
+            Self::TableLeaf(_) | Self::IndexLeaf(_) => None,
+        }
+    }
+
+    /// The type of page this kind of cell lives on.
+    pub fn page_type(&self) -> PageType {
+        match self {
+            Self::TableLeaf(_) => PageType::LeafTable,
+            Self::TableInterior(_) => PageType::InteriorTable,
+            Self::IndexLeaf(_) => PageType::LeafIndex,
+            Self::IndexInterior(_) => PageType::InteriorIndex,
+        }
+    }
+
+    /// Encodes the cell as it is stored on a page. The inverse of decoding it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Self::TableLeaf(cell) => {
+                bytes.extend(write_varint(cell.payload_size));
+                bytes.extend(write_varint(cell.rowid as u64));
+                bytes.extend(&cell.payload);
+            }
+            Self::TableInterior(cell) => {
+                bytes.extend(cell.left_child_page.to_be_bytes());
+                bytes.extend(write_varint(cell.rowid as u64));
+            }
+            Self::IndexLeaf(cell) => {
+                bytes.extend(write_varint(cell.payload_size));
+                bytes.extend(&cell.payload);
+            }
+            Self::IndexInterior(cell) => {
+                bytes.extend(cell.left_child_page.to_be_bytes());
+                bytes.extend(write_varint(cell.payload_size));
+                bytes.extend(&cell.payload);
+            }
+        }
+        if let Some(page) = self.first_overflow_page() {
+            bytes.extend(page.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// The first page of the cell's overflow chain, if its payload spills.
+    pub fn first_overflow_page(&self) -> Option<u32> {
+        match self {
+            Self::TableLeaf(cell) => cell.first_overflow_page,
+            Self::IndexLeaf(cell) => cell.first_overflow_page,
+            Self::IndexInterior(cell) => cell.first_overflow_page,
+            Self::TableInterior(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CellError {
+    #[error("Cell offset {offset} is outside of the page, which is {page_len} bytes")]
+    OffsetOutOfBounds { offset: usize, page_len: usize },
+    #[error("Cell at offset {offset} ends past the end of the page")]
+    Truncated { offset: usize },
+    #[error("Encountered an error decoding cell: {0}")]
+    Decode(DecodeError),
+}
+
+/// Header values that leave no room for a cell's payload on a page.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PayloadLimitsError {
+    #[error("A usable page size of {0} bytes is too small to hold any cell")]
+    UsableSizeTooSmall(usize),
+    #[error("A payload fraction of {fraction} leaves no room for payload in {usable_size} bytes")]
+    Fraction { fraction: u8, usable_size: usize },
+}
+
+impl From<DecodeError> for CellError {
+    fn from(value: DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+/// The thresholds that decide how much of a cell's payload is stored on the b-tree page and how
+/// much spills onto overflow pages.
+// https://www.sqlite.org/fileformat.html#b_tree_pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadLimits {
+    /// The page size minus the reserved space at the end of each page.
+    pub usable_size: usize,
+    pub table_leaf_max_local: usize,
+    pub table_leaf_min_local: usize,
+    pub index_max_local: usize,
+    pub index_min_local: usize,
+}
+
+impl PayloadLimits {
+    pub fn new(
+        usable_size: usize,
+        maximum_embedded_payload_fraction: u8,
+        minimum_embedded_payload_fraction: u8,
+        leaf_payload_fraction: u8,
+    ) -> Result<Self, PayloadLimitsError> {
+        let table_leaf_max_local = usable_size
+            .checked_sub(35)
+            .ok_or(PayloadLimitsError::UsableSizeTooSmall(usable_size))?;
+        let embedded = |fraction: u8| {
+            ((usable_size - 12) * usize::from(fraction) / 255)
+                .checked_sub(23)
+                .ok_or(PayloadLimitsError::Fraction {
+                    fraction,
+                    usable_size,
+                })
+        };
+        Ok(Self {
+            usable_size,
+            table_leaf_max_local,
+            table_leaf_min_local: embedded(leaf_payload_fraction)?,
+            index_max_local: embedded(maximum_embedded_payload_fraction)?,
+            index_min_local: embedded(minimum_embedded_payload_fraction)?,
+        })
+    }
+
+    /// Number of payload bytes stored on the b-tree page itself, given the total payload size.
+    pub fn local_payload_size(&self, page_type: &PageType, payload_size: u64) -> usize {
+        let (max_local, min_local) = match page_type {
+            PageType::LeafTable | PageType::InteriorTable => {
+                (self.table_leaf_max_local, self.table_leaf_min_local)
+            }
+            PageType::LeafIndex | PageType::InteriorIndex => {
+                (self.index_max_local, self.index_min_local)
+            }
+        };
+        if payload_size <= max_local as u64 {
+            return payload_size as usize;
+        }
+        let overflow_capacity = self.usable_size as u64 - 4;
+        let k = min_local + ((payload_size - min_local as u64) % overflow_capacity) as usize;
+        if k <= max_local { k } else { min_local }
+    }
+}
+
+impl TryFrom<&DatabaseHeader> for PayloadLimits {
+    type Error = PayloadLimitsError;
+
+    fn try_from(value: &DatabaseHeader) -> Result<Self, Self::Error> {
+        Self::new(
+            value.usable_size(),
+            value.maximum_embedded_payload_fraction,
+            value.minimum_embedded_payload_fraction,
+            value.leaf_payload_fraction,
+        )
+    }
+}
+
+/// Iterator over the cells of a page, in cell pointer array order.
+pub struct Cells<'a> {
+    page: &'a Page,
+    limits: PayloadLimits,
+    index: usize,
+}
+
+impl<'a> Cells<'a> {
+    pub(super) fn new(page: &'a Page, limits: PayloadLimits) -> Self {
+        Self {
+            page,
+            limits,
+            index: 0,
+        }
+    }
+}
+
+impl Iterator for Cells<'_> {
+    type Item = Result<Cell, CellError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cell = self.page.cell(self.index, self.limits)?;
+        self.index += 1;
+        Some(cell)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.page.cell_offsets.0.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+pub(super) fn decode_cell(
+    bytes: &[u8],
+    offset: usize,
+    page_type: &PageType,
+    limits: &PayloadLimits,
+) -> Result<Cell, CellError> {
+    decode_cell_with_size(bytes, offset, page_type, limits).map(|(cell, _)| cell)
+}
+
+/// Decodes a cell, also returning how many bytes of the page it takes up.
+pub(super) fn decode_cell_with_size(
+    bytes: &[u8],
+    offset: usize,
+    page_type: &PageType,
+    limits: &PayloadLimits,
+) -> Result<(Cell, usize), CellError> {
+    if offset >= bytes.len() {
+        return Err(CellError::OffsetOutOfBounds {
+            offset,
+            page_len: bytes.len(),
+        });
+    }
+    let mut reader = CellReader {
+        bytes,
+        start: offset,
+        position: offset,
+        page_type,
+        limits,
+    };
+    let cell = match page_type {
+        PageType::LeafTable => {
+            let payload_size = reader.varint("payload_size")?;
+            let rowid = reader.varint("rowid")? as i64;
+            let (payload, first_overflow_page) = reader.payload(payload_size)?;
+            Cell::TableLeaf(TableLeafCell {
+                payload_size,
+                rowid,
+                payload,
+                first_overflow_page,
+            })
+        }
+        PageType::InteriorTable => {
+            let left_child_page = reader.u32("left_child_page")?;
+            let rowid = reader.varint("rowid")? as i64;
+            Cell::TableInterior(TableInteriorCell {
+                left_child_page,
+                rowid,
+            })
+        }
+        PageType::LeafIndex => {
+            let payload_size = reader.varint("payload_size")?;
+            let (payload, first_overflow_page) = reader.payload(payload_size)?;
+            Cell::IndexLeaf(IndexLeafCell {
+                payload_size,
+                payload,
+                first_overflow_page,
+            })
+        }
+        PageType::InteriorIndex => {
+            let left_child_page = reader.u32("left_child_page")?;
+            let payload_size = reader.varint("payload_size")?;
+            let (payload, first_overflow_page) = reader.payload(payload_size)?;
+            Cell::IndexInterior(IndexInteriorCell {
+                left_child_page,
+                payload_size,
+                payload,
+                first_overflow_page,
+            })
+        }
+    };
+    Ok((cell, reader.position - offset))
+}
+
+struct CellReader<'a> {
+    bytes: &'a [u8],
+    start: usize,
+    position: usize,
+    page_type: &'a PageType,
+    limits: &'a PayloadLimits,
+}
+
+impl CellReader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], CellError> {
+        let end = self.position + len;
+        let taken = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(CellError::Truncated { offset: self.start })?;
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn u32(&mut self, item: &str) -> Result<u32, CellError> {
+        Ok(get_u32_from_bytes(self.take(4)?, item)?)
+    }
+
+    fn varint(&mut self, item: &str) -> Result<u64, CellError> {
+        let (value, len) = read_varint(&self.bytes[self.position..], item)?;
+        self.position += len;
+        Ok(value)
+    }
+
+    fn payload(&mut self, payload_size: u64) -> Result<(Vec<u8>, Option<u32>), CellError> {
+        let local_size = self.limits.local_payload_size(self.page_type, payload_size);
+        let payload = self.take(local_size)?.to_vec();
+        let first_overflow_page = if (local_size as u64) < payload_size {
+            Some(self.u32("first_overflow_page")?)
+        } else {
+            None
+        };
+        Ok((payload, first_overflow_page))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Cell, CellError, IndexLeafCell, PayloadLimits, PayloadLimitsError, TableInteriorCell,
+        TableLeafCell,
+    };
+    use crate::database::{page::Page, test_support::btree_page};
+    use crate::util::DecodeError;
+
+    const LIMITS: PayloadLimits = PayloadLimits {
+        usable_size: 512,
+        table_leaf_max_local: 477,
+        table_leaf_min_local: 39,
+        index_max_local: 102,
+        index_min_local: 39,
+    };
+
+    fn build_page(page_type: u8, right_most_pointer: Option<u32>, cells: &[Vec<u8>]) -> Vec<u8> {
+        btree_page(512, 0, page_type, right_most_pointer, cells)
+    }
+
+    #[test]
+    fn payload_limits_from_fractions() {
+        assert_eq!(PayloadLimits::new(512, 64, 32, 32), Ok(LIMITS));
+        assert_eq!(
+            PayloadLimits::new(4096, 64, 32, 32),
+            Ok(PayloadLimits {
+                usable_size: 4096,
+                table_leaf_max_local: 4061,
+                table_leaf_min_local: 489,
+                index_max_local: 1002,
+                index_min_local: 489,
+            })
+        );
+        assert_eq!(
+            PayloadLimits::new(512, 0, 32, 32),
+            Err(PayloadLimitsError::Fraction {
+                fraction: 0,
+                usable_size: 512,
+            })
+        );
+        assert_eq!(
+            PayloadLimits::new(20, 64, 32, 32),
+            Err(PayloadLimitsError::UsableSizeTooSmall(20))
+        );
+    }
+
+    #[test]
+    fn table_leaf_cells() {
+        let bytes = build_page(
+            13,
+            None,
+            &[vec![3, 1, 0xaa, 0xbb, 0xcc], vec![1, 0x81, 0x00, 0xdd]],
+        );
+        let page = Page::try_from(bytes.as_slice()).unwrap();
+        let cells: Vec<Cell> = page.cells(LIMITS).map(Result::unwrap).collect();
+        assert_eq!(
+            cells,
+            vec![
+                Cell::TableLeaf(TableLeafCell {
+                    payload_size: 3,
+                    rowid: 1,
+                    payload: vec![0xaa, 0xbb, 0xcc],
+                    first_overflow_page: None,
+                }),
+                Cell::TableLeaf(TableLeafCell {
+                    payload_size: 1,
+                    rowid: 128,
+                    payload: vec![0xdd],
+                    first_overflow_page: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn table_leaf_cell_with_overflow() {
+        // With a usable size of 512, table leaf cells keep at most 477 bytes locally. A payload
+        // of 1000 bytes keeps min_local + (1000 - min_local) % 508 = 39 + 453 = 492 > 477, so
+        // only min_local (39) bytes stay on the page.
+        let mut cell = vec![0x87, 0x68, 7];
+        cell.extend(vec![0x11; 39]);
+        cell.extend(9u32.to_be_bytes());
+        let bytes = build_page(13, None, &[cell]);
+        let page = Page::try_from(bytes.as_slice()).unwrap();
+        let cells: Vec<Cell> = page.cells(LIMITS).map(Result::unwrap).collect();
+        assert_eq!(
+            cells,
+            vec![Cell::TableLeaf(TableLeafCell {
+                payload_size: 1000,
+                rowid: 7,
+                payload: vec![0x11; 39],
+                first_overflow_page: Some(9),
+            })]
+        );
+    }
+
+    #[test]
+    fn table_interior_cells() {
+        let mut cell = 4u32.to_be_bytes().to_vec();
+        cell.push(42);
+        let bytes = build_page(5, Some(6), &[cell]);
+        let page = Page::try_from(bytes.as_slice()).unwrap();
+        let cells: Vec<Cell> = page.cells(LIMITS).map(Result::unwrap).collect();
+        assert_eq!(
+            cells,
+            vec![Cell::TableInterior(TableInteriorCell {
+                left_child_page: 4,
+                rowid: 42,
+            })]
+        );
+    }
+
+    #[test]
+    fn index_leaf_cells() {
+        let bytes = build_page(10, None, &[vec![3, 2, 1, 5]]);
+        let page = Page::try_from(bytes.as_slice()).unwrap();
+        let cells: Vec<Cell> = page.cells(LIMITS).map(Result::unwrap).collect();
+        assert_eq!(
+            cells,
+            vec![Cell::IndexLeaf(IndexLeafCell {
+                payload_size: 3,
+                payload: vec![2, 1, 5],
+                first_overflow_page: None,
+            })]
+        );
+    }
+
+    #[test]
+    fn truncated_cell() {
+        let mut bytes = build_page(13, None, &[vec![3, 1, 0xaa, 0xbb, 0xcc]]);
+        // Point the only cell at the last byte of the page so its payload runs off the end.
+        bytes[8..10].copy_from_slice(&511u16.to_be_bytes());
+        let page = Page::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(
+            page.cells(LIMITS).next(),
+            Some(Err(CellError::Decode(DecodeError::TruncatedVarint {
+                num_bytes_recieved: 1,
+                item_parsed: "payload_size".to_owned(),
+            })))
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use crate::util::{DecodeError, get_u16_from_bytes, get_u32_from_bytes};
 use thiserror::Error;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum PageType {
     InteriorIndex,
     InteriorTable,
@@ -15,6 +15,17 @@ pub enum PageTypeError {
     InvalidType(u8),
 }
 
+impl From<&PageType> for u8 {
+    fn from(value: &PageType) -> Self {
+        match value {
+            PageType::InteriorIndex => 2,
+            PageType::InteriorTable => 5,
+            PageType::LeafIndex => 10,
+            PageType::LeafTable => 13,
+        }
+    }
+}
+
 impl TryFrom<u8> for PageType {
     type Error = PageTypeError;
 
@@ -29,27 +40,6 @@ impl TryFrom<u8> for PageType {
     }
 }
 
-#[derive(Debug)]
-pub struct CellOffsets(Vec<u16>);
-
-#[derive(Debug, Error)]
-pub enum CellOffsetError {
-    #[error("Encountered error decoding cell offsets: {0}")]
-    Decode(DecodeError),
-}
-
-impl TryFrom<&[u8]> for CellOffsets {
-    type Error = CellOffsetError;
-
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let offsets: Result<Vec<u16>, CellOffsetError> = value
-            .chunks(2)
-            .map(|item| get_u16_from_bytes(item, "cell_offsets").map_err(CellOffsetError::Decode))
-            .collect();
-        offsets.map(CellOffsets)
-    }
-}
-
 #[derive(Debug)]
 pub struct PageHeader {
     page_type: PageType,
@@ -62,9 +52,29 @@ pub struct PageHeader {
 }
 
 impl PageHeader {
+    pub fn get_page_type(&self) -> &PageType {
+        &self.page_type
+    }
+
+    pub fn get_first_page_offset(&self) -> u16 {
+        self.first_page_offset
+    }
+
     pub fn get_number_of_cells(&self) -> u16 {
         self.number_of_cells
     }
+
+    pub fn get_cell_content_start(&self) -> u16 {
+        self.cell_content_start
+    }
+
+    pub fn get_num_fragmented_free_bytes(&self) -> u8 {
+        self.num_fragmented_free_bytes
+    }
+
+    pub fn get_right_most_pointer(&self) -> Option<u32> {
+        self.right_most_pointer
+    }
 }
 
 #[derive(Debug, Error)]
@@ -1,25 +1,154 @@
 use crate::util::{DecodeError, get_u16_from_bytes};
 use thiserror::Error;
 
+use cell::{Cell, CellError, Cells, PayloadLimits, decode_cell};
 use header::{PageHeader, PageHeaderError, PageType, PageTypeError};
 
+pub mod builder;
+pub mod cell;
 pub mod header;
+pub mod space;
+
+/// SQLite refuses to follow a b-tree more than this many levels down (`BTCURSOR_MAX_DEPTH`).
+/// Even the largest databases are far shallower, so a deeper b-tree is damaged.
+pub const MAX_BTREE_DEPTH: usize = 20;
 
 #[derive(Debug)]
 pub struct Page {
     page_header: PageHeader,
+    /// Where the b-tree page header starts: 100 on page 1 and 0 everywhere else.
+    header_offset: usize,
     cell_offsets: CellOffsets,
+    /// Every byte of the page, including the database header on page 1.
     bytes: Vec<u8>,
 }
 
+impl Page {
+    /// Parses a b-tree page from the full bytes of the page. `header_offset` is where the b-tree
+    /// page header starts, which is 100 on page 1 (after the database header) and 0 otherwise.
+    /// Cell offsets are always relative to the start of the page.
+    pub fn from_bytes(value: &[u8], header_offset: usize) -> Result<Self, DatabasePageError> {
+        let page_type_byte = *value
+            .get(header_offset)
+            .ok_or(DatabasePageError::Truncated {
+                item: "page header",
+                offset: header_offset,
+                needed: 1,
+                page_len: value.len(),
+            })?;
+        let page_type: PageType = page_type_byte
+            .try_into()
+            .map_err(|err| DatabasePageError::PageType(header_offset, err))?;
+        let header_len = match page_type {
+            PageType::InteriorIndex | PageType::InteriorTable => 12,
+            PageType::LeafIndex | PageType::LeafTable => 8,
+        };
+        let header_bytes = slice(value, "page header", header_offset, header_len)?;
+        let header = PageHeader::try_from(header_bytes)
+            .map_err(|err| DatabasePageError::PageHeader(header_offset, err))?;
+
+        let cell_offsets_start = header_offset + header_len;
+        let cell_offsets_len = usize::from(header.get_number_of_cells()) * 2;
+        let cell_offsets: CellOffsets = slice(
+            value,
+            "cell pointer array",
+            cell_offsets_start,
+            cell_offsets_len,
+        )?
+        .try_into()
+        .map_err(|err| DatabasePageError::CellOffset(cell_offsets_start, err))?;
+        Ok(Page {
+            cell_offsets,
+            page_header: header,
+            header_offset,
+            bytes: value.to_vec(),
+        })
+    }
+
+    pub fn get_page_header(&self) -> &PageHeader {
+        &self.page_header
+    }
+
+    pub fn header_offset(&self) -> usize {
+        self.header_offset
+    }
+
+    /// The bytes of the whole page. Cell offsets index into these.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The offset from the start of the page of the cell at `index` in the cell pointer array.
+    pub fn cell_offset(&self, index: usize) -> Option<usize> {
+        self.cell_offsets
+            .0
+            .get(index)
+            .map(|offset| usize::from(*offset))
+    }
+
+    /// Decodes the cell at `index` in the cell pointer array, or `None` past the last cell.
+    pub fn cell(&self, index: usize, limits: PayloadLimits) -> Option<Result<Cell, CellError>> {
+        let offset = *self.cell_offsets.0.get(index)?;
+        Some(decode_cell(
+            &self.bytes,
+            offset.into(),
+            self.page_header.get_page_type(),
+            &limits,
+        ))
+    }
+
+    /// Iterates over the cells on this page in cell pointer array order.
+    pub fn cells(&self, limits: PayloadLimits) -> Cells<'_> {
+        Cells::new(self, limits)
+    }
+}
+
+/// Errors parsing a b-tree page. Each carries the byte offset within the page where the problem
+/// was found.
 #[derive(Debug, Error)]
 pub enum DatabasePageError {
-    #[error("Encountered an error with the cell offsets:\n{0}")]
-    CellOffset(CellOffsetError),
-    #[error("Encountered an error calculating page type:\n{0}")]
-    PageType(PageTypeError),
-    #[error("")]
-    PageHeader(PageHeaderError),
+    #[error("Encountered an error with the cell offsets at offset {0}:\n{1}")]
+    CellOffset(usize, CellOffsetError),
+    #[error("Encountered an error calculating page type at offset {0}:\n{1}")]
+    PageType(usize, PageTypeError),
+    #[error("Encountered an error parsing the page header at offset {0}:\n{1}")]
+    PageHeader(usize, PageHeaderError),
+    #[error("The {item} at offset {offset} needs {needed} bytes, but the page is {page_len} bytes")]
+    Truncated {
+        item: &'static str,
+        offset: usize,
+        needed: usize,
+        page_len: usize,
+    },
+}
+
+impl DatabasePageError {
+    /// The byte offset within the page where the problem was found.
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::CellOffset(offset, _)
+            | Self::PageType(offset, _)
+            | Self::PageHeader(offset, _)
+            | Self::Truncated { offset, .. } => *offset,
+        }
+    }
+}
+
+/// Borrows `len` bytes of the page starting at `offset`.
+fn slice<'a>(
+    value: &'a [u8],
+    item: &'static str,
+    offset: usize,
+    len: usize,
+) -> Result<&'a [u8], DatabasePageError> {
+    value
+        .get(offset..offset + len)
+        .ok_or(DatabasePageError::Truncated {
+            item,
+            offset,
+            needed: len,
+            page_len: value.len(),
+        })
 }
 
 #[derive(Debug)]
@@ -47,33 +176,51 @@ impl TryFrom<&[u8]> for Page {
     type Error = DatabasePageError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let page_type: PageType = value[0].try_into().map_err(DatabasePageError::PageType)?;
-        let mut next_byte: usize = 0;
-        let page_header: Result<PageHeader, PageHeaderError> = match page_type {
-            PageType::InteriorIndex | PageType::InteriorTable => {
-                next_byte += 12;
-                &value[0..12]
-            }
-            PageType::LeafIndex | PageType::LeafTable => {
-                next_byte += 8;
-                &value[0..8]
+        Page::from_bytes(value, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DatabasePageError, Page};
+    use crate::database::test_support::btree_page;
+
+    #[test]
+    fn damaged_pages_are_errors() {
+        let page = btree_page(512, 0, 13, None, &[]);
+        assert!(Page::from_bytes(&page, 0).is_ok());
+
+        let err = Page::from_bytes(&page[..4], 0).unwrap_err();
+        assert!(matches!(
+            err,
+            DatabasePageError::Truncated {
+                item: "page header",
+                offset: 0,
+                needed: 8,
+                page_len: 4,
             }
-        }
-        .try_into();
-
-        match page_header {
-            Ok(header) => {
-                let cell_offsets_len: usize = (header.get_number_of_cells() * 2).into();
-                let cell_offsets: CellOffsets = value[next_byte..(next_byte + cell_offsets_len)]
-                    .try_into()
-                    .map_err(DatabasePageError::CellOffset)?;
-                Ok(Page {
-                    cell_offsets,
-                    page_header: header,
-                    bytes: value.to_vec(),
-                })
+        ));
+
+        let err = Page::from_bytes(&page, 600).unwrap_err();
+        assert_eq!(err.offset(), 600);
+
+        let mut page = btree_page(512, 100, 13, None, &[]);
+        page[100] = 7;
+        let err = Page::from_bytes(&page, 100).unwrap_err();
+        assert!(matches!(err, DatabasePageError::PageType(100, _)));
+
+        // More cells than fit in the page
+        let mut page = btree_page(512, 0, 13, None, &[]);
+        page[3..5].copy_from_slice(&0xffffu16.to_be_bytes());
+        let err = Page::from_bytes(&page, 0).unwrap_err();
+        assert!(matches!(
+            err,
+            DatabasePageError::Truncated {
+                item: "cell pointer array",
+                offset: 8,
+                needed: 131070,
+                page_len: 512,
             }
-            Err(err) => Err(DatabasePageError::PageHeader(err)),
-        }
+        ));
     }
 }
@@ -0,0 +1,308 @@
+use thiserror::Error;
+
+use super::{
+    Page,
+    cell::{CellError, MIN_CELL_SIZE, PayloadLimits, decode_cell_with_size},
+    header::PageType,
+};
+
+/// A run of free bytes inside the cell content area. Freeblocks form a linked list in offset
+/// order: each starts with the 2 byte offset of the next (zero on the last) and its 2 byte size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Freeblock {
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Where every usable byte of a page goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpaceUsage {
+    /// The b-tree page header and cell pointer array, plus the database header on page 1.
+    pub header_bytes: usize,
+    /// The gap between the cell pointer array and the cell content area.
+    pub unallocated_bytes: usize,
+    pub freeblock_bytes: usize,
+    pub fragmented_bytes: usize,
+    pub cell_bytes: usize,
+}
+
+impl SpaceUsage {
+    pub fn total(&self) -> usize {
+        self.header_bytes
+            + self.unallocated_bytes
+            + self.freeblock_bytes
+            + self.fragmented_bytes
+            + self.cell_bytes
+    }
+
+    /// Free space of every kind.
+    pub fn free_bytes(&self) -> usize {
+        self.unallocated_bytes + self.freeblock_bytes + self.fragmented_bytes
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SpaceError {
+    #[error(
+        "The cell content area starts at {content_start}, before the cell pointer array ends at {pointers_end}"
+    )]
+    ContentStartTooLow {
+        content_start: usize,
+        pointers_end: usize,
+    },
+    #[error("The freeblock at offset {offset} with size {size} is outside the cell content area")]
+    FreeblockOutOfBounds { offset: usize, size: usize },
+    #[error("The freeblock at offset {offset} is {size} bytes, smaller than a freeblock header")]
+    FreeblockTooSmall { offset: usize, size: usize },
+    #[error("The freeblock at offset {offset} doesn't come after the end of the one before it")]
+    FreeblockOutOfOrder { offset: usize },
+    #[error("Cell {index} at offset {offset} with size {size} is outside the cell content area")]
+    CellOutOfBounds {
+        index: usize,
+        offset: usize,
+        size: usize,
+    },
+    #[error("The {size} bytes at offset {offset} overlap another cell or freeblock")]
+    Overlap { offset: usize, size: usize },
+    #[error("Cell {0} couldn't be decoded: {1}")]
+    Cell(usize, CellError),
+    #[error("The page's space adds up to {total} bytes, but the usable size is {usable_size}")]
+    SizeMismatch { total: usize, usable_size: usize },
+}
+
+impl Page {
+    /// Where the cell content area starts. A stored value of zero means 65536.
+    pub fn content_start(&self) -> usize {
+        match self.page_header.get_cell_content_start() {
+            0 => 65536,
+            content_start => usize::from(content_start),
+        }
+    }
+
+    /// Where the cell pointer array ends, which is the end of the headers at the top of the page.
+    pub fn cell_pointers_end(&self) -> usize {
+        let header_len = match self.page_header.get_page_type() {
+            PageType::InteriorIndex | PageType::InteriorTable => 12,
+            PageType::LeafIndex | PageType::LeafTable => 8,
+        };
+        self.header_offset + header_len + 2 * self.cell_offsets.0.len()
+    }
+
+    /// Follows the freeblock list, checking each freeblock lies inside the cell content area and
+    /// after the one before it.
+    pub fn freeblocks(&self, usable_size: usize) -> Result<Vec<Freeblock>, SpaceError> {
+        let mut freeblocks: Vec<Freeblock> = Vec::new();
+        let mut offset = usize::from(self.page_header.get_first_page_offset());
+        while offset != 0 {
+            if let Some(previous) = freeblocks.last()
+                && offset < previous.offset + previous.size
+            {
+                return Err(SpaceError::FreeblockOutOfOrder { offset });
+            }
+            let header = self
+                .bytes
+                .get(offset..offset + 4)
+                .filter(|_| offset >= self.content_start() && offset + 4 <= usable_size)
+                .ok_or(SpaceError::FreeblockOutOfBounds { offset, size: 4 })?;
+            let next = usize::from(u16::from_be_bytes([header[0], header[1]]));
+            let size = usize::from(u16::from_be_bytes([header[2], header[3]]));
+            if size < 4 {
+                return Err(SpaceError::FreeblockTooSmall { offset, size });
+            }
+            if offset + size > usable_size {
+                return Err(SpaceError::FreeblockOutOfBounds { offset, size });
+            }
+            freeblocks.push(Freeblock { offset, size });
+            offset = next;
+        }
+        Ok(freeblocks)
+    }
+
+    /// Accounts for every usable byte of the page: headers, unallocated space, freeblocks,
+    /// fragmented bytes and cells. Fails if any cell or freeblock is out of bounds, if any of
+    /// them overlap, or if the parts don't add up to the usable size.
+    pub fn space_usage(&self, limits: PayloadLimits) -> Result<SpaceUsage, SpaceError> {
+        let usable_size = limits.usable_size;
+        let content_start = self.content_start();
+        let pointers_end = self.cell_pointers_end();
+        if content_start < pointers_end {
+            return Err(SpaceError::ContentStartTooLow {
+                content_start,
+                pointers_end,
+            });
+        }
+        let freeblocks = self.freeblocks(usable_size)?;
+        // Every allocated or free region of the cell content area, to check for overlaps
+        let mut regions: Vec<(usize, usize)> = freeblocks
+            .iter()
+            .map(|freeblock| (freeblock.offset, freeblock.size))
+            .collect();
+        let mut cell_bytes = 0;
+        for (index, offset) in self.cell_offsets.0.iter().enumerate() {
+            let offset = usize::from(*offset);
+            let (_, size) = decode_cell_with_size(
+                &self.bytes,
+                offset,
+                self.page_header.get_page_type(),
+                &limits,
+            )
+            .map_err(|err| SpaceError::Cell(index, err))?;
+            let size = size.max(MIN_CELL_SIZE);
+            if offset < content_start || offset + size > usable_size {
+                return Err(SpaceError::CellOutOfBounds {
+                    index,
+                    offset,
+                    size,
+                });
+            }
+            regions.push((offset, size));
+            cell_bytes += size;
+        }
+        regions.sort_unstable();
+        for pair in regions.windows(2) {
+            let ((previous, previous_size), (offset, size)) = (pair[0], pair[1]);
+            if offset < previous + previous_size {
+                return Err(SpaceError::Overlap { offset, size });
+            }
+        }
+        let usage = SpaceUsage {
+            header_bytes: pointers_end,
+            unallocated_bytes: content_start - pointers_end,
+            freeblock_bytes: freeblocks.iter().map(|freeblock| freeblock.size).sum(),
+            fragmented_bytes: usize::from(self.page_header.get_num_fragmented_free_bytes()),
+            cell_bytes,
+        };
+        if usage.total() != usable_size {
+            return Err(SpaceError::SizeMismatch {
+                total: usage.total(),
+                usable_size,
+            });
+        }
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Freeblock, SpaceError, SpaceUsage};
+    use crate::database::page::{
+        Page,
+        builder::PageBuilder,
+        cell::{Cell, PayloadLimits, TableLeafCell},
+        header::PageType,
+    };
+
+    const LIMITS: PayloadLimits = PayloadLimits {
+        usable_size: 512,
+        table_leaf_max_local: 477,
+        table_leaf_min_local: 39,
+        index_max_local: 102,
+        index_min_local: 39,
+    };
+
+    fn row(rowid: i64, payload_len: usize) -> Cell {
+        Cell::TableLeaf(TableLeafCell {
+            payload_size: payload_len as u64,
+            rowid,
+            payload: vec![0x55; payload_len],
+            first_overflow_page: None,
+        })
+    }
+
+    /// Five cells of 20 bytes from 492 down to 412. The second and fourth are freed, then an 18
+    /// byte cell takes the freeblock at 432, leaving 2 fragmented bytes and a freeblock at 472.
+    fn page_bytes() -> Vec<u8> {
+        let mut builder = PageBuilder::new(PageType::LeafTable, 512, LIMITS, 0);
+        for rowid in 1..=5 {
+            builder.insert_by_rowid(&row(rowid, 18)).unwrap();
+        }
+        builder.remove(3).unwrap();
+        builder.remove(1).unwrap();
+        builder.insert_by_rowid(&row(2, 16)).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn accounts_for_every_byte() {
+        let bytes = page_bytes();
+        let page = Page::from_bytes(&bytes, 0).unwrap();
+        assert_eq!(
+            page.freeblocks(512).unwrap(),
+            vec![Freeblock {
+                offset: 472,
+                size: 20,
+            }]
+        );
+        let usage = page.space_usage(LIMITS).unwrap();
+        assert_eq!(
+            usage,
+            SpaceUsage {
+                header_bytes: 16,
+                unallocated_bytes: 412 - 16,
+                freeblock_bytes: 20,
+                fragmented_bytes: 2,
+                cell_bytes: 78,
+            }
+        );
+        assert_eq!(usage.total(), 512);
+        assert_eq!(usage.free_bytes(), 396 + 22);
+
+        let limits = PayloadLimits::new(65536, 64, 32, 32).unwrap();
+        let empty = PageBuilder::new(PageType::LeafIndex, 65536, limits, 0).build();
+        let page = Page::from_bytes(&empty, 0).unwrap();
+        assert_eq!(page.content_start(), 65536);
+        assert_eq!(page.space_usage(limits).unwrap().unallocated_bytes, 65528);
+    }
+
+    #[test]
+    fn reports_corrupt_freeblocks() {
+        let space_usage = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut bytes = page_bytes();
+            edit(&mut bytes);
+            Page::from_bytes(&bytes, 0).unwrap().space_usage(LIMITS)
+        };
+        assert_eq!(
+            space_usage(&|bytes| bytes[474..476].copy_from_slice(&100u16.to_be_bytes())),
+            Err(SpaceError::FreeblockOutOfBounds {
+                offset: 472,
+                size: 100,
+            })
+        );
+        assert_eq!(
+            space_usage(&|bytes| bytes[474..476].copy_from_slice(&30u16.to_be_bytes())),
+            Err(SpaceError::Overlap {
+                offset: 492,
+                size: 20,
+            })
+        );
+        assert_eq!(
+            space_usage(&|bytes| bytes[472..474].copy_from_slice(&472u16.to_be_bytes())),
+            Err(SpaceError::FreeblockOutOfOrder { offset: 472 })
+        );
+        assert_eq!(
+            space_usage(&|bytes| bytes[1..3].copy_from_slice(&4u16.to_be_bytes())),
+            Err(SpaceError::FreeblockOutOfBounds { offset: 4, size: 4 })
+        );
+        assert_eq!(
+            space_usage(&|bytes| bytes[474..476].copy_from_slice(&2u16.to_be_bytes())),
+            Err(SpaceError::FreeblockTooSmall {
+                offset: 472,
+                size: 2,
+            })
+        );
+        assert_eq!(
+            space_usage(&|bytes| bytes[7] = 5),
+            Err(SpaceError::SizeMismatch {
+                total: 515,
+                usable_size: 512,
+            })
+        );
+        assert_eq!(
+            space_usage(&|bytes| bytes[5..7].copy_from_slice(&10u16.to_be_bytes())),
+            Err(SpaceError::ContentStartTooLow {
+                content_start: 10,
+                pointers_end: 16,
+            })
+        );
+    }
+}
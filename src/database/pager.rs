@@ -0,0 +1,475 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    rc::Rc,
+};
+
+use crate::{
+    database::header::DatabaseHeader,
+    database::page::{
+        DatabasePageError, Page,
+        cell::{CellError, PayloadLimits},
+        header::PageType,
+    },
+    database::wal::WalSnapshot,
+};
+use thiserror::Error;
+
+/// SQLite's default cache size when the header doesn't suggest one: -2000, meaning 2000 KiB.
+const DEFAULT_CACHE_SIZE: i32 = -2000;
+
+/// Offset of the first byte SQLite locks in the database file.
+const LOCK_BYTE_OFFSET: u64 = 1 << 30;
+
+/// Where the pager reads pages from.
+#[derive(Debug)]
+enum PageSource {
+    File(RefCell<File>),
+    Memory(Vec<u8>),
+}
+
+/// Reads pages on demand by page number, keeping the most recently used ones in memory.
+#[derive(Debug)]
+pub struct Pager {
+    source: PageSource,
+    /// Length of the database file itself in bytes.
+    source_len: u64,
+    page_size: usize,
+    /// Length of the database in bytes. The same as `source_len`, unless a WAL snapshot has
+    /// changed the size of the database.
+    file_len: u64,
+    /// Newer versions of pages, read in preference to the database file.
+    wal: Option<WalSnapshot>,
+    cache: RefCell<LruCache>,
+    reads: Cell<usize>,
+}
+
+/// A page as it looks on its own, without following the pointers to it from other pages.
+#[derive(Debug)]
+pub enum LoadedPage {
+    BTree(Page),
+    /// Any page that doesn't start with a b-tree page type. Overflow, freelist and pointer map
+    /// pages have no type byte, so they can only be told apart by the pages that point to them.
+    Other(Rc<[u8]>),
+}
+
+/// Something wrong with a page, found while loading it or decoding its cells.
+#[derive(Debug)]
+pub struct PageProblem {
+    pub page_number: u32,
+    /// Offset from the start of the file of the byte where the problem was found.
+    pub offset: u64,
+    pub error: PageProblemError,
+}
+
+#[derive(Debug, Error)]
+pub enum PageProblemError {
+    #[error("{0}")]
+    Pager(PagerError),
+    #[error("Encountered an error decoding a cell: {0}")]
+    Cell(CellError),
+}
+
+#[derive(Debug, Error)]
+pub enum PagerError {
+    #[error("Page {0} is outside of the database")]
+    PageOutOfRange(u32),
+    #[error("Encountered an IO error reading page {0}: {1}")]
+    Io(u32, io::Error),
+    #[error("Encountered an error parsing page {0}:\n{1}")]
+    Page(u32, DatabasePageError),
+}
+
+impl Pager {
+    /// Opens a pager over a database file. Only the pages that are asked for are ever read.
+    pub fn open(file: File, header: &DatabaseHeader) -> io::Result<Self> {
+        let file_len = file.metadata()?.len();
+        Ok(Self::new(
+            PageSource::File(RefCell::new(file)),
+            file_len,
+            header,
+        ))
+    }
+
+    /// A pager over a database file that has already been read into memory.
+    pub fn from_bytes(bytes: Vec<u8>, header: &DatabaseHeader) -> Self {
+        let file_len = bytes.len() as u64;
+        Self::new(PageSource::Memory(bytes), file_len, header)
+    }
+
+    fn new(source: PageSource, file_len: u64, header: &DatabaseHeader) -> Self {
+        let page_size = header.page_size.get() as usize;
+        Self {
+            source,
+            source_len: file_len,
+            page_size,
+            file_len,
+            wal: None,
+            cache: RefCell::new(LruCache::new(cache_capacity(
+                header.default_page_cache_size,
+                page_size,
+            ))),
+            reads: Cell::new(0),
+        }
+    }
+
+    /// Reads pages through a WAL snapshot, so each page written to the WAL as of the snapshot's
+    /// commit comes from there rather than the database file, and the database has the size
+    /// the commit gave it.
+    pub fn with_wal(self, snapshot: WalSnapshot) -> Self {
+        Self {
+            file_len: u64::from(snapshot.database_size()) * self.page_size as u64,
+            wal: Some(snapshot),
+            cache: RefCell::new(LruCache::new(self.cache.borrow().capacity)),
+            ..self
+        }
+    }
+
+    /// The WAL snapshot pages are read through, if there is one.
+    pub fn wal(&self) -> Option<&WalSnapshot> {
+        self.wal.as_ref()
+    }
+
+    /// Number of pages in the file, counting a partial page at the end.
+    pub fn len(&self) -> usize {
+        (self.file_len as usize).div_ceil(self.page_size)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file_len == 0
+    }
+
+    /// Length of the database in bytes. With a WAL snapshot, this is the size as of its commit
+    /// rather than the length of the database file.
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// The page holding the bytes SQLite uses for file locks, starting at 1 GiB. SQLite never
+    /// stores anything on it.
+    pub fn lock_byte_page(&self) -> u32 {
+        (LOCK_BYTE_OFFSET / self.page_size as u64) as u32 + 1
+    }
+
+    /// Number of times a page has been read from the file rather than the cache.
+    pub fn reads(&self) -> usize {
+        self.reads.get()
+    }
+
+    /// The raw bytes of a page. Page numbers start at 1.
+    pub fn page_bytes(&self, page_number: u32) -> Result<Rc<[u8]>, PagerError> {
+        if let Some(bytes) = self.cache.borrow_mut().get(page_number) {
+            return Ok(bytes);
+        }
+        let start = u64::from(page_number)
+            .checked_sub(1)
+            .map(|index| index * self.page_size as u64)
+            .filter(|start| *start < self.file_len)
+            .ok_or(PagerError::PageOutOfRange(page_number))?;
+        let len = (self.page_size as u64).min(self.file_len - start) as usize;
+        let wal_page = self.wal.as_ref().and_then(|wal| wal.page(page_number));
+        let bytes: Rc<[u8]> = match (wal_page, &self.source) {
+            (Some(page), _) => page.into(),
+            // A WAL commit can grow the database past the end of the file. SQLite reads any
+            // page there that the WAL doesn't have as zeros.
+            _ if start + len as u64 > self.source_len => vec![0; len].into(),
+            (None, PageSource::File(file)) => {
+                let mut bytes = vec![0; len];
+                let mut file = file.borrow_mut();
+                file.seek(SeekFrom::Start(start))
+                    .and_then(|_| file.read_exact(&mut bytes))
+                    .map_err(|err| PagerError::Io(page_number, err))?;
+                bytes.into()
+            }
+            (None, PageSource::Memory(memory)) => {
+                memory[start as usize..start as usize + len].into()
+            }
+        };
+        self.reads.set(self.reads.get() + 1);
+        self.cache
+            .borrow_mut()
+            .insert(page_number, Rc::clone(&bytes));
+        Ok(bytes)
+    }
+
+    /// Parses a page as a b-tree page. Page numbers start at 1.
+    pub fn get(&self, page_number: u32) -> Result<Page, PagerError> {
+        let bytes = self.page_bytes(page_number)?;
+        // The b-tree header on the first page comes after the 100 byte database header
+        let header_offset = if page_number == 1 { 100 } else { 0 };
+        Page::from_bytes(&bytes, header_offset).map_err(|err| PagerError::Page(page_number, err))
+    }
+
+    /// Loads a page, treating it as a b-tree page only if it starts with a b-tree page type.
+    /// Page 1 is always a b-tree page, so an unknown type there is an error.
+    pub fn load(&self, page_number: u32) -> Result<LoadedPage, PagerError> {
+        let bytes = self.page_bytes(page_number)?;
+        if page_number != 1
+            && bytes
+                .first()
+                .and_then(|&byte| PageType::try_from(byte).ok())
+                .is_none()
+        {
+            return Ok(LoadedPage::Other(bytes));
+        }
+        self.get(page_number).map(LoadedPage::BTree)
+    }
+
+    /// Loads every page in the file, in page number order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, Result<LoadedPage, PagerError>)> {
+        (1..=self.len() as u32).map(|page_number| (page_number, self.load(page_number)))
+    }
+
+    /// Loads each of `page_numbers` and decodes every cell on the b-tree pages, collecting
+    /// whatever fails rather than stopping at the first problem.
+    pub fn problems(
+        &self,
+        limits: PayloadLimits,
+        page_numbers: impl IntoIterator<Item = u32>,
+    ) -> Vec<PageProblem> {
+        let mut problems = Vec::new();
+        for page_number in page_numbers {
+            let page = self.load(page_number);
+            let page_start = (u64::from(page_number) - 1) * self.page_size as u64;
+            let page = match page {
+                Ok(LoadedPage::BTree(page)) => page,
+                Ok(LoadedPage::Other(_)) => continue,
+                Err(err) => {
+                    let offset = match &err {
+                        PagerError::Page(_, err) => err.offset() as u64,
+                        _ => 0,
+                    };
+                    problems.push(PageProblem {
+                        page_number,
+                        offset: page_start + offset,
+                        error: PageProblemError::Pager(err),
+                    });
+                    continue;
+                }
+            };
+            for (index, cell) in page.cells(limits).enumerate() {
+                if let Err(err) = cell {
+                    let offset = match &err {
+                        CellError::OffsetOutOfBounds { offset, .. }
+                        | CellError::Truncated { offset } => *offset,
+                        CellError::Decode(_) => page.cell_offset(index).unwrap_or_default(),
+                    };
+                    problems.push(PageProblem {
+                        page_number,
+                        offset: page_start + offset as u64,
+                        error: PageProblemError::Cell(err),
+                    });
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// Converts the header's suggested cache size into a number of pages. Like `PRAGMA cache_size`,
+/// a positive value is a number of pages and a negative value is a number of KiB.
+fn cache_capacity(default_page_cache_size: u32, page_size: usize) -> usize {
+    let cache_size = match default_page_cache_size as i32 {
+        0 => DEFAULT_CACHE_SIZE,
+        cache_size => cache_size,
+    };
+    let pages = if cache_size > 0 {
+        cache_size as usize
+    } else {
+        cache_size.unsigned_abs() as usize * 1024 / page_size
+    };
+    pages.max(1)
+}
+
+/// A least-recently-used cache of page bytes.
+#[derive(Debug)]
+struct LruCache {
+    capacity: usize,
+    /// Page bytes, and the tick at which each page was last used.
+    pages: HashMap<u32, (Rc<[u8]>, u64)>,
+    /// The page last used at each tick, so the least recently used page is the first entry.
+    recency: BTreeMap<u64, u32>,
+    tick: u64,
+}
+
+impl LruCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pages: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, page_number: u32) -> Option<Rc<[u8]>> {
+        self.tick += 1;
+        let (bytes, last_used) = self.pages.get_mut(&page_number)?;
+        self.recency.remove(last_used);
+        *last_used = self.tick;
+        self.recency.insert(self.tick, page_number);
+        Some(Rc::clone(bytes))
+    }
+
+    fn insert(&mut self, page_number: u32, bytes: Rc<[u8]>) {
+        self.tick += 1;
+        if let Some((_, last_used)) = self.pages.remove(&page_number) {
+            self.recency.remove(&last_used);
+        } else if self.pages.len() >= self.capacity
+            && let Some((_, least_recently_used)) = self.recency.pop_first()
+        {
+            self.pages.remove(&least_recently_used);
+        }
+        self.pages.insert(page_number, (bytes, self.tick));
+        self.recency.insert(self.tick, page_number);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoadedPage, LruCache, PageProblemError, PagerError, cache_capacity};
+    use crate::database::{
+        Database,
+        page::{DatabasePageError, cell::CellError},
+        test_support::{btree_page, database_bytes, table_leaf_cell},
+    };
+
+    #[test]
+    fn cache_capacity_from_header() {
+        assert_eq!(cache_capacity(0, 4096), 500);
+        assert_eq!(cache_capacity(100, 4096), 100);
+        assert_eq!(cache_capacity(-64i32 as u32, 4096), 16);
+        assert_eq!(cache_capacity(-1i32 as u32, 65536), 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, vec![1].into());
+        cache.insert(2, vec![2].into());
+        assert!(cache.get(1).is_some());
+        cache.insert(3, vec![3].into());
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+
+        // Inserting a page that is already cached just refreshes it
+        cache.insert(1, vec![1].into());
+        cache.insert(4, vec![4].into());
+        assert!(cache.get(3).is_none());
+        assert!(cache.get(1).is_some());
+        assert_eq!(cache.pages.len(), 2);
+        assert_eq!(cache.recency.len(), 2);
+    }
+
+    #[test]
+    fn reads_pages_from_file_on_demand() {
+        let bytes = database_bytes(
+            512,
+            (0..10)
+                .map(|idx| btree_page(512, if idx == 0 { 100 } else { 0 }, 13, None, &[]))
+                .collect(),
+        );
+        let path = std::env::temp_dir().join(format!("pager-test-{}.db", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let database = Database::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let pager = database.pager();
+        assert_eq!(pager.len(), 10);
+        assert_eq!(pager.reads(), 0);
+        assert!(pager.get(7).is_ok());
+        assert!(pager.get(7).is_ok());
+        assert_eq!(pager.reads(), 1);
+        assert!(pager.get(11).is_err());
+    }
+
+    #[test]
+    fn page_one_keeps_database_header() {
+        let database = Database::from_bytes(database_bytes(
+            512,
+            vec![
+                btree_page(
+                    512,
+                    100,
+                    13,
+                    None,
+                    &[table_leaf_cell(1, &[0x02, 0x01, 0x07])],
+                ),
+                btree_page(512, 0, 13, None, &[table_leaf_cell(2, &[0x02, 0x01, 0x08])]),
+            ],
+        ))
+        .unwrap();
+        let pager = database.pager();
+        assert!(matches!(pager.get(0), Err(PagerError::PageOutOfRange(0))));
+        assert!(matches!(pager.get(3), Err(PagerError::PageOutOfRange(3))));
+
+        let page = pager.get(1).unwrap();
+        assert_eq!(page.header_offset(), 100);
+        assert_eq!(page.bytes().len(), 512);
+        assert_eq!(&page.bytes()[..16], b"SQLite format 3\0");
+        // Cell offsets on page 1 count from the start of the page, not the end of the header
+        let offset = page.cell_offset(0).unwrap();
+        assert_eq!(offset, 512 - 5);
+        assert_eq!(page.bytes()[offset..], [0x03, 0x01, 0x02, 0x01, 0x07]);
+        assert!(page.cell(0, database.payload_limits()).unwrap().is_ok());
+
+        let page = pager.get(2).unwrap();
+        assert_eq!(page.header_offset(), 0);
+        assert_eq!(
+            page.bytes()[page.cell_offset(0).unwrap()..],
+            [0x03, 0x02, 0x02, 0x01, 0x08]
+        );
+    }
+
+    #[test]
+    fn classifies_and_reports_damaged_pages() {
+        let mut overflow = vec![0x55; 512];
+        overflow[0..4].copy_from_slice(&0u32.to_be_bytes());
+        let mut bad_cell = btree_page(512, 0, 13, None, &[table_leaf_cell(1, &[0x01])]);
+        // Point the only cell past the end of the page
+        bad_cell[8..10].copy_from_slice(&600u16.to_be_bytes());
+        let mut bad_header = btree_page(512, 0, 5, Some(2), &[]);
+        bad_header[3..5].copy_from_slice(&300u16.to_be_bytes());
+        let database = Database::from_bytes(database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 13, None, &[]),
+                overflow,
+                bad_cell,
+                bad_header,
+            ],
+        ))
+        .unwrap();
+
+        let pages: Vec<_> = database.pager().iter().collect();
+        assert!(matches!(pages[0], (1, Ok(LoadedPage::BTree(_)))));
+        assert!(matches!(pages[1], (2, Ok(LoadedPage::Other(_)))));
+        assert!(matches!(pages[2], (3, Ok(LoadedPage::BTree(_)))));
+        assert!(matches!(pages[3], (4, Err(PagerError::Page(4, _)))));
+
+        let problems = database.page_problems();
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].page_number, 3);
+        assert_eq!(problems[0].offset, 2 * 512 + 600);
+        assert!(matches!(
+            problems[0].error,
+            PageProblemError::Cell(CellError::OffsetOutOfBounds { offset: 600, .. })
+        ));
+        assert_eq!(problems[1].page_number, 4);
+        assert_eq!(problems[1].offset, 3 * 512 + 12);
+        assert!(matches!(
+            problems[1].error,
+            PageProblemError::Pager(PagerError::Page(
+                4,
+                DatabasePageError::Truncated { offset: 12, .. }
+            ))
+        ));
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    database::{
+        Database,
+        pager::PagerError,
+        structure::{PageUse, StructureError, walk_btrees},
+    },
+    util::{DecodeError, get_u32_from_bytes},
+};
+use thiserror::Error;
+
+/// What a page is, according to its pointer map entry.
+// https://www.sqlite.org/fileformat.html#pointer_map_or_ptrmap_pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerMapEntryType {
+    /// The root page of a b-tree. Has no parent.
+    RootPage,
+    /// A page on the freelist. Has no parent.
+    FreePage,
+    /// The first page of an overflow chain. The parent is the b-tree page holding the cell.
+    FirstOverflow,
+    /// A later page of an overflow chain. The parent is the previous overflow page.
+    LaterOverflow,
+    /// A non-root b-tree page. The parent is its parent b-tree page.
+    BTree,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PointerMapEntryTypeError {
+    #[error("Pointer map entry type should be between 1 and 5, was {0}")]
+    IncorrectVariant(u8),
+}
+
+impl TryFrom<u8> for PointerMapEntryType {
+    type Error = PointerMapEntryTypeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::RootPage),
+            2 => Ok(Self::FreePage),
+            3 => Ok(Self::FirstOverflow),
+            4 => Ok(Self::LaterOverflow),
+            5 => Ok(Self::BTree),
+            _ => Err(PointerMapEntryTypeError::IncorrectVariant(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointerMapEntry {
+    pub entry_type: PointerMapEntryType,
+    /// The page pointing at this one, or zero for root and free pages.
+    pub parent_page: u32,
+}
+
+/// A page whose pointer map entry disagrees with the pages that actually point at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointerMapMismatch {
+    pub page_number: u32,
+    /// The entry the page should have, or `None` if nothing points at it.
+    pub expected: Option<PointerMapEntry>,
+    pub found: PointerMapEntry,
+}
+
+#[derive(Debug, Error)]
+pub enum PointerMapError {
+    #[error("Encountered an error reading a page: {0}")]
+    Pager(PagerError),
+    #[error("Pointer map entry for page {page} is invalid: {error}")]
+    EntryType {
+        page: u32,
+        error: PointerMapEntryTypeError,
+    },
+    #[error("Encountered an error walking the database's pages: {0}")]
+    Structure(StructureError),
+    #[error("Encountered an error decoding a pointer map page: {0}")]
+    Decode(DecodeError),
+}
+
+impl From<DecodeError> for PointerMapError {
+    fn from(value: DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+/// The pointer map of an auto-vacuum database, which records the parent of every page after
+/// page 2 so pages can be moved without searching for whatever points at them.
+///
+/// Pointer map pages hold `usable_size / 5` entries of 5 bytes each: the entry type followed by
+/// the 4 byte parent page number. The first pointer map page is page 2 and covers the pages
+/// straight after it; each later one follows the last page the one before it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointerMap {
+    usable_size: usize,
+    lock_byte_page: u32,
+    pub entries: BTreeMap<u32, PointerMapEntry>,
+}
+
+impl PointerMap {
+    /// Reads the entries for every page of the database.
+    pub fn read(database: &Database) -> Result<Self, PointerMapError> {
+        let pager = database.pager();
+        let usable_size = database.header.usable_size();
+        let lock_byte_page = pager.lock_byte_page();
+        let mut entries = BTreeMap::new();
+        for page_number in 3..=pager.len() as u32 {
+            let map_page = pointer_map_page(usable_size, lock_byte_page, page_number);
+            if map_page == page_number || page_number == lock_byte_page {
+                continue;
+            }
+            let bytes = pager.page_bytes(map_page).map_err(PointerMapError::Pager)?;
+            let offset = 5 * (page_number - map_page - 1) as usize;
+            let entry_bytes = bytes
+                .get(offset..offset + 5)
+                .ok_or(PointerMapError::Pager(PagerError::PageOutOfRange(map_page)))?;
+            let entry_type = PointerMapEntryType::try_from(entry_bytes[0]).map_err(|error| {
+                PointerMapError::EntryType {
+                    page: page_number,
+                    error,
+                }
+            })?;
+            let parent_page = get_u32_from_bytes(&entry_bytes[1..5], "pointer_map_parent")?;
+            entries.insert(
+                page_number,
+                PointerMapEntry {
+                    entry_type,
+                    parent_page,
+                },
+            );
+        }
+        Ok(Self {
+            usable_size,
+            lock_byte_page,
+            entries,
+        })
+    }
+
+    pub fn get(&self, page_number: u32) -> Option<&PointerMapEntry> {
+        self.entries.get(&page_number)
+    }
+
+    pub fn is_pointer_map_page(&self, page_number: u32) -> bool {
+        page_number >= 2
+            && pointer_map_page(self.usable_size, self.lock_byte_page, page_number) == page_number
+    }
+
+    /// Compares every entry against the parents found by walking each b-tree from its root and
+    /// the freelist.
+    pub fn check(&self, database: &Database) -> Result<Vec<PointerMapMismatch>, PointerMapError> {
+        let expected = expected_entries(database)?;
+        Ok(self
+            .entries
+            .iter()
+            .filter(|(page_number, found)| expected.get(page_number) != Some(found))
+            .map(|(page_number, found)| PointerMapMismatch {
+                page_number: *page_number,
+                expected: expected.get(page_number).copied(),
+                found: *found,
+            })
+            .collect())
+    }
+}
+
+/// The pointer map page holding the entry for `page_number`, or `page_number` itself if it is a
+/// pointer map page.
+pub fn pointer_map_page(usable_size: usize, lock_byte_page: u32, page_number: u32) -> u32 {
+    // Each pointer map page is followed by the pages it covers
+    let pages_per_map = (usable_size / 5) as u32 + 1;
+    let map_page = (page_number - 2) / pages_per_map * pages_per_map + 2;
+    // A pointer map page that would land on the lock byte page moves to the page after it
+    if map_page == lock_byte_page {
+        map_page + 1
+    } else {
+        map_page
+    }
+}
+
+/// Works out what each page's pointer map entry should be from the pages that point at it.
+fn expected_entries(
+    database: &Database,
+) -> Result<BTreeMap<u32, PointerMapEntry>, PointerMapError> {
+    let mut expected = BTreeMap::new();
+    walk_btrees(database, &mut |reference| {
+        let entry = match reference.page_use {
+            PageUse::BTree { parent: None, .. } => PointerMapEntry {
+                entry_type: PointerMapEntryType::RootPage,
+                parent_page: 0,
+            },
+            PageUse::BTree {
+                parent: Some(parent),
+                ..
+            } => PointerMapEntry {
+                entry_type: PointerMapEntryType::BTree,
+                parent_page: parent,
+            },
+            PageUse::Overflow { parent, first } => PointerMapEntry {
+                entry_type: if first {
+                    PointerMapEntryType::FirstOverflow
+                } else {
+                    PointerMapEntryType::LaterOverflow
+                },
+                parent_page: parent,
+            },
+        };
+        expected.insert(reference.page_number, entry);
+    })
+    .map_err(PointerMapError::Structure)?;
+    let freelist = database
+        .freelist()
+        .map_err(|err| PointerMapError::Structure(StructureError::Freelist(err)))?;
+    for page_number in freelist.pages() {
+        expected.insert(
+            page_number,
+            PointerMapEntry {
+                entry_type: PointerMapEntryType::FreePage,
+                parent_page: 0,
+            },
+        );
+    }
+    Ok(expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PointerMapEntry, PointerMapEntryType, PointerMapMismatch, pointer_map_page};
+    use crate::{
+        database::{
+            Database,
+            test_support::{
+                btree_page, database_bytes, schema_page, table_interior_cell, table_leaf_cell,
+            },
+        },
+        util::write_varint,
+    };
+
+    fn entry(entry_type: PointerMapEntryType, parent_page: u32) -> PointerMapEntry {
+        PointerMapEntry {
+            entry_type,
+            parent_page,
+        }
+    }
+
+    fn pointer_map_bytes(entries: &[(u8, u32)]) -> Vec<u8> {
+        let mut page = vec![0; 512];
+        for (idx, (entry_type, parent)) in entries.iter().enumerate() {
+            page[idx * 5] = *entry_type;
+            page[idx * 5 + 1..idx * 5 + 5].copy_from_slice(&parent.to_be_bytes());
+        }
+        page
+    }
+
+    fn overflow_page(next_page: u32) -> Vec<u8> {
+        let mut page = vec![0x55; 512];
+        page[0..4].copy_from_slice(&next_page.to_be_bytes());
+        page
+    }
+
+    /// Page 2 is the pointer map. Table `t` has an interior root on page 3 over leaves on pages
+    /// 4 and 5, and the row on page 4 overflows onto pages 6 and 7. Page 8 is free.
+    fn auto_vacuum_database(entries: &[(u8, u32)]) -> Database {
+        // A 1000 byte payload keeps 39 bytes on the page and spills the rest onto two pages
+        let mut overflowing = write_varint(1000);
+        overflowing.extend(write_varint(1));
+        overflowing.extend([0x55; 39]);
+        overflowing.extend(6u32.to_be_bytes());
+        let mut bytes = database_bytes(
+            512,
+            vec![
+                schema_page(&[("t", 3)]),
+                pointer_map_bytes(entries),
+                btree_page(512, 0, 5, Some(5), &[table_interior_cell(4, 1)]),
+                btree_page(512, 0, 13, None, &[overflowing]),
+                btree_page(512, 0, 13, None, &[table_leaf_cell(2, &[0x02, 0x01, 0x07])]),
+                overflow_page(7),
+                overflow_page(0),
+                vec![0; 512],
+            ],
+        );
+        bytes[32..36].copy_from_slice(&8u32.to_be_bytes());
+        bytes[36..40].copy_from_slice(&1u32.to_be_bytes());
+        bytes[52..56].copy_from_slice(&3u32.to_be_bytes());
+        Database::from_bytes(bytes).unwrap()
+    }
+
+    const ENTRIES: [(u8, u32); 6] = [(1, 0), (5, 3), (5, 3), (3, 4), (4, 6), (2, 0)];
+
+    #[test]
+    fn locates_pointer_map_pages() {
+        // 102 entries fit on a 512 byte page, so the second map page is 2 + 103
+        assert_eq!(pointer_map_page(512, 2_097_153, 2), 2);
+        assert_eq!(pointer_map_page(512, 2_097_153, 104), 2);
+        assert_eq!(pointer_map_page(512, 2_097_153, 105), 105);
+        assert_eq!(pointer_map_page(512, 2_097_153, 106), 105);
+        // A map page that would be the lock byte page moves to the page after it
+        assert_eq!(pointer_map_page(512, 105, 106), 106);
+    }
+
+    #[test]
+    fn reads_and_checks_entries() {
+        let database = auto_vacuum_database(&ENTRIES);
+        let pointer_map = database.pointer_map().unwrap().unwrap();
+        assert!(pointer_map.is_pointer_map_page(2));
+        assert!(!pointer_map.is_pointer_map_page(3));
+        assert_eq!(
+            pointer_map.get(3),
+            Some(&entry(PointerMapEntryType::RootPage, 0))
+        );
+        assert_eq!(
+            pointer_map.get(7),
+            Some(&entry(PointerMapEntryType::LaterOverflow, 6))
+        );
+        assert_eq!(pointer_map.entries.len(), 6);
+        assert_eq!(pointer_map.check(&database).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn reports_mismatched_entries() {
+        let mut entries = ENTRIES;
+        entries[0] = (5, 0);
+        entries[2] = (5, 4);
+        entries[3] = (4, 4);
+        let database = auto_vacuum_database(&entries);
+        // Page 2 now starts with a b-tree page type, but is still known to be a pointer map page
+        assert!(database.page_problems().is_empty());
+        let pointer_map = database.pointer_map().unwrap().unwrap();
+        assert_eq!(
+            pointer_map.check(&database).unwrap(),
+            vec![
+                PointerMapMismatch {
+                    page_number: 3,
+                    expected: Some(entry(PointerMapEntryType::RootPage, 0)),
+                    found: entry(PointerMapEntryType::BTree, 0),
+                },
+                PointerMapMismatch {
+                    page_number: 5,
+                    expected: Some(entry(PointerMapEntryType::BTree, 3)),
+                    found: entry(PointerMapEntryType::BTree, 4),
+                },
+                PointerMapMismatch {
+                    page_number: 6,
+                    expected: Some(entry(PointerMapEntryType::FirstOverflow, 4)),
+                    found: entry(PointerMapEntryType::LaterOverflow, 4),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn no_pointer_map_without_auto_vacuum() {
+        let database = Database::from_bytes(database_bytes(
+            512,
+            vec![btree_page(512, 100, 13, None, &[])],
+        ))
+        .unwrap();
+        assert!(database.pointer_map().unwrap().is_none());
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{
+    database::header::TextEncoding,
+    util::{DecodeError, read_varint},
+};
+use thiserror::Error;
+
+/// A single column value decoded from a record.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Blob(Vec<u8>),
+    Text(String),
+}
+
+/// Describes how a value is stored in the body of a record.
+// https://www.sqlite.org/fileformat.html#record_format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialType {
+    Null,
+    /// A big-endian twos-complement integer of the given number of bytes: 1, 2, 3, 4, 6 or 8.
+    Integer(usize),
+    Float,
+    /// The integer 0. Only used in schema format 4 and above.
+    Zero,
+    /// The integer 1. Only used in schema format 4 and above.
+    One,
+    Blob(usize),
+    Text(usize),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SerialTypeError {
+    #[error("Serial types 10 and 11 are reserved for internal use. Found: {0}")]
+    Reserved(u64),
+}
+
+impl TryFrom<u64> for SerialType {
+    type Error = SerialTypeError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Null),
+            1 => Ok(Self::Integer(1)),
+            2 => Ok(Self::Integer(2)),
+            3 => Ok(Self::Integer(3)),
+            4 => Ok(Self::Integer(4)),
+            5 => Ok(Self::Integer(6)),
+            6 => Ok(Self::Integer(8)),
+            7 => Ok(Self::Float),
+            8 => Ok(Self::Zero),
+            9 => Ok(Self::One),
+            10 | 11 => Err(SerialTypeError::Reserved(value)),
+            n if n % 2 == 0 => Ok(Self::Blob(((n - 12) / 2) as usize)),
+            n => Ok(Self::Text(((n - 13) / 2) as usize)),
+        }
+    }
+}
+
+impl SerialType {
+    /// Number of bytes the value occupies in the record body.
+    pub fn content_size(&self) -> usize {
+        match self {
+            Self::Null | Self::Zero | Self::One => 0,
+            Self::Integer(size) | Self::Blob(size) | Self::Text(size) => *size,
+            Self::Float => 8,
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RecordError {
+    #[error("Record header claims {header_size} bytes but the payload is {payload_len} bytes")]
+    HeaderOutOfBounds {
+        header_size: usize,
+        payload_len: usize,
+    },
+    #[error("Encountered an invalid serial type in the record header: {0}")]
+    SerialType(SerialTypeError),
+    #[error("Record body needs {needed} bytes but only {available} are left in the payload")]
+    BodyTruncated { needed: usize, available: usize },
+    #[error("Encountered an error decoding the record: {0}")]
+    Decode(DecodeError),
+}
+
+impl From<DecodeError> for RecordError {
+    fn from(value: DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+impl From<SerialTypeError> for RecordError {
+    fn from(value: SerialTypeError) -> Self {
+        Self::SerialType(value)
+    }
+}
+
+/// A decoded record: the column values of a table row or index entry, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub values: Vec<Value>,
+}
+
+impl Record {
+    /// Decodes a complete record payload. Text values are decoded using `encoding`, which
+    /// should be the database's `DatabaseHeader::text_encoding`.
+    pub fn decode(payload: &[u8], encoding: &TextEncoding) -> Result<Self, RecordError> {
+        let (header_size, mut position) = read_varint(payload, "record_header_size")?;
+        let header_size = header_size as usize;
+        if header_size > payload.len() {
+            return Err(RecordError::HeaderOutOfBounds {
+                header_size,
+                payload_len: payload.len(),
+            });
+        }
+
+        let mut serial_types = Vec::new();
+        while position < header_size {
+            let (serial_type, len) = read_varint(&payload[position..header_size], "serial_type")?;
+            serial_types.push(SerialType::try_from(serial_type)?);
+            position += len;
+        }
+
+        let mut body = &payload[header_size..];
+        let values = serial_types
+            .iter()
+            .map(|serial_type| {
+                let size = serial_type.content_size();
+                if size > body.len() {
+                    return Err(RecordError::BodyTruncated {
+                        needed: size,
+                        available: body.len(),
+                    });
+                }
+                let (content, rest) = body.split_at(size);
+                body = rest;
+                Ok(decode_value(serial_type, content, encoding))
+            })
+            .collect::<Result<Vec<Value>, RecordError>>()?;
+        Ok(Record { values })
+    }
+}
+
+/// Decodes one value. `content` must be exactly `serial_type.content_size()` bytes.
+fn decode_value(serial_type: &SerialType, content: &[u8], encoding: &TextEncoding) -> Value {
+    match serial_type {
+        SerialType::Null => Value::Null,
+        SerialType::Integer(_) => {
+            // Sign-extend from the stored width to 64 bits.
+            let negative = content.first().is_some_and(|byte| byte & 0x80 != 0);
+            let mut bytes = if negative { [0xff; 8] } else { [0; 8] };
+            bytes[8 - content.len()..].copy_from_slice(content);
+            Value::Integer(i64::from_be_bytes(bytes))
+        }
+        SerialType::Float => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(content);
+            Value::Float(f64::from_be_bytes(bytes))
+        }
+        SerialType::Zero => Value::Integer(0),
+        SerialType::One => Value::Integer(1),
+        SerialType::Blob(_) => Value::Blob(content.to_vec()),
+        SerialType::Text(_) => Value::Text(decode_text(content, encoding)),
+    }
+}
+
+// SQLite does not validate text it stores, so invalid sequences are replaced rather than
+// rejected to keep damaged rows readable.
+fn decode_text(content: &[u8], encoding: &TextEncoding) -> String {
+    let units = |from_bytes: fn([u8; 2]) -> u16| -> Vec<u16> {
+        content
+            .chunks_exact(2)
+            .map(|pair| from_bytes([pair[0], pair[1]]))
+            .collect()
+    };
+    match encoding {
+        TextEncoding::Utf8 => String::from_utf8_lossy(content).into_owned(),
+        TextEncoding::Utf16Le => String::from_utf16_lossy(&units(u16::from_le_bytes)),
+        TextEncoding::Utf16Be => String::from_utf16_lossy(&units(u16::from_be_bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Record, RecordError, SerialType, SerialTypeError, Value};
+    use crate::database::header::TextEncoding;
+
+    #[test]
+    fn serial_type_conversion() {
+        assert_eq!(SerialType::try_from(0), Ok(SerialType::Null));
+        assert_eq!(SerialType::try_from(5), Ok(SerialType::Integer(6)));
+        assert_eq!(SerialType::try_from(6), Ok(SerialType::Integer(8)));
+        assert_eq!(SerialType::try_from(9), Ok(SerialType::One));
+        assert_eq!(SerialType::try_from(10), Err(SerialTypeError::Reserved(10)));
+        assert_eq!(SerialType::try_from(12), Ok(SerialType::Blob(0)));
+        assert_eq!(SerialType::try_from(13), Ok(SerialType::Text(0)));
+        assert_eq!(SerialType::try_from(18), Ok(SerialType::Blob(3)));
+        assert_eq!(SerialType::try_from(19), Ok(SerialType::Text(3)));
+    }
+
+    #[test]
+    fn decode_every_type() {
+        #[rustfmt::skip]
+        let payload = [
+            // Header: size, then serial types
+            12, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 17,
+            // Body
+            0xff,
+            0x01, 0x00,
+            0xff, 0xff, 0xfe,
+            0x00, 0x01, 0x00, 0x00,
+            0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0x40, 0x09, 0x21, 0xfb, 0x54, 0x44, 0x2d, 0x18,
+            b'h', b'i',
+        ];
+        let record = Record::decode(&payload, &TextEncoding::Utf8).unwrap();
+        assert_eq!(
+            record.values,
+            vec![
+                Value::Null,
+                Value::Integer(-1),
+                Value::Integer(256),
+                Value::Integer(-2),
+                Value::Integer(65536),
+                Value::Integer(-(1 << 47)),
+                Value::Integer(i64::MAX),
+                Value::Float(std::f64::consts::PI),
+                Value::Integer(0),
+                Value::Integer(1),
+                Value::Text("hi".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_blob() {
+        let payload = [2, 18, 1, 2, 3];
+        let record = Record::decode(&payload, &TextEncoding::Utf8).unwrap();
+        assert_eq!(record.values, vec![Value::Blob(vec![1, 2, 3])]);
+    }
+
+    #[test]
+    fn decode_utf16_text() {
+        let little_endian = [2, 21, b'h', 0, b'i', 0];
+        let big_endian = [2, 21, 0, b'h', 0, b'i'];
+        assert_eq!(
+            Record::decode(&little_endian, &TextEncoding::Utf16Le)
+                .unwrap()
+                .values,
+            vec![Value::Text("hi".to_owned())]
+        );
+        assert_eq!(
+            Record::decode(&big_endian, &TextEncoding::Utf16Be)
+                .unwrap()
+                .values,
+            vec![Value::Text("hi".to_owned())]
+        );
+    }
+
+    #[test]
+    fn malformed_records() {
+        assert_eq!(
+            Record::decode(&[9, 1], &TextEncoding::Utf8),
+            Err(RecordError::HeaderOutOfBounds {
+                header_size: 9,
+                payload_len: 2,
+            })
+        );
+        assert_eq!(
+            Record::decode(&[2, 11], &TextEncoding::Utf8),
+            Err(RecordError::SerialType(SerialTypeError::Reserved(11)))
+        );
+        assert_eq!(
+            Record::decode(&[2, 4, 0, 1], &TextEncoding::Utf8),
+            Err(RecordError::BodyTruncated {
+                needed: 4,
+                available: 2,
+            })
+        );
+    }
+}
@@ -0,0 +1,687 @@
+use crate::database::{
+    Database,
+    collation::Collation,
+    cursor::{CursorError, table::TableCursor},
+    record::Value,
+};
+use thiserror::Error;
+
+/// The schema table is always rooted on the first page.
+pub const SCHEMA_ROOT_PAGE: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaObjectType {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+impl TryFrom<&str> for SchemaObjectType {
+    type Error = SchemaError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "table" => Ok(Self::Table),
+            "index" => Ok(Self::Index),
+            "view" => Ok(Self::View),
+            "trigger" => Ok(Self::Trigger),
+            _ => Err(SchemaError::UnknownObjectType(value.to_owned())),
+        }
+    }
+}
+
+/// One row of the `sqlite_schema` table.
+// https://www.sqlite.org/schematab.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaObject {
+    pub object_type: SchemaObjectType,
+    pub name: String,
+    /// The table a table, index or trigger belongs to. For views, the view's own name.
+    pub tbl_name: String,
+    /// Root b-tree page of tables and indexes. `None` for views, triggers and virtual tables.
+    pub root_page: Option<u32>,
+    /// The statement that created the object. `None` for automatically created indexes.
+    pub sql: Option<String>,
+}
+
+/// A column of a table, from its `CREATE TABLE` statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub collation: Collation,
+    /// An `INTEGER PRIMARY KEY` column is another name for the rowid. Records store it as NULL.
+    pub rowid_alias: bool,
+}
+
+/// A column of an index, from its `CREATE INDEX` statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexColumn {
+    /// The position of the indexed column in its table, or `None` if the index is on an
+    /// expression.
+    pub column: Option<usize>,
+    pub collation: Collation,
+    pub descending: bool,
+}
+
+/// The layout of an index, from its `CREATE INDEX` statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexDefinition {
+    pub columns: Vec<IndexColumn>,
+    /// A partial index has a `WHERE` clause, so only holds some of its table's rows.
+    pub partial: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("Encountered an error reading the schema table:\n{0}")]
+    Cursor(CursorError),
+    #[error("Schema row {rowid} has an invalid {column} column: {value:?}")]
+    InvalidColumn {
+        rowid: i64,
+        column: &'static str,
+        value: Option<Value>,
+    },
+    #[error("Unknown schema object type {0}")]
+    UnknownObjectType(String),
+}
+
+/// Every table, index, view and trigger in the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    pub objects: Vec<SchemaObject>,
+}
+
+impl Schema {
+    pub fn read(database: &Database) -> Result<Self, SchemaError> {
+        let objects = TableCursor::new(database, SCHEMA_ROOT_PAGE)
+            .map(|row| {
+                let (rowid, values) = row.map_err(SchemaError::Cursor)?;
+                schema_object(rowid, values)
+            })
+            .collect::<Result<Vec<SchemaObject>, SchemaError>>()?;
+        Ok(Schema { objects })
+    }
+
+    /// Finds an object by name. Like SQLite, names are matched case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&SchemaObject> {
+        self.objects
+            .iter()
+            .find(|object| object.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Resolves a table or index name to the root page of its b-tree.
+    pub fn root_page(&self, name: &str) -> Option<u32> {
+        self.get(name).and_then(|object| object.root_page)
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &SchemaObject> {
+        self.of_type(SchemaObjectType::Table)
+    }
+
+    pub fn indexes(&self) -> impl Iterator<Item = &SchemaObject> {
+        self.of_type(SchemaObjectType::Index)
+    }
+
+    pub fn views(&self) -> impl Iterator<Item = &SchemaObject> {
+        self.of_type(SchemaObjectType::View)
+    }
+
+    pub fn triggers(&self) -> impl Iterator<Item = &SchemaObject> {
+        self.of_type(SchemaObjectType::Trigger)
+    }
+
+    /// The indexes on the given table.
+    pub fn indexes_on<'a>(&'a self, table: &'a str) -> impl Iterator<Item = &'a SchemaObject> {
+        self.indexes()
+            .filter(move |object| object.tbl_name.eq_ignore_ascii_case(table))
+    }
+
+    /// How an index is laid out, parsed from its `CREATE INDEX` statement. `None` for indexes
+    /// SQLite created automatically, which have no SQL, and for SQL this can't parse.
+    pub fn index_definition(&self, name: &str) -> Option<IndexDefinition> {
+        let index = self
+            .get(name)
+            .filter(|object| object.object_type == SchemaObjectType::Index)?;
+        let table_columns = self.get(&index.tbl_name)?.columns()?;
+        let tokens = tokenize(index.sql.as_deref()?);
+        let (items, rest) = parenthesized_list(&tokens)?;
+        let columns = items
+            .iter()
+            .map(|item| index_column(item, &table_columns))
+            .collect::<Option<Vec<IndexColumn>>>()?;
+        Some(IndexDefinition {
+            columns,
+            partial: rest.iter().any(|token| token.is_keyword("WHERE")),
+        })
+    }
+
+    fn of_type(&self, object_type: SchemaObjectType) -> impl Iterator<Item = &SchemaObject> {
+        self.objects
+            .iter()
+            .filter(move |object| object.object_type == object_type)
+    }
+}
+
+impl SchemaObject {
+    /// The columns of a table, parsed from its `CREATE TABLE` statement. `None` for other
+    /// objects, virtual tables, tables created with `AS SELECT`, and SQL this can't parse.
+    pub fn columns(&self) -> Option<Vec<Column>> {
+        if self.object_type != SchemaObjectType::Table {
+            return None;
+        }
+        let tokens = tokenize(self.sql.as_deref()?);
+        let open = tokens
+            .iter()
+            .position(|token| *token == Token::Punct('('))?;
+        if tokens[..open]
+            .iter()
+            .any(|token| token.is_keyword("VIRTUAL") || token.is_keyword("AS"))
+        {
+            return None;
+        }
+        let (items, rest) = parenthesized_list(&tokens)?;
+        let without_rowid = is_without_rowid(rest);
+        let mut columns = Vec::new();
+        let mut integer_columns = Vec::new();
+        let mut primary_key = None;
+        for item in items {
+            let first = item.first()?;
+            if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+                .iter()
+                .any(|keyword| first.is_keyword(keyword))
+            {
+                // A table constraint. Only a single column primary key can make a rowid alias.
+                if let Some(key) = item.iter().position(|token| token.is_keyword("PRIMARY"))
+                    && let Some((key_columns, _)) = parenthesized_list(&item[key..])
+                    && let [column] = key_columns.as_slice()
+                    && !column.iter().any(|token| token.is_keyword("DESC"))
+                {
+                    primary_key = column.first().and_then(Token::name);
+                }
+                continue;
+            }
+            // Generated columns change which columns are stored in the record
+            if item.iter().any(|token| token.is_keyword("AS")) {
+                return None;
+            }
+            let type_end = item[1..]
+                .iter()
+                .position(|token| token.name().is_none() || is_constraint_keyword(token))
+                .map_or(item.len(), |idx| idx + 1);
+            let integer = matches!(&item[1..type_end], [token] if token.is_keyword("INTEGER"));
+            let rowid_alias = integer
+                && item
+                    .iter()
+                    .position(|token| token.is_keyword("PRIMARY"))
+                    .is_some_and(|primary| {
+                        !item
+                            .get(primary + 2)
+                            .is_some_and(|token| token.is_keyword("DESC"))
+                    });
+            integer_columns.push(integer);
+            columns.push(Column {
+                name: first.name()?,
+                collation: collation(item)?,
+                rowid_alias,
+            });
+        }
+        if let Some(primary_key) = primary_key
+            && let Some(idx) = columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(&primary_key))
+        {
+            columns[idx].rowid_alias = integer_columns[idx];
+        }
+        if without_rowid {
+            for column in &mut columns {
+                column.rowid_alias = false;
+            }
+        }
+        Some(columns)
+    }
+
+    /// Whether a table was created `WITHOUT ROWID`, which stores it in an index b-tree keyed on
+    /// its primary key.
+    pub fn without_rowid(&self) -> bool {
+        let Some(sql) = self.sql.as_deref() else {
+            return false;
+        };
+        let tokens = tokenize(sql);
+        parenthesized_list(&tokens).is_some_and(|(_, rest)| is_without_rowid(rest))
+    }
+}
+
+/// A piece of a SQL statement, as far as column definitions need to be understood.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A keyword or identifier, with any quotes removed.
+    Word(String),
+    /// A quoted identifier, with its quotes removed.
+    Quoted(String),
+    /// A string literal or number.
+    Literal,
+    Punct(char),
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Self::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn name(&self) -> Option<String> {
+        match self {
+            Self::Word(name) | Self::Quoted(name) => Some(name.clone()),
+            Self::Literal | Self::Punct(_) => None,
+        }
+    }
+}
+
+/// Splits SQL into words, quoted names, literals and punctuation, dropping comments. This is
+/// just enough of a lexer to read column lists, not a full SQL tokenizer.
+fn tokenize(sql: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '-' if chars.peek() == Some(&'-') => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            '"' | '`' | '[' | '\'' => {
+                let close = if c == '[' { ']' } else { c };
+                let mut text = String::new();
+                while let Some(c) = chars.next() {
+                    if c == close {
+                        // Doubling a quote escapes it, except inside brackets
+                        if close != ']' && chars.peek() == Some(&close) {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    text.push(c);
+                }
+                tokens.push(if c == '\'' {
+                    Token::Literal
+                } else {
+                    Token::Quoted(text)
+                });
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '$' => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+                {
+                    word.push(c);
+                }
+                tokens.push(if c.is_ascii_digit() {
+                    Token::Literal
+                } else {
+                    Token::Word(word)
+                });
+            }
+            c => tokens.push(Token::Punct(c)),
+        }
+    }
+    tokens
+}
+
+/// Finds the first parenthesized list in `tokens` and splits it on its top level commas.
+/// Returns the items and the tokens after the closing parenthesis.
+fn parenthesized_list(tokens: &[Token]) -> Option<(Vec<&[Token]>, &[Token])> {
+    let open = tokens
+        .iter()
+        .position(|token| *token == Token::Punct('('))?;
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = open + 1;
+    for (idx, token) in tokens.iter().enumerate().skip(open + 1) {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') if depth == 0 => {
+                items.push(&tokens[start..idx]);
+                return Some((items, &tokens[idx + 1..]));
+            }
+            Token::Punct(')') => depth -= 1,
+            Token::Punct(',') if depth == 0 => {
+                items.push(&tokens[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Keywords that end a column's type name and start its constraints.
+fn is_constraint_keyword(token: &Token) -> bool {
+    [
+        "CONSTRAINT",
+        "PRIMARY",
+        "NOT",
+        "NULL",
+        "UNIQUE",
+        "CHECK",
+        "DEFAULT",
+        "COLLATE",
+        "REFERENCES",
+        "GENERATED",
+        "AS",
+    ]
+    .iter()
+    .any(|keyword| token.is_keyword(keyword))
+}
+
+/// The collating function named by a `COLLATE` clause, or `Binary` without one. `None` for
+/// collating functions that aren't built in.
+fn collation(tokens: &[Token]) -> Option<Collation> {
+    match tokens.iter().position(|token| token.is_keyword("COLLATE")) {
+        Some(idx) => Collation::try_from(tokens.get(idx + 1)?.name()?.as_str()).ok(),
+        None => Some(Collation::Binary),
+    }
+}
+
+fn is_without_rowid(tokens: &[Token]) -> bool {
+    tokens
+        .windows(2)
+        .any(|pair| pair[0].is_keyword("WITHOUT") && pair[1].is_keyword("ROWID"))
+}
+
+/// One term of an index's column list: a column name or an expression, followed by an optional
+/// `COLLATE` clause and sort order.
+fn index_column(item: &[Token], table_columns: &[Column]) -> Option<IndexColumn> {
+    let descending = item.last()?.is_keyword("DESC");
+    let item = match item.last()? {
+        token if token.is_keyword("ASC") || token.is_keyword("DESC") => &item[..item.len() - 1],
+        _ => item,
+    };
+    let (expression, collate) = match item.iter().position(|token| token.is_keyword("COLLATE")) {
+        Some(idx) => (&item[..idx], &item[idx..]),
+        None => (item, &[][..]),
+    };
+    let column = match expression {
+        [token] => token.name().and_then(|name| {
+            table_columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(&name))
+        }),
+        _ => None,
+    };
+    let collation = if collate.is_empty() {
+        column.map_or(Collation::Binary, |column| table_columns[column].collation)
+    } else {
+        collation(collate)?
+    };
+    Some(IndexColumn {
+        column,
+        collation,
+        descending,
+    })
+}
+
+fn schema_object(rowid: i64, values: Vec<Value>) -> Result<SchemaObject, SchemaError> {
+    let mut values = values.into_iter();
+    let invalid = |column: &'static str, value: Option<Value>| SchemaError::InvalidColumn {
+        rowid,
+        column,
+        value,
+    };
+    let text = |column: &'static str, value: Option<Value>| match value {
+        Some(Value::Text(text)) => Ok(text),
+        value => Err(invalid(column, value)),
+    };
+
+    let object_type = SchemaObjectType::try_from(text("type", values.next())?.as_str())?;
+    let name = text("name", values.next())?;
+    let tbl_name = text("tbl_name", values.next())?;
+    let root_page = match values.next() {
+        Some(Value::Null) | Some(Value::Integer(0)) => None,
+        Some(Value::Integer(page)) if page > 0 && page <= i64::from(u32::MAX) => Some(page as u32),
+        value => return Err(invalid("rootpage", value)),
+    };
+    let sql = match values.next() {
+        Some(Value::Null) | None => None,
+        value => Some(text("sql", value)?),
+    };
+    Ok(SchemaObject {
+        object_type,
+        name,
+        tbl_name,
+        root_page,
+        sql,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Column, IndexColumn, IndexDefinition, Schema, SchemaObject, SchemaObjectType};
+    use crate::database::{
+        Database,
+        collation::Collation,
+        record::Value,
+        test_support::{
+            btree_page, database_bytes, record_bytes, table_interior_cell, table_leaf_cell,
+        },
+    };
+
+    fn schema_row(rowid: i64, object_type: &str, name: &str, tbl_name: &str, root: i64) -> Vec<u8> {
+        let sql = if object_type == "index" && name.starts_with("sqlite_autoindex") {
+            Value::Null
+        } else {
+            Value::Text(format!("CREATE {} {}", object_type.to_uppercase(), name))
+        };
+        table_leaf_cell(
+            rowid,
+            &record_bytes(&[
+                Value::Text(object_type.to_owned()),
+                Value::Text(name.to_owned()),
+                Value::Text(tbl_name.to_owned()),
+                Value::Integer(root),
+                sql,
+            ]),
+        )
+    }
+
+    fn empty_leaf() -> Vec<u8> {
+        btree_page(512, 0, 13, None, &[])
+    }
+
+    #[test]
+    fn read_schema_from_first_page() {
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(
+                    512,
+                    100,
+                    13,
+                    None,
+                    &[
+                        schema_row(1, "table", "users", "users", 2),
+                        schema_row(2, "index", "sqlite_autoindex_users_1", "users", 3),
+                        schema_row(3, "view", "adults", "adults", 0),
+                        schema_row(4, "trigger", "on_insert", "users", 0),
+                    ],
+                ),
+                empty_leaf(),
+                btree_page(512, 0, 10, None, &[]),
+            ],
+        );
+        let database = Database::from_bytes(bytes).unwrap();
+        let schema = database.schema().unwrap();
+
+        assert_eq!(
+            schema.get("USERS"),
+            Some(&SchemaObject {
+                object_type: SchemaObjectType::Table,
+                name: "users".to_owned(),
+                tbl_name: "users".to_owned(),
+                root_page: Some(2),
+                sql: Some("CREATE TABLE users".to_owned()),
+            })
+        );
+        assert_eq!(schema.root_page("sqlite_autoindex_users_1"), Some(3));
+        assert_eq!(schema.root_page("adults"), None);
+        assert_eq!(schema.tables().count(), 1);
+        assert_eq!(schema.views().count(), 1);
+        assert_eq!(schema.triggers().count(), 1);
+        let indexes: Vec<&SchemaObject> = schema.indexes_on("users").collect();
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].sql, None);
+    }
+
+    #[test]
+    fn read_schema_across_interior_page() {
+        let bytes = database_bytes(
+            512,
+            vec![
+                btree_page(512, 100, 5, Some(3), &[table_interior_cell(2, 1)]),
+                btree_page(512, 0, 13, None, &[schema_row(1, "table", "a", "a", 4)]),
+                btree_page(512, 0, 13, None, &[schema_row(2, "table", "b", "b", 5)]),
+                empty_leaf(),
+                empty_leaf(),
+            ],
+        );
+        let database = Database::from_bytes(bytes).unwrap();
+        let schema = database.schema().unwrap();
+        let names: Vec<&str> = schema.tables().map(|table| table.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(schema.root_page("b"), Some(5));
+    }
+
+    #[test]
+    fn empty_database_has_empty_schema() {
+        let bytes = database_bytes(512, vec![btree_page(512, 100, 13, None, &[])]);
+        let database = Database::from_bytes(bytes).unwrap();
+        assert_eq!(database.schema().unwrap(), Schema::default());
+    }
+
+    fn object(
+        object_type: SchemaObjectType,
+        name: &str,
+        tbl_name: &str,
+        sql: &str,
+    ) -> SchemaObject {
+        SchemaObject {
+            object_type,
+            name: name.to_owned(),
+            tbl_name: tbl_name.to_owned(),
+            root_page: Some(2),
+            sql: Some(sql.to_owned()),
+        }
+    }
+
+    #[test]
+    fn parses_columns_and_indexes() {
+        let schema = Schema {
+            objects: vec![
+                object(
+                    SchemaObjectType::Table,
+                    "t",
+                    "t",
+                    "CREATE TABLE t(\n  id INTEGER PRIMARY KEY, -- the rowid\n  \"Name\" TEXT COLLATE NOCASE NOT NULL,\n  [size] VARCHAR(10) DEFAULT 'a,b',\n  UNIQUE (\"Name\", size)\n)",
+                ),
+                object(
+                    SchemaObjectType::Index,
+                    "i",
+                    "t",
+                    "CREATE INDEX i ON t(name DESC, size COLLATE rtrim, size + 1) WHERE size > 0",
+                ),
+                object(SchemaObjectType::Index, "j", "t", "CREATE INDEX j ON t(id)"),
+                object(
+                    SchemaObjectType::Table,
+                    "w",
+                    "w",
+                    "CREATE TABLE w(a INTEGER, b, PRIMARY KEY(a)) WITHOUT ROWID",
+                ),
+                object(
+                    SchemaObjectType::Table,
+                    "k",
+                    "k",
+                    "CREATE TABLE k(a integer, b, PRIMARY KEY(a))",
+                ),
+                object(
+                    SchemaObjectType::Table,
+                    "g",
+                    "g",
+                    "CREATE TABLE g(a, b AS (a * 2))",
+                ),
+                object(
+                    SchemaObjectType::Index,
+                    "u",
+                    "t",
+                    "CREATE INDEX u ON t(name COLLATE unicode)",
+                ),
+            ],
+        };
+        let t = schema.get("t").unwrap();
+        assert_eq!(
+            t.columns().unwrap(),
+            vec![
+                Column {
+                    name: "id".to_owned(),
+                    collation: Collation::Binary,
+                    rowid_alias: true,
+                },
+                Column {
+                    name: "Name".to_owned(),
+                    collation: Collation::NoCase,
+                    rowid_alias: false,
+                },
+                Column {
+                    name: "size".to_owned(),
+                    collation: Collation::Binary,
+                    rowid_alias: false,
+                },
+            ]
+        );
+        assert!(!t.without_rowid());
+        assert_eq!(
+            schema.index_definition("i"),
+            Some(IndexDefinition {
+                columns: vec![
+                    IndexColumn {
+                        column: Some(1),
+                        collation: Collation::NoCase,
+                        descending: true,
+                    },
+                    IndexColumn {
+                        column: Some(2),
+                        collation: Collation::RTrim,
+                        descending: false,
+                    },
+                    IndexColumn {
+                        column: None,
+                        collation: Collation::Binary,
+                        descending: false,
+                    },
+                ],
+                partial: true,
+            })
+        );
+        assert!(!schema.index_definition("j").unwrap().partial);
+
+        let w = schema.get("w").unwrap();
+        assert!(w.without_rowid());
+        assert!(
+            w.columns()
+                .unwrap()
+                .iter()
+                .all(|column| !column.rowid_alias)
+        );
+        assert!(schema.get("k").unwrap().columns().unwrap()[0].rowid_alias);
+        assert_eq!(schema.get("g").unwrap().columns(), None);
+        assert_eq!(schema.index_definition("u"), None);
+        assert_eq!(schema.index_definition("t"), None);
+    }
+}
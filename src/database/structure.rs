@@ -0,0 +1,500 @@
+use std::collections::HashSet;
+
+use crate::{
+    database::{
+        Database,
+        freelist::{Freelist, FreelistError},
+        page::{MAX_BTREE_DEPTH, cell::CellError, header::PageType},
+        pager::PagerError,
+        ptrmap::pointer_map_page,
+        schema::{SCHEMA_ROOT_PAGE, SchemaError},
+    },
+    util::{DecodeError, get_u32_from_bytes},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StructureError {
+    #[error("Encountered an error reading a page: {0}")]
+    Pager(PagerError),
+    #[error("Encountered an error decoding a cell on page {0}:\n{1}")]
+    Cell(u32, CellError),
+    #[error("Page {0} is referenced more than once")]
+    ReferencedTwice(u32),
+    #[error(
+        "Page {page_number} is more than {} levels down the b-tree rooted at page {root_page}",
+        MAX_BTREE_DEPTH
+    )]
+    TooDeep { page_number: u32, root_page: u32 },
+    #[error("Encountered an error reading the schema: {0}")]
+    Schema(SchemaError),
+    #[error("Encountered an error reading the freelist: {0}")]
+    Freelist(FreelistError),
+    #[error("Encountered an error decoding an overflow page: {0}")]
+    Decode(DecodeError),
+}
+
+impl From<DecodeError> for StructureError {
+    fn from(value: DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+/// How a page reached from a b-tree root is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageUse {
+    /// A b-tree page. `parent` is `None` for the root.
+    BTree {
+        page_type: PageType,
+        parent: Option<u32>,
+    },
+    /// An overflow page. The parent of the first page of a chain is the b-tree page holding the
+    /// cell; the parent of every later page is the overflow page before it.
+    Overflow { parent: u32, first: bool },
+}
+
+/// A page reached by following pointers down from the root of a b-tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageReference {
+    pub page_number: u32,
+    pub root_page: u32,
+    pub page_use: PageUse,
+}
+
+/// Visits every page of every b-tree in the database, starting with the schema table on page 1
+/// and then each root page listed in the schema. Fails if any page is reached twice.
+pub fn walk_btrees(
+    database: &Database,
+    visit: &mut impl FnMut(PageReference),
+) -> Result<(), StructureError> {
+    match walk_btrees_collecting(database, visit).into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Like [`walk_btrees`], but carries on past problems and returns all of them. A page reached
+/// twice, one that can't be read, or one more than `MAX_BTREE_DEPTH` levels down is skipped
+/// along with everything below it.
+pub fn walk_btrees_collecting(
+    database: &Database,
+    visit: &mut impl FnMut(PageReference),
+) -> Vec<StructureError> {
+    let mut problems = Vec::new();
+    // Without the schema only the schema table itself can be walked
+    let roots = match database.schema() {
+        Ok(schema) => schema
+            .objects
+            .iter()
+            .filter_map(|object| object.root_page)
+            .collect(),
+        Err(err) => {
+            problems.push(StructureError::Schema(err));
+            Vec::new()
+        }
+    };
+    let mut walker = Walker {
+        database,
+        visited: HashSet::new(),
+        problems,
+    };
+    for root in std::iter::once(SCHEMA_ROOT_PAGE).chain(roots) {
+        if root != 0 {
+            walker.walk_btree(root, None, root, 0, visit);
+        }
+    }
+    walker.problems
+}
+
+struct Walker<'a> {
+    database: &'a Database,
+    visited: HashSet<u32>,
+    problems: Vec<StructureError>,
+}
+
+impl Walker<'_> {
+    fn walk_btree(
+        &mut self,
+        page_number: u32,
+        parent: Option<u32>,
+        root_page: u32,
+        depth: usize,
+        visit: &mut impl FnMut(PageReference),
+    ) {
+        if !self.visited.insert(page_number) {
+            self.problems
+                .push(StructureError::ReferencedTwice(page_number));
+            return;
+        }
+        if depth >= MAX_BTREE_DEPTH {
+            self.problems.push(StructureError::TooDeep {
+                page_number,
+                root_page,
+            });
+            return;
+        }
+        let page = match self.database.pager().get(page_number) {
+            Ok(page) => page,
+            Err(err) => {
+                self.problems.push(StructureError::Pager(err));
+                return;
+            }
+        };
+        visit(PageReference {
+            page_number,
+            root_page,
+            page_use: PageUse::BTree {
+                page_type: page.get_page_header().get_page_type().clone(),
+                parent,
+            },
+        });
+        let mut children = Vec::new();
+        for cell in page.cells(self.database.payload_limits()) {
+            let cell = match cell {
+                Ok(cell) => cell,
+                Err(err) => {
+                    self.problems.push(StructureError::Cell(page_number, err));
+                    continue;
+                }
+            };
+            children.extend(cell.left_child_page());
+            if let Some(first_overflow) = cell.first_overflow_page() {
+                self.walk_overflow_chain(first_overflow, page_number, root_page, visit);
+            }
+        }
+        children.extend(page.get_page_header().get_right_most_pointer());
+        for child in children {
+            self.walk_btree(child, Some(page_number), root_page, depth + 1, visit);
+        }
+    }
+
+    fn walk_overflow_chain(
+        &mut self,
+        first_overflow: u32,
+        cell_page: u32,
+        root_page: u32,
+        visit: &mut impl FnMut(PageReference),
+    ) {
+        let mut parent = cell_page;
+        let mut next_overflow = first_overflow;
+        while next_overflow != 0 {
+            let overflow_page = next_overflow;
+            if !self.visited.insert(overflow_page) {
+                self.problems
+                    .push(StructureError::ReferencedTwice(overflow_page));
+                return;
+            }
+            visit(PageReference {
+                page_number: overflow_page,
+                root_page,
+                page_use: PageUse::Overflow {
+                    parent,
+                    first: parent == cell_page,
+                },
+            });
+            let next = match self.database.pager().page_bytes(overflow_page) {
+                Ok(bytes) => match bytes.get(0..4) {
+                    Some(next_bytes) => get_u32_from_bytes(next_bytes, "next_overflow_page")
+                        .map_err(StructureError::Decode),
+                    None => Err(StructureError::Pager(PagerError::PageOutOfRange(
+                        overflow_page,
+                    ))),
+                },
+                Err(err) => Err(StructureError::Pager(err)),
+            };
+            match next {
+                Ok(next) => next_overflow = next,
+                Err(err) => {
+                    self.problems.push(err);
+                    return;
+                }
+            }
+            parent = overflow_page;
+        }
+    }
+}
+
+/// What a page of the database file is used for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageKind {
+    /// A page of the b-tree rooted at `root_page`.
+    BTree {
+        page_type: PageType,
+        root_page: u32,
+    },
+    /// An overflow page holding part of a payload from the b-tree rooted at `root_page`.
+    Overflow {
+        root_page: u32,
+    },
+    FreelistTrunk,
+    FreelistLeaf,
+    PointerMap,
+    /// The page holding the bytes SQLite locks, at 1 GiB into the file. It is never used.
+    LockByte,
+    /// A page nothing points at.
+    Unused,
+}
+
+impl PageKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::BTree {
+                page_type: PageType::InteriorIndex,
+                ..
+            } => "interior index",
+            Self::BTree {
+                page_type: PageType::InteriorTable,
+                ..
+            } => "interior table",
+            Self::BTree {
+                page_type: PageType::LeafIndex,
+                ..
+            } => "index leaf",
+            Self::BTree {
+                page_type: PageType::LeafTable,
+                ..
+            } => "table leaf",
+            Self::Overflow { .. } => "overflow",
+            Self::FreelistTrunk => "freelist trunk",
+            Self::FreelistLeaf => "freelist leaf",
+            Self::PointerMap => "pointer map",
+            Self::LockByte => "lock byte",
+            Self::Unused => "unused",
+        }
+    }
+}
+
+/// The kind of every page in the database file.
+#[derive(Debug)]
+pub struct PageKinds {
+    /// The kind of page `n` is at index `n - 1`.
+    kinds: Vec<PageKind>,
+    /// Everything that stopped a page from being classified. Pages below a page that couldn't
+    /// be read are left as [`PageKind::Unused`].
+    pub problems: Vec<StructureError>,
+}
+
+impl PageKinds {
+    /// Classifies every page by walking each b-tree from its root, the freelist, and, in
+    /// auto-vacuum databases, the pointer map. Problems along the way are collected rather than
+    /// stopping the walk, so one damaged page doesn't hide the kind of every other page.
+    pub fn read(database: &Database) -> Self {
+        let pager = database.pager();
+        let mut kinds = vec![PageKind::Unused; pager.len()];
+        let mut classify = |page_number: u32, kind: PageKind| {
+            if let Some(slot) = kinds.get_mut(page_number as usize - 1) {
+                *slot = kind;
+            }
+        };
+        let lock_byte_page = pager.lock_byte_page();
+        classify(lock_byte_page, PageKind::LockByte);
+        if database.header.largest_root_page != 0 {
+            let usable_size = database.header.usable_size();
+            for page_number in 2..=pager.len() as u32 {
+                if pointer_map_page(usable_size, lock_byte_page, page_number) == page_number {
+                    classify(page_number, PageKind::PointerMap);
+                }
+            }
+        }
+        let mut problems = walk_btrees_collecting(database, &mut |reference| {
+            let kind = match reference.page_use {
+                PageUse::BTree { page_type, .. } => PageKind::BTree {
+                    page_type,
+                    root_page: reference.root_page,
+                },
+                PageUse::Overflow { .. } => PageKind::Overflow {
+                    root_page: reference.root_page,
+                },
+            };
+            classify(reference.page_number, kind);
+        });
+        // A freelist whose size disagrees with the header is still worth classifying
+        match Freelist::read(
+            pager,
+            database.header.usable_size(),
+            database.header.first_freelist,
+        ) {
+            Ok(freelist) => {
+                if let Err(err) = freelist.validate(database.header.num_freelist) {
+                    problems.push(StructureError::Freelist(err));
+                }
+                for trunk in &freelist.trunks {
+                    classify(trunk.page_number, PageKind::FreelistTrunk);
+                    for leaf in &trunk.leaves {
+                        classify(*leaf, PageKind::FreelistLeaf);
+                    }
+                }
+            }
+            Err(err) => problems.push(StructureError::Freelist(err)),
+        }
+        Self { kinds, problems }
+    }
+
+    /// The kind of a page. Page numbers start at 1.
+    pub fn get(&self, page_number: u32) -> Option<&PageKind> {
+        self.kinds.get((page_number as usize).checked_sub(1)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    /// Every page number with its kind, in page number order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &PageKind)> {
+        self.kinds
+            .iter()
+            .enumerate()
+            .map(|(idx, kind)| (idx as u32 + 1, kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageKind, StructureError};
+    use crate::database::{
+        Database,
+        freelist::FreelistError,
+        page::header::PageType,
+        test_support::{
+            btree_page, database_bytes, schema_page, table_interior_cell, table_leaf_cell,
+        },
+    };
+    use crate::util::write_varint;
+
+    fn overflow_page(next_page: u32) -> Vec<u8> {
+        let mut page = vec![0x55; 512];
+        page[0..4].copy_from_slice(&next_page.to_be_bytes());
+        page
+    }
+
+    fn free_trunk_page(leaves: &[u32]) -> Vec<u8> {
+        let mut page = vec![0; 512];
+        page[4..8].copy_from_slice(&(leaves.len() as u32).to_be_bytes());
+        for (idx, leaf) in leaves.iter().enumerate() {
+            page[8 + idx * 4..12 + idx * 4].copy_from_slice(&leaf.to_be_bytes());
+        }
+        page
+    }
+
+    /// Table `t` has an interior root on page 2 with leaves on pages 3 and 4, and the row on
+    /// page 3 overflows onto page 5. Pages 6 and 7 are free and page 8 is unused. The header
+    /// counts `free_pages` freelist pages.
+    fn database(right_most_pointer: u32, free_pages: u32) -> Database {
+        let mut overflowing = write_varint(500);
+        overflowing.extend(write_varint(1));
+        overflowing.extend([0x55; 39]);
+        overflowing.extend(5u32.to_be_bytes());
+        let mut bytes = database_bytes(
+            512,
+            vec![
+                schema_page(&[("t", 2)]),
+                btree_page(
+                    512,
+                    0,
+                    5,
+                    Some(right_most_pointer),
+                    &[table_interior_cell(3, 1)],
+                ),
+                btree_page(512, 0, 13, None, &[overflowing]),
+                btree_page(512, 0, 13, None, &[table_leaf_cell(2, &[0x02, 0x01, 0x07])]),
+                overflow_page(0),
+                free_trunk_page(&[7]),
+                vec![0; 512],
+                vec![0; 512],
+            ],
+        );
+        bytes[32..36].copy_from_slice(&6u32.to_be_bytes());
+        bytes[36..40].copy_from_slice(&free_pages.to_be_bytes());
+        Database::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn classifies_every_page() {
+        let kinds = database(4, 2).page_kinds();
+        assert!(kinds.problems.is_empty());
+        let table_leaf = PageKind::BTree {
+            page_type: PageType::LeafTable,
+            root_page: 2,
+        };
+        assert_eq!(
+            kinds
+                .iter()
+                .map(|(_, kind)| kind.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                PageKind::BTree {
+                    page_type: PageType::LeafTable,
+                    root_page: 1,
+                },
+                PageKind::BTree {
+                    page_type: PageType::InteriorTable,
+                    root_page: 2,
+                },
+                table_leaf.clone(),
+                table_leaf,
+                PageKind::Overflow { root_page: 2 },
+                PageKind::FreelistTrunk,
+                PageKind::FreelistLeaf,
+                PageKind::Unused,
+            ]
+        );
+        assert_eq!(kinds.get(5).unwrap().label(), "overflow");
+        assert_eq!(kinds.get(0), None);
+        assert_eq!(kinds.get(9), None);
+    }
+
+    #[test]
+    fn pages_reached_twice_are_reported_and_the_rest_classified() {
+        let kinds = database(3, 2).page_kinds();
+        assert!(matches!(
+            kinds.problems[..],
+            [StructureError::ReferencedTwice(3)]
+        ));
+        assert_eq!(kinds.get(5), Some(&PageKind::Overflow { root_page: 2 }));
+        assert_eq!(kinds.get(4), Some(&PageKind::Unused));
+        assert_eq!(kinds.get(6), Some(&PageKind::FreelistTrunk));
+    }
+
+    #[test]
+    fn freelist_pages_are_classified_when_the_header_miscounts_them() {
+        let kinds = database(4, 3).page_kinds();
+        assert!(matches!(
+            kinds.problems[..],
+            [StructureError::Freelist(
+                FreelistError::CountMismatch { .. }
+            )]
+        ));
+        assert_eq!(kinds.get(6), Some(&PageKind::FreelistTrunk));
+        assert_eq!(kinds.get(7), Some(&PageKind::FreelistLeaf));
+    }
+
+    #[test]
+    fn stops_at_the_btree_depth_limit() {
+        // Interior pages 2 to 24 each lead to the next through the right-most pointer
+        let mut pages = vec![schema_page(&[("t", 2)])];
+        pages.extend((3..=25).map(|child| btree_page(512, 0, 5, Some(child), &[])));
+        pages.push(btree_page(512, 0, 13, None, &[]));
+        let kinds = Database::from_bytes(database_bytes(512, pages))
+            .unwrap()
+            .page_kinds();
+        assert!(matches!(
+            kinds.problems[..],
+            [StructureError::TooDeep {
+                page_number: 22,
+                root_page: 2,
+            }]
+        ));
+        assert_eq!(
+            kinds.get(21),
+            Some(&PageKind::BTree {
+                page_type: PageType::InteriorTable,
+                root_page: 2,
+            })
+        );
+        assert_eq!(kinds.get(22), Some(&PageKind::Unused));
+    }
+}
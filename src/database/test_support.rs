@@ -0,0 +1,298 @@
+//! Helpers for laying out database images by hand in tests.
+
+use crate::{
+    database::{
+        record::Value,
+        wal::{Wal, wal_checksum},
+    },
+    util::write_varint,
+};
+
+/// Builds a 100 byte database header for `page_count` pages of `page_size` bytes.
+pub(crate) fn header_bytes(page_size: u16, page_count: u32) -> Vec<u8> {
+    let mut header = vec![0; 100];
+    header[0..16].copy_from_slice(b"SQLite format 3\0");
+    header[16..18].copy_from_slice(&page_size.to_be_bytes());
+    header[18] = 1;
+    header[19] = 1;
+    header[21] = 64;
+    header[22] = 32;
+    header[23] = 32;
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&page_count.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[96..100].copy_from_slice(&3_045_001u32.to_be_bytes());
+    header
+}
+
+/// Builds a b-tree page of `page_size` bytes whose header starts at `header_offset`, with the
+/// cells packed against the end of the page in the order given.
+pub(crate) fn btree_page(
+    page_size: usize,
+    header_offset: usize,
+    page_type: u8,
+    right_most_pointer: Option<u32>,
+    cells: &[Vec<u8>],
+) -> Vec<u8> {
+    let mut page = vec![0; page_size];
+    let header_len = if right_most_pointer.is_some() { 12 } else { 8 };
+    let mut content_start = page_size;
+    let mut offsets = Vec::new();
+    for cell in cells {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        offsets.push(content_start as u16);
+    }
+    let header = &mut page[header_offset..];
+    header[0] = page_type;
+    header[3..5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    header[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    if let Some(pointer) = right_most_pointer {
+        header[8..12].copy_from_slice(&pointer.to_be_bytes());
+    }
+    for (idx, offset) in offsets.iter().enumerate() {
+        let at = header_len + idx * 2;
+        header[at..at + 2].copy_from_slice(&offset.to_be_bytes());
+    }
+    page
+}
+
+/// Concatenates `pages` into a database file, writing the database header over the first 100
+/// bytes of page 1.
+pub(crate) fn database_bytes(page_size: u16, pages: Vec<Vec<u8>>) -> Vec<u8> {
+    let page_count = pages.len() as u32;
+    let mut bytes: Vec<u8> = pages.into_iter().flatten().collect();
+    bytes[..100].copy_from_slice(&header_bytes(page_size, page_count));
+    bytes
+}
+
+/// Encodes `values` in the record format.
+pub(crate) fn record_bytes(values: &[Value]) -> Vec<u8> {
+    let mut serial_types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial_type = match value {
+            Value::Null => 0,
+            Value::Integer(integer) => {
+                body.extend(integer.to_be_bytes());
+                6
+            }
+            Value::Float(float) => {
+                body.extend(float.to_be_bytes());
+                7
+            }
+            Value::Blob(blob) => {
+                body.extend(blob);
+                blob.len() as u64 * 2 + 12
+            }
+            Value::Text(text) => {
+                body.extend(text.as_bytes());
+                text.len() as u64 * 2 + 13
+            }
+        };
+        serial_types.extend(write_varint(serial_type));
+    }
+    // The header size counts itself; every header in these tests is under 127 bytes.
+    let mut record = write_varint(serial_types.len() as u64 + 1);
+    record.extend(serial_types);
+    record.extend(body);
+    record
+}
+
+/// A table leaf cell holding `payload` entirely on the page.
+pub(crate) fn table_leaf_cell(rowid: i64, payload: &[u8]) -> Vec<u8> {
+    let mut cell = write_varint(payload.len() as u64);
+    cell.extend(write_varint(rowid as u64));
+    cell.extend(payload);
+    cell
+}
+
+/// A table interior cell pointing at `left_child_page`.
+pub(crate) fn table_interior_cell(left_child_page: u32, rowid: i64) -> Vec<u8> {
+    let mut cell = left_child_page.to_be_bytes().to_vec();
+    cell.extend(write_varint(rowid as u64));
+    cell
+}
+
+/// An index leaf cell holding `payload` entirely on the page.
+pub(crate) fn index_leaf_cell(payload: &[u8]) -> Vec<u8> {
+    let mut cell = write_varint(payload.len() as u64);
+    cell.extend(payload);
+    cell
+}
+
+/// An index interior cell pointing at `left_child_page` and holding `payload` on the page.
+pub(crate) fn index_interior_cell(left_child_page: u32, payload: &[u8]) -> Vec<u8> {
+    let mut cell = left_child_page.to_be_bytes().to_vec();
+    cell.extend(index_leaf_cell(payload));
+    cell
+}
+
+/// A `sqlite_schema` row as a table leaf cell.
+pub(crate) fn schema_row(
+    rowid: i64,
+    object_type: &str,
+    name: &str,
+    tbl_name: &str,
+    root_page: i64,
+    sql: &str,
+) -> Vec<u8> {
+    table_leaf_cell(
+        rowid,
+        &record_bytes(&[
+            Value::Text(object_type.to_owned()),
+            Value::Text(name.to_owned()),
+            Value::Text(tbl_name.to_owned()),
+            Value::Integer(root_page),
+            Value::Text(sql.to_owned()),
+        ]),
+    )
+}
+
+/// Page 1 of a 512 byte page database, with room for the header, whose schema holds a table
+/// `name(a)` rooted at each given page.
+pub(crate) fn schema_page(tables: &[(&str, i64)]) -> Vec<u8> {
+    let rows: Vec<Vec<u8>> = tables
+        .iter()
+        .enumerate()
+        .map(|(idx, (name, root_page))| {
+            let sql = format!("CREATE TABLE {name}(a)");
+            schema_row(idx as i64 + 1, "table", name, name, *root_page, &sql)
+        })
+        .collect();
+    btree_page(512, 100, 13, None, &rows)
+}
+
+/// A 512 byte table leaf page with a row for each rowid, holding the rowid as its only column.
+pub(crate) fn rows_page(rowids: &[i64]) -> Vec<u8> {
+    let cells: Vec<Vec<u8>> = rowids
+        .iter()
+        .map(|rowid| table_leaf_cell(*rowid, &record_bytes(&[Value::Integer(*rowid)])))
+        .collect();
+    btree_page(512, 0, 13, None, &cells)
+}
+
+/// Builds a WAL with big-endian checksums and the given salts. Each frame is a page number, the
+/// database size for commit frames (zero otherwise), and the page, with its checksum chained on
+/// from the header.
+pub(crate) fn wal_bytes(
+    page_size: u32,
+    salts: [u32; 2],
+    frames: &[(u32, u32, Vec<u8>)],
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for word in [0x377f_0683, 3_007_000, page_size, 0, salts[0], salts[1]] {
+        bytes.extend(u32::to_be_bytes(word));
+    }
+    let mut checksum = wal_checksum(&bytes, true, [0, 0]);
+    bytes.extend(checksum.iter().flat_map(|word| word.to_be_bytes()));
+    for (page_number, database_size, page) in frames {
+        let mut header = Vec::new();
+        for word in [*page_number, *database_size, salts[0], salts[1]] {
+            header.extend(word.to_be_bytes());
+        }
+        checksum = wal_checksum(&header[..8], true, checksum);
+        checksum = wal_checksum(page, true, checksum);
+        header.extend(checksum.iter().flat_map(|word| word.to_be_bytes()));
+        bytes.extend(header);
+        bytes.extend(page);
+    }
+    bytes
+}
+
+/// Builds the little-endian wal-index a connection would have for `wal` after its last commit,
+/// with one reader holding a snapshot at that commit.
+pub(crate) fn wal_index_bytes(wal: &Wal) -> Vec<u8> {
+    let max_frame = wal.last_commit().unwrap_or(0);
+    let (database_size, frame_checksum) = match max_frame {
+        0 => (0, [0, 0]),
+        _ => {
+            let commit = wal.frames[max_frame as usize - 1].header;
+            (commit.database_size, commit.checksum)
+        }
+    };
+    let mut header = Vec::new();
+    for word in [3_007_000, 0, max_frame] {
+        header.extend(u32::to_le_bytes(word));
+    }
+    header.extend([1, u8::from(wal.header.big_endian_checksums)]);
+    let page_size = wal.header.page_size.get();
+    header.extend(((page_size & 0xff00) as u16 | (page_size >> 16) as u16).to_le_bytes());
+    for word in [
+        max_frame,
+        database_size,
+        frame_checksum[0],
+        frame_checksum[1],
+    ] {
+        header.extend(word.to_le_bytes());
+    }
+    for salt in wal.header.salts {
+        header.extend(salt.to_be_bytes());
+    }
+    let checksum = wal_checksum(&header, false, [0, 0]);
+    header.extend(checksum.iter().flat_map(|word| word.to_le_bytes()));
+
+    let segments = (max_frame as usize + 34).div_ceil(4096).max(1);
+    let mut bytes = vec![0; segments * 32768];
+    bytes[..48].copy_from_slice(&header);
+    bytes[48..96].copy_from_slice(&header);
+    // Nothing checkpointed, reader 1 at the last commit and the other readers unused
+    for (reader, mark) in [0, max_frame, u32::MAX, u32::MAX, u32::MAX]
+        .iter()
+        .enumerate()
+    {
+        let offset = 100 + 4 * reader;
+        bytes[offset..offset + 4].copy_from_slice(&mark.to_le_bytes());
+    }
+    for frame in &wal.frames[..max_frame as usize] {
+        // Frame numbers as if the page number arrays were one array after the index header
+        let index = frame.frame_number as usize + 33;
+        let (segment, entry) = (index / 4096, index % 4096);
+        let first_entry = if segment == 0 { 34 } else { 0 };
+        let page_offset = segment * 32768 + 4 * entry;
+        bytes[page_offset..page_offset + 4]
+            .copy_from_slice(&frame.header.page_number.to_le_bytes());
+        let mut slot = frame.header.page_number.wrapping_mul(383) as usize % 8192;
+        loop {
+            let slot_offset = segment * 32768 + 16384 + 2 * slot;
+            if bytes[slot_offset..slot_offset + 2] == [0, 0] {
+                let value = (entry - first_entry + 1) as u16;
+                bytes[slot_offset..slot_offset + 2].copy_from_slice(&value.to_le_bytes());
+                break;
+            }
+            slot = (slot + 1) % 8192;
+        }
+    }
+    bytes
+}
+
+/// Builds a rollback journal with one header, padded to `sector_size`, and a record for each
+/// page with a correct checksum.
+pub(crate) fn journal_bytes(
+    page_size: u32,
+    sector_size: u32,
+    nonce: u32,
+    initial_size: u32,
+    records: &[(u32, Vec<u8>)],
+) -> Vec<u8> {
+    let mut bytes = vec![0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+    let record_count = records.len() as u32;
+    for word in [record_count, nonce, initial_size, sector_size, page_size] {
+        bytes.extend(word.to_be_bytes());
+    }
+    bytes.resize(sector_size as usize, 0);
+    for (page_number, page) in records {
+        bytes.extend(page_number.to_be_bytes());
+        bytes.extend(page);
+        let checksum = (200..page.len())
+            .step_by(200)
+            .map(|back| page[page.len() - back])
+            .fold(nonce, |checksum, byte| {
+                checksum.wrapping_add(u32::from(byte))
+            });
+        bytes.extend(checksum.to_be_bytes());
+    }
+    bytes
+}
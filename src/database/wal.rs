@@ -0,0 +1,540 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::{
+    database::header::{PageSize, PageSizeError},
+    util::read_u32_at,
+};
+use thiserror::Error;
+
+/// Length of the header at the start of a WAL file.
+pub const WAL_HEADER_SIZE: usize = 32;
+/// Length of the header before each page in a WAL frame.
+pub const FRAME_HEADER_SIZE: usize = 24;
+/// The WAL magic number. Its lowest bit is set when checksums are computed on big-endian words.
+const WAL_MAGIC: u32 = 0x377f_0682;
+/// The only WAL format version there has ever been.
+const WAL_FORMAT_VERSION: u32 = 3_007_000;
+
+#[derive(Debug, Error)]
+pub enum WalError {
+    #[error("Encountered an IO error reading the WAL: {0}")]
+    Io(io::Error),
+    #[error("WAL files start with a {WAL_HEADER_SIZE} byte header, this one is {0} bytes")]
+    TooShort(usize),
+    #[error("WAL magic number should be 0x377f0682 or 0x377f0683, was {0:#010x}")]
+    Magic(u32),
+    #[error("WAL format version should be {WAL_FORMAT_VERSION}, was {0}")]
+    FormatVersion(u32),
+    #[error("WAL header has an invalid page size: {0}")]
+    PageSize(PageSizeError),
+    #[error("WAL header checksum is {found:08x?}, but its contents give {expected:08x?}")]
+    HeaderChecksum { expected: [u32; 2], found: [u32; 2] },
+    #[error("Frame {0} isn't a valid commit frame")]
+    NotACommit(u32),
+}
+
+/// Why a frame isn't part of the log. Readers stop at the first such frame and ignore every
+/// frame after it, so frames left over from before the WAL was last reset are expected to fail
+/// the salt check.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FrameError {
+    #[error(
+        "Frame {frame_number} has salts {found:08x?}, but the WAL header's are {expected:08x?}"
+    )]
+    SaltMismatch {
+        frame_number: u32,
+        expected: [u32; 2],
+        found: [u32; 2],
+    },
+    #[error(
+        "Frame {frame_number} has checksum {found:08x?}, but the checksum chain gives {expected:08x?}"
+    )]
+    ChecksumMismatch {
+        frame_number: u32,
+        expected: [u32; 2],
+        found: [u32; 2],
+    },
+    #[error("Frame {frame_number} is for page 0, which doesn't exist")]
+    PageNumber { frame_number: u32 },
+    #[error("Frame {frame_number} is cut off after {len} of its {frame_size} bytes")]
+    Truncated {
+        frame_number: u32,
+        len: usize,
+        frame_size: usize,
+    },
+}
+
+/// The header at the start of a WAL file.
+// https://www.sqlite.org/fileformat.html#wal_file_format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalHeader {
+    /// Whether checksums are computed on big-endian words, from the lowest bit of the magic
+    /// number.
+    pub big_endian_checksums: bool,
+    pub format_version: u32,
+    pub page_size: PageSize,
+    /// Incremented by each checkpoint that resets the WAL.
+    pub checkpoint_sequence: u32,
+    /// Random values chosen each time the WAL is reset. Every frame written since then copies
+    /// them.
+    pub salts: [u32; 2],
+    /// Checksum of the first 24 bytes of the header, which also seeds the checksum of the first
+    /// frame.
+    pub checksum: [u32; 2],
+}
+
+impl TryFrom<&[u8]> for WalHeader {
+    type Error = WalError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let bytes = value
+            .get(..WAL_HEADER_SIZE)
+            .ok_or(WalError::TooShort(value.len()))?;
+        let word = |offset: usize| read_u32_at(bytes, offset, true);
+        let magic = word(0);
+        if magic & !1 != WAL_MAGIC {
+            return Err(WalError::Magic(magic));
+        }
+        let format_version = word(4);
+        if format_version != WAL_FORMAT_VERSION {
+            return Err(WalError::FormatVersion(format_version));
+        }
+        let page_size = PageSize::from_bytes(word(8)).map_err(WalError::PageSize)?;
+        let big_endian_checksums = magic & 1 == 1;
+        let checksum = [word(24), word(28)];
+        let expected = wal_checksum(&bytes[..24], big_endian_checksums, [0, 0]);
+        if checksum != expected {
+            return Err(WalError::HeaderChecksum {
+                expected,
+                found: checksum,
+            });
+        }
+        Ok(Self {
+            big_endian_checksums,
+            format_version,
+            page_size,
+            checkpoint_sequence: word(12),
+            salts: [word(16), word(20)],
+            checksum,
+        })
+    }
+}
+
+impl WalHeader {
+    /// Encodes the header, with the checksum it was read with.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let magic = WAL_MAGIC | u32::from(self.big_endian_checksums);
+        [
+            magic,
+            self.format_version,
+            self.page_size.get(),
+            self.checkpoint_sequence,
+            self.salts[0],
+            self.salts[1],
+            self.checksum[0],
+            self.checksum[1],
+        ]
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect()
+    }
+
+    /// The header a checkpoint writes when it resets the WAL: the next checkpoint sequence and
+    /// new salts, so none of the frames already in the file are valid any more. Like SQLite,
+    /// the first salt is incremented and the second replaced with `salt`.
+    pub fn restarted(&self, salt: u32) -> Self {
+        let mut header = Self {
+            checkpoint_sequence: self.checkpoint_sequence.wrapping_add(1),
+            salts: [self.salts[0].wrapping_add(1), salt],
+            checksum: [0, 0],
+            ..self.clone()
+        };
+        header.checksum = wal_checksum(
+            &header.to_bytes()[..24],
+            header.big_endian_checksums,
+            [0, 0],
+        );
+        header
+    }
+}
+
+/// The header before each page in the WAL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The database page this frame holds a new version of.
+    pub page_number: u32,
+    /// On the last frame of a transaction, the size of the database in pages after it commits.
+    /// Zero on every other frame.
+    pub database_size: u32,
+    pub salts: [u32; 2],
+    /// The cumulative checksum of every frame up to and including this one.
+    pub checksum: [u32; 2],
+}
+
+impl FrameHeader {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let word = |offset: usize| read_u32_at(bytes, offset, true);
+        Self {
+            page_number: word(0),
+            database_size: word(4),
+            salts: [word(8), word(12)],
+            checksum: [word(16), word(20)],
+        }
+    }
+
+    /// Whether this frame ends a transaction.
+    pub fn is_commit(&self) -> bool {
+        self.database_size != 0
+    }
+}
+
+/// A frame of the WAL: a header followed by a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalFrame {
+    /// Frames are numbered from 1, in the order they were written.
+    pub frame_number: u32,
+    pub header: FrameHeader,
+}
+
+/// A write-ahead log. Writers append frames holding new versions of pages, and readers look for
+/// the newest version of a page in the WAL before falling back to the database file.
+#[derive(Debug)]
+pub struct Wal {
+    pub header: WalHeader,
+    /// The valid frames, up to the first invalid one. The last few may belong to a transaction
+    /// that never committed.
+    pub frames: Vec<WalFrame>,
+    /// Why the log ends before the end of the file, or `None` if every frame is valid.
+    pub end: Option<FrameError>,
+    bytes: Vec<u8>,
+}
+
+impl Wal {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, WalError> {
+        Self::from_bytes(fs::read(path).map_err(WalError::Io)?)
+    }
+
+    /// Parses the header and every frame header, following the checksum chain from the header
+    /// through each frame until a frame fails it.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, WalError> {
+        let header = WalHeader::try_from(bytes.as_slice())?;
+        let frame_size = FRAME_HEADER_SIZE + header.page_size.get() as usize;
+        let mut frames = Vec::new();
+        let mut end = None;
+        let mut checksum = header.checksum;
+        let mut offset = WAL_HEADER_SIZE;
+        while offset < bytes.len() {
+            let frame_number = frames.len() as u32 + 1;
+            let Some(frame) = bytes.get(offset..offset + frame_size) else {
+                end = Some(FrameError::Truncated {
+                    frame_number,
+                    len: bytes.len() - offset,
+                    frame_size,
+                });
+                break;
+            };
+            let frame_header = FrameHeader::from_bytes(frame);
+            if frame_header.salts != header.salts {
+                end = Some(FrameError::SaltMismatch {
+                    frame_number,
+                    expected: header.salts,
+                    found: frame_header.salts,
+                });
+                break;
+            }
+            if frame_header.page_number == 0 {
+                end = Some(FrameError::PageNumber { frame_number });
+                break;
+            }
+            // The checksum covers the page number and database size, then the page itself
+            checksum = wal_checksum(&frame[..8], header.big_endian_checksums, checksum);
+            checksum = wal_checksum(
+                &frame[FRAME_HEADER_SIZE..],
+                header.big_endian_checksums,
+                checksum,
+            );
+            if frame_header.checksum != checksum {
+                end = Some(FrameError::ChecksumMismatch {
+                    frame_number,
+                    expected: checksum,
+                    found: frame_header.checksum,
+                });
+                break;
+            }
+            frames.push(WalFrame {
+                frame_number,
+                header: frame_header,
+            });
+            offset += frame_size;
+        }
+        Ok(Self {
+            header,
+            frames,
+            end,
+            bytes,
+        })
+    }
+
+    /// The frame number of the last commit frame, which is where a reader's snapshot ends.
+    /// `None` if no transaction in the WAL has committed.
+    pub fn last_commit(&self) -> Option<u32> {
+        self.commits().last().map(|frame| frame.frame_number)
+    }
+
+    /// Every commit frame, oldest first. Each is a point in time a reader could have seen.
+    pub fn commits(&self) -> impl Iterator<Item = &WalFrame> {
+        self.frames.iter().filter(|frame| frame.header.is_commit())
+    }
+
+    /// The frames that belong to committed transactions.
+    pub fn committed_frames(&self) -> &[WalFrame] {
+        &self.frames[..self.last_commit().unwrap_or(0) as usize]
+    }
+
+    /// The page stored in a frame. Frame numbers start at 1.
+    pub fn frame_page(&self, frame_number: u32) -> Option<&[u8]> {
+        let frame_size = FRAME_HEADER_SIZE + self.header.page_size.get() as usize;
+        let start = WAL_HEADER_SIZE
+            + (frame_number as usize).checked_sub(1)? * frame_size
+            + FRAME_HEADER_SIZE;
+        self.bytes
+            .get(start..start + frame_size - FRAME_HEADER_SIZE)
+    }
+}
+
+/// The database as a reader sees it at one commit: the newest version of each page written at or
+/// before the commit frame comes from the WAL, and every other page from the database file.
+#[derive(Debug)]
+pub struct WalSnapshot {
+    wal: Wal,
+    commit_frame: u32,
+    database_size: u32,
+    /// The newest frame holding each page, as of the commit frame.
+    page_frames: HashMap<u32, u32>,
+}
+
+impl WalSnapshot {
+    /// The snapshot at `commit_frame`, which must be a valid commit frame.
+    pub fn new(wal: Wal, commit_frame: u32) -> Result<Self, WalError> {
+        let commit = wal
+            .frames
+            .get((commit_frame as usize).wrapping_sub(1))
+            .filter(|frame| frame.header.is_commit())
+            .ok_or(WalError::NotACommit(commit_frame))?;
+        let database_size = commit.header.database_size;
+        let page_frames = wal.frames[..commit_frame as usize]
+            .iter()
+            .map(|frame| (frame.header.page_number, frame.frame_number))
+            .collect();
+        Ok(Self {
+            wal,
+            commit_frame,
+            database_size,
+            page_frames,
+        })
+    }
+
+    /// The snapshot at the last commit, which is what a new reader would see. `None` if no
+    /// transaction in the WAL has committed.
+    pub fn latest(wal: Wal) -> Option<Self> {
+        let commit_frame = wal.last_commit()?;
+        Self::new(wal, commit_frame).ok()
+    }
+
+    pub fn wal(&self) -> &Wal {
+        &self.wal
+    }
+
+    pub fn commit_frame(&self) -> u32 {
+        self.commit_frame
+    }
+
+    /// Size of the database in pages as of the commit.
+    pub fn database_size(&self) -> u32 {
+        self.database_size
+    }
+
+    /// The frame holding the version of a page this snapshot sees, or `None` if the page comes
+    /// from the database file.
+    pub fn page_frame(&self, page_number: u32) -> Option<u32> {
+        self.page_frames.get(&page_number).copied()
+    }
+
+    /// Every page this snapshot reads from the WAL, with the frame it reads it from, in page
+    /// number order.
+    pub fn page_frames(&self) -> Vec<(u32, u32)> {
+        let mut page_frames: Vec<(u32, u32)> = self
+            .page_frames
+            .iter()
+            .map(|(page_number, frame_number)| (*page_number, *frame_number))
+            .collect();
+        page_frames.sort_unstable();
+        page_frames
+    }
+
+    /// The version of a page this snapshot sees, if it is in the WAL.
+    pub fn page(&self, page_number: u32) -> Option<&[u8]> {
+        self.wal.frame_page(self.page_frame(page_number)?)
+    }
+}
+
+/// SQLite's WAL checksum: a Fibonacci-weighted sum over pairs of 32-bit words, continuing from
+/// `initial`. `bytes` must be a multiple of 8 bytes long.
+pub fn wal_checksum(bytes: &[u8], big_endian: bool, initial: [u32; 2]) -> [u32; 2] {
+    let word = |bytes: &[u8]| read_u32_at(bytes, 0, big_endian);
+    let [mut s0, mut s1] = initial;
+    for pair in bytes.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&pair[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..])).wrapping_add(s0);
+    }
+    [s0, s1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameError, Wal, WalError, wal_checksum};
+
+    use crate::database::{header::PageSizeError, test_support::wal_bytes};
+
+    const SALTS: [u32; 2] = [0x1234_5678, 0x9abc_def0];
+
+    fn page(fill: u8) -> Vec<u8> {
+        vec![fill; 512]
+    }
+
+    /// Page 2, then page 3 committing a 3 page database, then page 2 again without a commit.
+    fn frames() -> Vec<(u32, u32, Vec<u8>)> {
+        vec![(2, 0, page(1)), (3, 3, page(2)), (2, 0, page(3))]
+    }
+
+    #[test]
+    fn parses_frames_and_finds_last_commit() {
+        let wal = Wal::from_bytes(wal_bytes(512, SALTS, &frames())).unwrap();
+        assert!(wal.header.big_endian_checksums);
+        assert_eq!(wal.header.page_size.get(), 512);
+        assert_eq!(wal.header.salts, SALTS);
+        assert_eq!(wal.end, None);
+        assert_eq!(wal.frames.len(), 3);
+        assert_eq!(wal.frames[1].header.page_number, 3);
+        assert!(wal.frames[1].header.is_commit());
+        assert!(!wal.frames[2].header.is_commit());
+        assert_eq!(wal.last_commit(), Some(2));
+        assert_eq!(wal.committed_frames().len(), 2);
+        assert_eq!(wal.frame_page(3), Some(page(3).as_slice()));
+        assert_eq!(wal.frame_page(0), None);
+        assert_eq!(wal.frame_page(4), None);
+
+        let empty = Wal::from_bytes(wal_bytes(512, SALTS, &[])).unwrap();
+        assert_eq!(empty.last_commit(), None);
+        assert!(empty.committed_frames().is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_first_invalid_frame() {
+        let frame_size = 24 + 512;
+        let mut bytes = wal_bytes(512, SALTS, &frames());
+        bytes[32 + frame_size + 100] ^= 0xff;
+        let wal = Wal::from_bytes(bytes).unwrap();
+        assert_eq!(wal.frames.len(), 1);
+        assert_eq!(wal.last_commit(), None);
+        assert!(matches!(
+            wal.end,
+            Some(FrameError::ChecksumMismatch {
+                frame_number: 2,
+                ..
+            })
+        ));
+
+        // Frames from before the WAL was reset carry the old salts
+        let mut bytes = wal_bytes(512, SALTS, &frames()[..2]);
+        bytes.extend(&wal_bytes(512, [1, 2], &frames())[32 + 2 * frame_size..]);
+        let wal = Wal::from_bytes(bytes).unwrap();
+        assert_eq!(wal.frames.len(), 2);
+        assert_eq!(
+            wal.end,
+            Some(FrameError::SaltMismatch {
+                frame_number: 3,
+                expected: SALTS,
+                found: [1, 2],
+            })
+        );
+
+        let mut zero_page = frames();
+        zero_page[1].0 = 0;
+        let wal = Wal::from_bytes(wal_bytes(512, SALTS, &zero_page)).unwrap();
+        assert_eq!(wal.frames.len(), 1);
+        assert_eq!(wal.end, Some(FrameError::PageNumber { frame_number: 2 }));
+
+        let mut bytes = wal_bytes(512, SALTS, &frames());
+        bytes.truncate(bytes.len() - 10);
+        let wal = Wal::from_bytes(bytes).unwrap();
+        assert_eq!(wal.last_commit(), Some(2));
+        assert_eq!(
+            wal.end,
+            Some(FrameError::Truncated {
+                frame_number: 3,
+                len: frame_size - 10,
+                frame_size,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let header = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut bytes = wal_bytes(512, SALTS, &[]);
+            edit(&mut bytes);
+            // Keep the checksum right so only the edited field is wrong
+            let checksum = wal_checksum(&bytes[..24], true, [0, 0]);
+            bytes[24..28].copy_from_slice(&checksum[0].to_be_bytes());
+            bytes[28..32].copy_from_slice(&checksum[1].to_be_bytes());
+            Wal::from_bytes(bytes)
+        };
+        assert!(matches!(
+            header(&|bytes| bytes[3] = 0x84),
+            Err(WalError::Magic(0x377f_0684))
+        ));
+        assert!(matches!(
+            header(&|bytes| bytes[7] = 0),
+            Err(WalError::FormatVersion(3_006_976))
+        ));
+        assert!(matches!(
+            header(&|bytes| bytes[8..12].copy_from_slice(&1000u32.to_be_bytes())),
+            Err(WalError::PageSize(PageSizeError::InvalidBytes(1000)))
+        ));
+        let mut bytes = wal_bytes(512, SALTS, &[]);
+        bytes[12] = 1;
+        assert!(matches!(
+            Wal::from_bytes(bytes),
+            Err(WalError::HeaderChecksum { .. })
+        ));
+        assert!(matches!(
+            Wal::from_bytes(vec![0; 20]),
+            Err(WalError::TooShort(20))
+        ));
+    }
+
+    #[test]
+    fn restarted_headers_invalidate_old_frames() {
+        let bytes = wal_bytes(512, SALTS, &frames());
+        let wal = Wal::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(wal.header.to_bytes(), bytes[..32]);
+
+        let restarted = wal.header.restarted(42);
+        assert_eq!(restarted.checkpoint_sequence, 1);
+        assert_eq!(restarted.salts, [SALTS[0] + 1, 42]);
+        let mut bytes = bytes;
+        bytes[..32].copy_from_slice(&restarted.to_bytes());
+        let wal = Wal::from_bytes(bytes).unwrap();
+        assert_eq!(wal.header, restarted);
+        assert!(wal.frames.is_empty());
+    }
+
+    #[test]
+    fn little_endian_checksums() {
+        let words = [1u32, 2, 3, 4];
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        // s0 = 1, s1 = 2 + 1, then s0 = 1 + 3 + 3, s1 = 3 + 4 + 7
+        assert_eq!(wal_checksum(&bytes, false, [0, 0]), [7, 14]);
+    }
+}
@@ -0,0 +1,515 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    database::{
+        header::PageSize,
+        wal::{Wal, wal_checksum},
+    },
+    util::{read_u16_at, read_u32_at},
+};
+use thiserror::Error;
+
+/// Length of one copy of the index header.
+const INDEX_HEADER_SIZE: usize = 48;
+/// Length of the two index header copies and the checkpoint information that follows them.
+const WAL_INDEX_HEADER_SIZE: usize = 136;
+/// The only wal-index format version there has ever been.
+const WAL_INDEX_VERSION: u32 = 3_007_000;
+/// The file is split into segments of this many bytes, each holding a page number array for a
+/// run of frames and a hash table over it.
+const SEGMENT_SIZE: usize = 32768;
+/// Page numbers in a segment's array. The first segment loses some to the index header.
+const SEGMENT_PAGES: usize = 4096;
+const FIRST_SEGMENT_PAGES: usize = SEGMENT_PAGES - WAL_INDEX_HEADER_SIZE / 4;
+/// Slots in a segment's hash table, twice the number of pages so probe chains stay short.
+const HASH_SLOTS: usize = 8192;
+/// Read marks a reader can hold. Reader 0 always reads the database file without the WAL.
+const READERS: usize = 5;
+/// The read mark of a reader slot no connection has claimed.
+const READ_MARK_NOT_USED: u32 = 0xffff_ffff;
+
+#[derive(Debug, Error)]
+pub enum WalIndexError {
+    #[error("Encountered an IO error reading the wal-index: {0}")]
+    Io(io::Error),
+    #[error(
+        "The wal-index starts with a {WAL_INDEX_HEADER_SIZE} byte header, this one is {0} bytes"
+    )]
+    TooShort(usize),
+}
+
+/// A way the wal-index is damaged, stale, or doesn't describe the WAL next to it.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum WalIndexProblem {
+    #[error("The two copies of the index header differ, so a writer was interrupted updating it")]
+    HeaderCopiesDiffer,
+    #[error("The index header was never initialized")]
+    NotInitialized,
+    #[error("Index header checksum is {found:08x?}, but its contents give {expected:08x?}")]
+    HeaderChecksum { expected: [u32; 2], found: [u32; 2] },
+    #[error("Wal-index version should be {WAL_INDEX_VERSION}, was {0}")]
+    Version(u32),
+    #[error("Index header has an invalid page size: {0}")]
+    PageSize(u16),
+    #[error("The index says pages are {index} bytes, but the WAL has {wal} byte pages")]
+    PageSizeMismatch { index: PageSize, wal: PageSize },
+    #[error("The index and the WAL disagree about the byte order of checksums")]
+    ChecksumByteOrder,
+    #[error("The index has salts {index:08x?}, but the WAL header's are {wal:08x?}")]
+    Salts { index: [u32; 2], wal: [u32; 2] },
+    #[error("The index ends the log at frame {index}, but the WAL's last commit is frame {wal}")]
+    MaxFrame { index: u32, wal: u32 },
+    #[error("The index says the database has {index} pages, but the last commit says {wal}")]
+    DatabaseSize { index: u32, wal: u32 },
+    #[error("The index has frame checksum {index:08x?}, but the last commit frame's is {wal:08x?}")]
+    FrameChecksum { index: [u32; 2], wal: [u32; 2] },
+    #[error(
+        "{backfilled} frames are marked as checkpointed, but the log ends at frame {max_frame}"
+    )]
+    Backfill { backfilled: u32, max_frame: u32 },
+    #[error("Reader {reader} has read mark {mark}, past the end of the log at frame {max_frame}")]
+    ReadMark {
+        reader: usize,
+        mark: u32,
+        max_frame: u32,
+    },
+    #[error("The index records frame {frame_number} as page {index:?}, but the WAL has page {wal}")]
+    FramePage {
+        frame_number: u32,
+        index: Option<u32>,
+        wal: u32,
+    },
+    #[error("Frame {frame_number} can't be found from page {page_number} through the hash table")]
+    Unreachable { frame_number: u32, page_number: u32 },
+}
+
+/// One copy of the index header. Writers update both copies, the second first, and readers
+/// only trust them when they match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalIndexHeader {
+    pub version: u32,
+    /// Incremented by every transaction that commits.
+    pub change_counter: u32,
+    pub initialized: bool,
+    /// Whether the WAL's checksums are computed on big-endian words.
+    pub big_endian_checksums: bool,
+    /// Encoded like the database header's, so 65536 is stored as 1.
+    pub page_size: u16,
+    /// The last commit frame readers should use. Frames after it are ignored.
+    pub max_frame: u32,
+    /// Size of the database in pages as of `max_frame`.
+    pub database_size: u32,
+    /// The checksum of frame `max_frame`, which the next frame's checksum continues from.
+    pub frame_checksum: [u32; 2],
+    /// The WAL header's salts.
+    pub salts: [u32; 2],
+    /// Checksum of the first 40 bytes of this header.
+    pub checksum: [u32; 2],
+}
+
+impl WalIndexHeader {
+    fn from_bytes(bytes: &[u8], big_endian: bool) -> Self {
+        let word = |offset: usize| read_u32_at(bytes, offset, big_endian);
+        Self {
+            version: word(0),
+            change_counter: word(8),
+            initialized: bytes[12] != 0,
+            big_endian_checksums: bytes[13] != 0,
+            page_size: read_u16_at(bytes, 14, big_endian),
+            max_frame: word(16),
+            database_size: word(20),
+            frame_checksum: [word(24), word(28)],
+            // Copied byte for byte from the WAL header, so always big-endian
+            salts: [read_u32_at(bytes, 32, true), read_u32_at(bytes, 36, true)],
+            checksum: [word(40), word(44)],
+        }
+    }
+}
+
+/// The checkpoint and reader state after the index headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointInfo {
+    /// Frames already copied into the database file by a checkpoint.
+    pub backfilled: u32,
+    /// The `max_frame` each reader slot's snapshot ends at, or `None` if no connection has
+    /// claimed the slot. A checkpoint can't copy frames past the smallest mark in use.
+    pub read_marks: [Option<u32>; READERS],
+    /// Frames a checkpoint started copying, which can be more than it finished.
+    pub backfill_attempted: u32,
+}
+
+/// The wal-index, SQLite's `-shm` file: shared memory that lets connections find the newest frame
+/// for a page without scanning the WAL.
+///
+/// It is written in the byte order of the machine that wrote it, and only describes the WAL
+/// while a connection has the database open. SQLite rebuilds it from the WAL when the first
+/// connection opens the database. The reader and writer locks are POSIX advisory locks on bytes
+/// 120 to 127 of the file rather than anything stored in it.
+// https://www.sqlite.org/walformat.html#the_wal_index_file_format
+#[derive(Debug)]
+pub struct WalIndex {
+    /// Whether the index was written by a big-endian machine.
+    pub big_endian: bool,
+    pub headers: [WalIndexHeader; 2],
+    pub checkpoint_info: CheckpointInfo,
+    bytes: Vec<u8>,
+}
+
+impl WalIndex {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, WalIndexError> {
+        Self::from_bytes(fs::read(path).map_err(WalIndexError::Io)?)
+    }
+
+    /// Decodes the headers, guessing the byte order from the version number. An index whose
+    /// version is wrong both ways is read as little-endian.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, WalIndexError> {
+        if bytes.len() < WAL_INDEX_HEADER_SIZE {
+            return Err(WalIndexError::TooShort(bytes.len()));
+        }
+        let big_endian = read_u32_at(&bytes, 0, false) != WAL_INDEX_VERSION
+            && read_u32_at(&bytes, 0, true) == WAL_INDEX_VERSION;
+        let headers = [
+            WalIndexHeader::from_bytes(&bytes[..INDEX_HEADER_SIZE], big_endian),
+            WalIndexHeader::from_bytes(
+                &bytes[INDEX_HEADER_SIZE..2 * INDEX_HEADER_SIZE],
+                big_endian,
+            ),
+        ];
+        let word = |offset: usize| read_u32_at(&bytes, 2 * INDEX_HEADER_SIZE + offset, big_endian);
+        let read_marks = std::array::from_fn(|reader| {
+            Some(word(4 + 4 * reader)).filter(|mark| *mark != READ_MARK_NOT_USED)
+        });
+        let checkpoint_info = CheckpointInfo {
+            backfilled: word(0),
+            read_marks,
+            backfill_attempted: word(32),
+        };
+        Ok(Self {
+            big_endian,
+            headers,
+            checkpoint_info,
+            bytes,
+        })
+    }
+
+    /// The index header readers use. Only trustworthy if [`Self::header_problems`] is empty.
+    pub fn header(&self) -> &WalIndexHeader {
+        &self.headers[0]
+    }
+
+    /// Everything that would make a reader distrust the index header and rebuild the index.
+    pub fn header_problems(&self) -> Vec<WalIndexProblem> {
+        let mut problems = Vec::new();
+        let header = self.header();
+        if self.bytes[..INDEX_HEADER_SIZE] != self.bytes[INDEX_HEADER_SIZE..2 * INDEX_HEADER_SIZE] {
+            problems.push(WalIndexProblem::HeaderCopiesDiffer);
+        }
+        if !header.initialized {
+            problems.push(WalIndexProblem::NotInitialized);
+        }
+        let expected = wal_checksum(&self.bytes[..40], self.big_endian, [0, 0]);
+        if header.checksum != expected {
+            problems.push(WalIndexProblem::HeaderChecksum {
+                expected,
+                found: header.checksum,
+            });
+        }
+        if header.version != WAL_INDEX_VERSION {
+            problems.push(WalIndexProblem::Version(header.version));
+        }
+        if PageSize::try_from(header.page_size).is_err() {
+            problems.push(WalIndexProblem::PageSize(header.page_size));
+        }
+        problems
+    }
+
+    /// The page number the index records for a frame, or `None` if the file doesn't reach that
+    /// far. Frame numbers start at 1.
+    pub fn frame_page(&self, frame_number: u32) -> Option<u32> {
+        let (segment, index) = segment_of(frame_number)?;
+        let offset = segment * SEGMENT_SIZE
+            + if segment == 0 {
+                WAL_INDEX_HEADER_SIZE
+            } else {
+                0
+            }
+            + 4 * index;
+        self.bytes
+            .get(offset..offset + 4)
+            .map(|bytes| read_u32_at(bytes, 0, self.big_endian))
+    }
+
+    /// The newest frame at or before `max_frame` that holds a page, looked up through the hash
+    /// tables the way a reader does, from the newest segment back.
+    pub fn find_frame(&self, page_number: u32, max_frame: u32) -> Option<u32> {
+        let (last_segment, _) = segment_of(max_frame)?;
+        (0..=last_segment).rev().find_map(|segment| {
+            let first_frame = segment_first_frame(segment);
+            let mut slot = hash(page_number);
+            let mut newest = None;
+            // Every slot is probed at most once, even if the table is full
+            for _ in 0..HASH_SLOTS {
+                let offset = segment * SEGMENT_SIZE + SEGMENT_PAGES * 4 + 2 * slot;
+                let entry = read_u16_at(self.bytes.get(offset..offset + 2)?, 0, self.big_endian);
+                if entry == 0 {
+                    break;
+                }
+                let frame_number = first_frame + u32::from(entry) - 1;
+                if frame_number <= max_frame && self.frame_page(frame_number) == Some(page_number) {
+                    newest = newest.max(Some(frame_number));
+                }
+                slot = (slot + 1) % HASH_SLOTS;
+            }
+            newest
+        })
+    }
+
+    /// Checks the index against a fresh scan of the WAL: the headers, the reader state, and that
+    /// the hash tables lead to every frame up to the index's last commit.
+    pub fn check(&self, wal: &Wal) -> Vec<WalIndexProblem> {
+        let mut problems = self.header_problems();
+        let header = self.header();
+        if let Ok(page_size) = PageSize::try_from(header.page_size)
+            && page_size != wal.header.page_size
+        {
+            problems.push(WalIndexProblem::PageSizeMismatch {
+                index: page_size,
+                wal: wal.header.page_size,
+            });
+        }
+        if header.big_endian_checksums != wal.header.big_endian_checksums {
+            problems.push(WalIndexProblem::ChecksumByteOrder);
+        }
+        if header.salts != wal.header.salts {
+            problems.push(WalIndexProblem::Salts {
+                index: header.salts,
+                wal: wal.header.salts,
+            });
+        }
+        let max_frame = header.max_frame;
+        let last_commit = wal.last_commit().unwrap_or(0);
+        if max_frame != last_commit {
+            problems.push(WalIndexProblem::MaxFrame {
+                index: max_frame,
+                wal: last_commit,
+            });
+        } else if let Some(commit) = wal.frames.get((max_frame as usize).wrapping_sub(1)) {
+            if header.database_size != commit.header.database_size {
+                problems.push(WalIndexProblem::DatabaseSize {
+                    index: header.database_size,
+                    wal: commit.header.database_size,
+                });
+            }
+            if header.frame_checksum != commit.header.checksum {
+                problems.push(WalIndexProblem::FrameChecksum {
+                    index: header.frame_checksum,
+                    wal: commit.header.checksum,
+                });
+            }
+        }
+
+        let info = &self.checkpoint_info;
+        if info.backfilled > max_frame {
+            problems.push(WalIndexProblem::Backfill {
+                backfilled: info.backfilled,
+                max_frame,
+            });
+        }
+        for (reader, mark) in info.read_marks.iter().enumerate() {
+            if let Some(mark) = *mark
+                && mark > max_frame
+            {
+                problems.push(WalIndexProblem::ReadMark {
+                    reader,
+                    mark,
+                    max_frame,
+                });
+            }
+        }
+
+        for frame in wal.frames.iter().take(max_frame as usize) {
+            let frame_number = frame.frame_number;
+            let page_number = frame.header.page_number;
+            let index = self.frame_page(frame_number);
+            if index != Some(page_number) {
+                problems.push(WalIndexProblem::FramePage {
+                    frame_number,
+                    index,
+                    wal: page_number,
+                });
+            } else if self.find_frame(page_number, frame_number) != Some(frame_number) {
+                problems.push(WalIndexProblem::Unreachable {
+                    frame_number,
+                    page_number,
+                });
+            }
+        }
+        problems
+    }
+}
+
+/// The segment holding a frame's page number, and its index in that segment's array.
+fn segment_of(frame_number: u32) -> Option<(usize, usize)> {
+    let index = (frame_number as usize).checked_sub(1)?;
+    Some(match index.checked_sub(FIRST_SEGMENT_PAGES) {
+        None => (0, index),
+        Some(index) => (index / SEGMENT_PAGES + 1, index % SEGMENT_PAGES),
+    })
+}
+
+/// The frame number of the first page number in a segment's array.
+fn segment_first_frame(segment: usize) -> u32 {
+    match segment {
+        0 => 1,
+        _ => (FIRST_SEGMENT_PAGES + (segment - 1) * SEGMENT_PAGES + 1) as u32,
+    }
+}
+
+/// The hash table slot a page number's probe starts at.
+fn hash(page_number: u32) -> usize {
+    page_number.wrapping_mul(383) as usize % HASH_SLOTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WalIndex, WalIndexError, WalIndexProblem};
+    use crate::database::{
+        test_support::{wal_bytes, wal_index_bytes},
+        wal::Wal,
+    };
+
+    const SALTS: [u32; 2] = [0x1234_5678, 0x9abc_def0];
+
+    /// Pages 2 and 3 commit at frame 2, page 2 again at frame 4, and frame 5 never commits.
+    fn frames() -> Vec<(u32, u32, Vec<u8>)> {
+        [(2, 0), (3, 3), (2, 0), (2, 3), (4, 0)]
+            .into_iter()
+            .enumerate()
+            .map(|(fill, (page_number, database_size))| {
+                (page_number, database_size, vec![fill as u8; 512])
+            })
+            .collect()
+    }
+
+    fn wal() -> Wal {
+        Wal::from_bytes(wal_bytes(512, SALTS, &frames())).unwrap()
+    }
+
+    #[test]
+    fn decodes_the_index_of_a_wal() {
+        let wal = wal();
+        let index = WalIndex::from_bytes(wal_index_bytes(&wal)).unwrap();
+        assert!(!index.big_endian);
+        assert_eq!(index.headers[0], index.headers[1]);
+        let header = index.header();
+        assert_eq!(header.max_frame, 4);
+        assert_eq!(header.database_size, 3);
+        assert_eq!(header.page_size, 512);
+        assert_eq!(header.salts, SALTS);
+        assert_eq!(header.frame_checksum, wal.frames[3].header.checksum);
+        assert_eq!(index.checkpoint_info.backfilled, 0);
+        assert_eq!(
+            index.checkpoint_info.read_marks,
+            [Some(0), Some(4), None, None, None]
+        );
+
+        assert_eq!(index.frame_page(3), Some(2));
+        assert_eq!(index.frame_page(0), None);
+        assert_eq!(index.find_frame(2, 4), Some(4));
+        assert_eq!(index.find_frame(2, 2), Some(1));
+        assert_eq!(index.find_frame(3, 4), Some(2));
+        assert_eq!(index.find_frame(3, 1), None);
+        assert_eq!(index.find_frame(4, 5), None);
+        assert_eq!(index.check(&wal), vec![]);
+
+        assert!(matches!(
+            WalIndex::from_bytes(vec![0; 100]),
+            Err(WalIndexError::TooShort(100))
+        ));
+    }
+
+    #[test]
+    fn finds_frames_past_the_first_segment() {
+        let frames: Vec<(u32, u32, Vec<u8>)> = (1..=4100)
+            .map(|frame| {
+                (
+                    frame % 7 + 1,
+                    if frame == 4100 { 7 } else { 0 },
+                    vec![0; 512],
+                )
+            })
+            .collect();
+        let wal = Wal::from_bytes(wal_bytes(512, SALTS, &frames)).unwrap();
+        let index = WalIndex::from_bytes(wal_index_bytes(&wal)).unwrap();
+        assert_eq!(index.frame_page(4062), Some(4062 % 7 + 1));
+        assert_eq!(index.frame_page(4063), Some(4063 % 7 + 1));
+        assert_eq!(index.find_frame(1, 4100), Some(4095));
+        assert_eq!(index.find_frame(1, 4062), Some(4060));
+        assert_eq!(index.check(&wal), vec![]);
+    }
+
+    #[test]
+    fn reports_indexes_that_disagree_with_the_wal() {
+        let wal = wal();
+        let bytes = wal_index_bytes(&wal);
+
+        // A writer that stopped between updating the two copies
+        let mut torn = bytes.clone();
+        torn[48 + 16] = 2;
+        let index = WalIndex::from_bytes(torn).unwrap();
+        assert_eq!(index.check(&wal), vec![WalIndexProblem::HeaderCopiesDiffer]);
+
+        let mut damaged = bytes.clone();
+        damaged[20] = 9;
+        damaged[48 + 20] = 9;
+        let index = WalIndex::from_bytes(damaged).unwrap();
+        assert!(matches!(
+            index.check(&wal)[..],
+            [
+                WalIndexProblem::HeaderChecksum { .. },
+                WalIndexProblem::DatabaseSize { index: 9, wal: 3 },
+            ]
+        ));
+
+        // An index from before the last transaction committed
+        let older = Wal::from_bytes(wal_bytes(512, SALTS, &frames()[..2])).unwrap();
+        let index = WalIndex::from_bytes(wal_index_bytes(&older)).unwrap();
+        assert_eq!(
+            index.check(&wal),
+            vec![WalIndexProblem::MaxFrame { index: 2, wal: 4 }]
+        );
+
+        let mut hashes = bytes.clone();
+        hashes[136 + 4..136 + 8].copy_from_slice(&5u32.to_le_bytes());
+        hashes[16384..].fill(0);
+        hashes[96 + 8..96 + 12].copy_from_slice(&7u32.to_le_bytes());
+        let index = WalIndex::from_bytes(hashes).unwrap();
+        assert_eq!(
+            index.check(&wal),
+            vec![
+                WalIndexProblem::ReadMark {
+                    reader: 1,
+                    mark: 7,
+                    max_frame: 4,
+                },
+                WalIndexProblem::Unreachable {
+                    frame_number: 1,
+                    page_number: 2,
+                },
+                WalIndexProblem::FramePage {
+                    frame_number: 2,
+                    index: Some(5),
+                    wal: 3,
+                },
+                WalIndexProblem::Unreachable {
+                    frame_number: 3,
+                    page_number: 2,
+                },
+                WalIndexProblem::Unreachable {
+                    frame_number: 4,
+                    page_number: 2,
+                },
+            ]
+        );
+    }
+}
@@ -1,9 +1,8 @@
-mod database;
-mod ui;
-mod util;
-
-use ui::cli::start_cli;
+use sqlite_clone::ui::cli::start_cli;
 
 fn main() {
-    let _ = start_cli();
+    if let Err(err) = start_cli() {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
 }
@@ -1,13 +1,12 @@
 use clap::Parser;
-use std::{fs, io};
+use std::io;
 use thiserror::Error;
 
-use super::main_panel::start_ui;
+use super::main_panel::{UiError, start_ui};
 
 pub fn start_cli() -> Result<(), CliError> {
     let args = Args::parse();
-    start_ui(args);
-    Ok(())
+    start_ui(args).map_err(CliError::Ui)
 }
 
 /// A file explorer to visualize a SQLite database.
@@ -16,10 +15,16 @@ pub fn start_cli() -> Result<(), CliError> {
 pub struct Args {
     /// Path to the SQLite database file.
     pub filepath: String,
+    /// Show the database as it was when this WAL frame committed, rather than as of the last
+    /// commit.
+    #[arg(long)]
+    pub as_of: Option<u32>,
 }
 
 #[derive(Error, Debug)]
 pub enum CliError {
     #[error("Encountered an IO Error ")]
     IoError { filepath: String, err: io::Error },
+    #[error("Encountered an error in the UI: {0}")]
+    Ui(UiError),
 }
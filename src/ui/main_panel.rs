@@ -1,24 +1,66 @@
-use std::{fs, io};
+use std::{cell::OnceCell, io};
 
 use ratatui::{
     DefaultTerminal, Frame,
-    crossterm::event::{self, Event},
+    crossterm::event::{self, Event, KeyCode},
     style::Style,
     widgets::Widget,
 };
 use thiserror::Error;
 
-use crate::database::{Database, DatabaseReadError, header::FileFormatVersion};
+use crate::database::{
+    Database, DatabaseReadError,
+    header::FileFormatVersion,
+    schema::{Schema, SchemaError},
+    structure::PageKinds,
+};
 
 use super::cli::Args;
 
 pub struct MainPanel {
     database: Database,
+    schema: Result<Schema, SchemaError>,
+    /// Classifying pages reads every page in the file, so it waits until the user asks for it.
+    page_kinds: OnceCell<PageKinds>,
+    show_page_kinds: bool,
 }
 
 impl MainPanel {
     fn new(database: Database) -> Self {
-        Self { database }
+        let schema = database.schema();
+        Self {
+            database,
+            schema,
+            page_kinds: OnceCell::new(),
+            show_page_kinds: false,
+        }
+    }
+
+    /// How many pages there are of each kind, like "3 table leaf, 1 overflow".
+    fn page_kind_summary(&self) -> String {
+        if !self.show_page_kinds {
+            return "press p to classify every page".to_owned();
+        }
+        let page_kinds = self.page_kinds.get_or_init(|| self.database.page_kinds());
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for (_, kind) in page_kinds.iter() {
+            match counts.iter_mut().find(|(label, _)| *label == kind.label()) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((kind.label(), 1)),
+            }
+        }
+        let summary = counts
+            .iter()
+            .map(|(label, count)| format!("{count} {label}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        match page_kinds.problems.first() {
+            Some(problem) => format!(
+                "{summary} ({} problems, the first: {problem})",
+                page_kinds.problems.len()
+            ),
+            None => summary,
+        }
     }
 }
 
@@ -31,8 +73,12 @@ impl Widget for &MainPanel {
             area.x,
             area.y,
             format!(
-                "there are {} pages",
-                self.database.header.database_size_in_pages
+                "there are {} pages{}",
+                self.database.header.database_size_in_pages,
+                self.database
+                    .wal()
+                    .map(|wal| format!(" as of WAL commit frame {}", wal.commit_frame()))
+                    .unwrap_or_default()
             ),
             Style::default(),
         );
@@ -64,6 +110,37 @@ impl Widget for &MainPanel {
             ),
             Style::default(),
         );
+        buf.set_string(
+            area.x,
+            area.y + 4,
+            format!("pages: {}", self.page_kind_summary()),
+            Style::default(),
+        );
+        match &self.schema {
+            Ok(schema) => {
+                let rows = usize::from(area.height.saturating_sub(5));
+                for (idx, object) in schema.objects.iter().take(rows).enumerate() {
+                    let root_page = object
+                        .root_page
+                        .map(|page| format!(" (root page {page})"))
+                        .unwrap_or_default();
+                    buf.set_string(
+                        area.x,
+                        area.y + 5 + idx as u16,
+                        format!("{:?} {}{}", object.object_type, object.name, root_page),
+                        Style::default(),
+                    );
+                }
+            }
+            Err(err) => {
+                buf.set_string(
+                    area.x,
+                    area.y + 5,
+                    format!("couldn't read the schema: {err}"),
+                    Style::default(),
+                );
+            }
+        }
     }
 }
 
@@ -75,15 +152,21 @@ pub fn start_ui(args: Args) -> Result<(), UiError> {
 }
 
 pub fn run(mut terminal: DefaultTerminal, args: Args) -> Result<(), UiError> {
-    let db_bytes = fs::read(args.filepath.clone()).map_err(UiError::IoError)?;
-    let database = Database::from_bytes(db_bytes).map_err(UiError::DatabaseReadError)?;
-    let panel = MainPanel::new(database);
+    let database = match args.as_of {
+        Some(commit_frame) => Database::open_as_of(&args.filepath, commit_frame),
+        None => Database::open(&args.filepath),
+    }
+    .map_err(UiError::DatabaseReadError)?;
+    let mut panel = MainPanel::new(database);
     loop {
         terminal
             .draw(|frame| render(frame, &panel))
             .map_err(UiError::IoError)?;
-        if matches!(event::read().map_err(UiError::IoError)?, Event::Key(_)) {
-            break Ok(());
+        if let Event::Key(key) = event::read().map_err(UiError::IoError)? {
+            match key.code {
+                KeyCode::Char('p') => panel.show_page_kinds = true,
+                _ => break Ok(()),
+            }
         }
     }
 }
@@ -10,6 +10,13 @@ pub enum DecodeError {
         num_bytes_recieved: usize,
         item_parsed: String,
     },
+    #[error(
+        "Tried to parse a varint for {item_parsed} out of {num_bytes_recieved} bytes, but it ran past the end"
+    )]
+    TruncatedVarint {
+        num_bytes_recieved: usize,
+        item_parsed: String,
+    },
 }
 
 pub(crate) fn get_u16_from_bytes(bytes: &[u8], item: &str) -> Result<u16, DecodeError> {
@@ -32,6 +39,86 @@ pub(crate) fn get_u32_from_bytes(bytes: &[u8], item: &str) -> Result<u32, Decode
     )?))
 }
 
+/// Reads the 2 byte integer at `offset` in either byte order. Callers check lengths up front, so
+/// this panics if `bytes` is too short.
+pub(crate) fn read_u16_at(bytes: &[u8], offset: usize, big_endian: bool) -> u16 {
+    let bytes = [bytes[offset], bytes[offset + 1]];
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+/// Reads the 4 byte integer at `offset` in either byte order. Callers check lengths up front, so
+/// this panics if `bytes` is too short.
+pub(crate) fn read_u32_at(bytes: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let bytes = [
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ];
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Reads a SQLite varint from the start of `bytes`, returning the value and the number of bytes
+/// it occupied.
+///
+/// A varint is 1-9 bytes, big-endian, with the high bit of each of the first eight bytes set when
+/// another byte follows. The ninth byte, if reached, contributes all eight of its bits.
+pub fn read_varint(bytes: &[u8], item: &str) -> Result<(u64, usize), DecodeError> {
+    let mut value: u64 = 0;
+    for (idx, byte) in bytes.iter().take(9).enumerate() {
+        if idx == 8 {
+            return Ok(((value << 8) | u64::from(*byte), 9));
+        }
+        value = (value << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            return Ok((value, idx + 1));
+        }
+    }
+    Err(DecodeError::TruncatedVarint {
+        num_bytes_recieved: bytes.len(),
+        item_parsed: item.to_owned(),
+    })
+}
+
+/// Number of bytes `write_varint` uses to encode `value`.
+pub fn varint_len(value: u64) -> usize {
+    if value > 0x00ff_ffff_ffff_ffff {
+        return 9;
+    }
+    let mut len = 1;
+    while value >> (7 * len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+/// Encodes `value` as a SQLite varint using the fewest bytes possible.
+pub fn write_varint(value: u64) -> Vec<u8> {
+    let len = varint_len(value);
+    if len == 9 {
+        // The last byte carries a full eight bits, leaving 56 for the first eight bytes.
+        let mut bytes: Vec<u8> = (0..8)
+            .map(|idx| ((value >> (8 + 7 * (7 - idx))) as u8 & 0x7f) | 0x80)
+            .collect();
+        bytes.push(value as u8);
+        return bytes;
+    }
+    (0..len)
+        .map(|idx| {
+            let group = (value >> (7 * (len - 1 - idx))) as u8 & 0x7f;
+            if idx == len - 1 { group } else { group | 0x80 }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use crate::util::DecodeError;
@@ -70,4 +157,98 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn read_varint() {
+        assert_eq!(super::read_varint(&[0x00], "test"), Ok((0, 1)));
+        assert_eq!(super::read_varint(&[0x7f], "test"), Ok((127, 1)));
+        assert_eq!(super::read_varint(&[0x81, 0x00], "test"), Ok((128, 2)));
+        assert_eq!(super::read_varint(&[0x87, 0x68], "test"), Ok((1000, 2)));
+        // Trailing bytes after the varint are left alone.
+        assert_eq!(super::read_varint(&[0x05, 0xff, 0xff], "test"), Ok((5, 1)));
+        assert_eq!(
+            super::read_varint(&[], "test"),
+            Err(DecodeError::TruncatedVarint {
+                num_bytes_recieved: 0,
+                item_parsed: "test".to_owned(),
+            })
+        );
+        assert_eq!(
+            super::read_varint(&[0x81, 0x80], "test"),
+            Err(DecodeError::TruncatedVarint {
+                num_bytes_recieved: 2,
+                item_parsed: "test".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn read_nine_byte_varint() {
+        // The ninth byte contributes all 8 bits, including its high bit.
+        assert_eq!(super::read_varint(&[0xff; 9], "test"), Ok((u64::MAX, 9)));
+        assert_eq!(
+            super::read_varint(
+                &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01],
+                "test"
+            ),
+            Ok((1, 9))
+        );
+        assert_eq!(
+            super::read_varint(
+                &[0x81, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00],
+                "test"
+            ),
+            Ok((1 << 57, 9))
+        );
+        // A tenth byte is never consumed.
+        assert_eq!(super::read_varint(&[0xff; 10], "test"), Ok((u64::MAX, 9)));
+        // Negative rowids are stored as their two's complement and always take nine bytes.
+        assert_eq!(
+            super::read_varint(&[0xff; 9], "test").map(|(value, _)| value as i64),
+            Ok(-1)
+        );
+        for len in 1..9 {
+            assert_eq!(
+                super::read_varint(&[0xff; 9][..len], "test"),
+                Err(DecodeError::TruncatedVarint {
+                    num_bytes_recieved: len,
+                    item_parsed: "test".to_owned(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn write_varint() {
+        assert_eq!(super::write_varint(0), vec![0x00]);
+        assert_eq!(super::write_varint(127), vec![0x7f]);
+        assert_eq!(super::write_varint(128), vec![0x81, 0x00]);
+        assert_eq!(super::write_varint(1000), vec![0x87, 0x68]);
+        assert_eq!(super::write_varint(u64::MAX), vec![0xff; 9]);
+        assert_eq!(
+            super::write_varint(1 << 57),
+            vec![0x81, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]
+        );
+    }
+
+    #[test]
+    fn varint_round_trip_at_length_boundaries() {
+        // Every value on either side of a 7-bit boundary, plus the 8/9 byte boundary at 2^56.
+        for bits in 1..64 {
+            for value in [(1u64 << bits) - 1, 1u64 << bits, (1u64 << bits) + 1] {
+                let bytes = super::write_varint(value);
+                assert_eq!(bytes.len(), super::varint_len(value));
+                assert_eq!(super::read_varint(&bytes, "test"), Ok((value, bytes.len())));
+            }
+        }
+        assert_eq!(super::varint_len((1 << 56) - 1), 8);
+        assert_eq!(super::varint_len(1 << 56), 9);
+        for value in [i64::MIN, -1, i64::MAX] {
+            let bytes = super::write_varint(value as u64);
+            assert_eq!(
+                super::read_varint(&bytes, "test"),
+                Ok((value as u64, bytes.len()))
+            );
+        }
+    }
 }